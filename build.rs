@@ -1,5 +1,23 @@
-use std::io::Result;
+use std::{env, io::Result, path::PathBuf};
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["src/mapData.proto"], &["src/"])?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    let mut config = prost_build::Config::new();
+    config
+        // shipped next to caches, for tools that don't have the .proto files
+        .file_descriptor_set_path(out_dir.join("mapData.desc"))
+        // lets the non-protobuf exporters reuse the generated types
+        // sorted keys, so the same maps always encode to the same bytes
+        .btree_map(["."])
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
+
+    // the gRPC service lives in the same package, so it ends up in the same generated file
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos_with_config(
+            config,
+            &["src/mapData.proto", "src/mapService.proto"],
+            &["src/"],
+        )?;
     Ok(())
 }
\ No newline at end of file
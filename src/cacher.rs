@@ -1,29 +1,96 @@
+pub mod accsaber;
+pub mod api;
+pub mod beatleader;
+pub mod bsaber;
+pub mod checksum;
+pub mod chunks;
+pub mod covers;
+pub mod delta;
+pub mod descriptor;
+pub mod dump;
+pub mod envelope;
+pub mod error;
+pub mod exclusion;
+pub mod export;
+pub mod expr;
+pub mod fields;
+pub mod filter;
+pub mod fixture;
+pub mod hash_index;
+pub mod history;
+pub mod hitbloq;
+pub mod integrity;
+pub mod intern;
+pub mod journal;
+pub mod local;
+pub mod manifest;
+pub mod metrics;
+pub mod migrate;
+pub mod mirror;
+pub mod mods;
+pub mod playlist;
+pub mod previews;
+pub mod progress;
 pub mod protogen;
+pub mod quarantine;
+pub mod query;
+pub mod ratelimit;
+pub mod refresh;
+pub mod repair;
+pub mod scoresaber;
+pub mod search;
+pub mod shard;
+pub mod source;
+pub mod spill;
+pub mod store;
+pub mod summary;
+pub mod update;
+pub mod upload;
+pub mod usage;
+pub mod webhook;
 
 use std::{
-    collections::HashMap,
-    fs::{self},
-    time::Duration,
+    borrow::Borrow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, SeekFrom},
 };
 
-use beatsaver_api::{
-    builders::BeatSaverMapSearchBuilder,
-    client::{BeatSaverClient, ClientError},
-    models::{
-        enums::{AIDeclarationType, MapState},
-        map::{Map, MapDetail, MapVersion},
-    },
+use beatsaver_api::models::{
+    enums::MapState,
+    map::{Map, MapVersion},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use flate2::{Compression, GzBuilder, read::GzDecoder};
+use futures::future::try_join_all;
+use prost::{
+    Message,
+    encoding::{WireType, encode_key, encode_varint, fixed32, message, string, uint32},
 };
-use flate2::{Compression, write::GzEncoder};
-use log::{debug, error, info};
-use prost::Message;
+use serde::Serialize;
+use serde_json::Value;
 use std::io::prelude::*;
-use tokio::time::sleep;
-
-use crate::cacher::protogen::{
-    generate_protobuf_curator, generate_protobuf_diffs, generate_protobuf_map_mods,
-    generate_protobuf_votes,
+use tokio::{sync::mpsc, time::sleep};
+use tracing::{Span, debug, error, field, info, instrument, warn};
+
+use crate::cacher::{
+    api::{ApiClient, ApiError, LatestPage, SearchQuery},
+    error::CacherError,
+    fields::select_fields,
+    filter::{FilterPipeline, SkipReason},
+    intern::{StringTable, resolve_strings},
+    metrics::{record_cached, record_skipped},
+    progress::ScrapeProgress,
+    protogen::{
+        add_requirements, generate_protobuf_characteristics, generate_protobuf_collaborators,
+        generate_protobuf_curator, generate_protobuf_diffs, generate_protobuf_map_mods,
+        generate_protobuf_versions, generate_protobuf_votes,
+    },
+    quarantine::quarantine_map,
+    ratelimit::Backoff,
+    source::{ListingSource, MapSource, SourcePage, WindowSource},
 };
+use crate::config::FieldConfig;
 use crate::mapdata::{MapList, MapMetadata};
 
 #[derive(Default)]
@@ -35,31 +102,38 @@ struct MapMods {
     pub vivify: bool,
 }
 
-fn should_cache_map(map: &Map) -> bool {
-    // not published yet
-    if map.last_published_at.is_none() {
-        info!("{} hasn't been published before, ignoring", map.id);
-        return false;
-    }
+/// Somewhere `init_cache` can put maps as it scrapes them.
+pub trait MapStore {
+    /// Adds a map, replacing any map already stored under the same key. Returns whether the key
+    /// is new.
+    fn insert(&mut self, key: String, map: MapMetadata) -> bool;
 
-    // version of map hasn't been published
-    if map.versions[0].state != MapState::Published {
-        info!("Version of {} is not published, ignoring", map.id);
-        return false;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
+}
 
-    // AI-generated (map or song)
-    if map.declared_ai != AIDeclarationType::None {
-        info!("{} has been declared as AI-generated, ignoring", map.id);
-        return false;
+impl MapStore for MapList {
+    fn insert(&mut self, key: String, map: MapMetadata) -> bool {
+        self.map_metadata.insert(key, map).is_none()
     }
 
-    if map.automapper {
-        info!("{} is automapped, ignoring", map.id);
-        return false;
+    fn len(&self) -> usize {
+        self.map_metadata.len()
     }
+}
 
-    true
+/// The newest published version of a map. BeatSaver lists the newest version first, but that one
+/// might still be in testplay, so it's only what we fall back to when nothing is published. `None`
+/// if the map has no versions at all.
+pub fn current_version(map: &Map) -> Option<&MapVersion> {
+    map.versions
+        .iter()
+        .filter(|version| version.state == MapState::Published)
+        .max_by_key(|version| version.created_at)
+        .or(map.versions.first())
 }
 
 fn get_map_mods(map_version: &MapVersion) -> MapMods {
@@ -92,121 +166,727 @@ fn get_map_mods(map_version: &MapVersion) -> MapMods {
     mods
 }
 
-pub fn cache_map_data(map: &Map) -> Option<MapMetadata> {
-    if !should_cache_map(map) {
+#[instrument(level = "trace", skip_all, fields(map_id = %map.id, outcome = field::Empty))]
+pub fn cache_map_data(
+    map: &Map,
+    raw: &Value,
+    profile: &Profile,
+) -> Result<MapMetadata, SkipReason> {
+    let filters = &profile.filters;
+    let span = Span::current();
+
+    if let Some(reason) = filters.check(map) {
         debug!("Not caching {:?}", map.id);
-        return None;
+        span.record("outcome", reason.as_str());
+        record_skipped(reason);
+        return Err(reason);
+    }
+
+    // unpublished maps only get this far with published_only off, their last update will do
+    let published_at = map.last_published_at.or(map.updated_at);
+
+    let (Some(published_at), Some(updated_at)) = (published_at, map.updated_at) else {
+        span.record("outcome", SkipReason::Incomplete.as_str());
+        record_skipped(SkipReason::Incomplete);
+        return Err(SkipReason::Incomplete);
+    };
+
+    // keys are hex, and DumbRequestManager wants them as a number
+    let Ok(key) = u32::from_str_radix(&map.id, 16) else {
+        warn!("{:?} isn't a key we can store, skipping", map.id);
+        span.record("outcome", SkipReason::BadKey.as_str());
+        record_skipped(SkipReason::BadKey);
+        return Err(SkipReason::BadKey);
+    };
+
+    let Some(version) = current_version(map) else {
+        warn!("{} has no versions, skipping", map.id);
+        span.record("outcome", SkipReason::Incomplete.as_str());
+        record_skipped(SkipReason::Incomplete);
+        return Err(SkipReason::Incomplete);
+    };
+    let mut difficulties = generate_protobuf_diffs(version);
+    let mut mods = generate_protobuf_map_mods(version);
+
+    if filters.trims_difficulties() {
+        difficulties.retain(|diff| {
+            filters.keeps_difficulty(&diff.characteristic_name, &diff.difficulty_name)
+        });
+
+        // only the mods of what's left count
+        mods = difficulties.iter().fold(0, |mods, diff| mods | diff.mods);
     }
 
     // now we make the map data
-    let cached_map = MapMetadata {
-        key: u32::from_str_radix(&map.id, 16).unwrap(),
-        hash: map.versions[0].hash.clone(),
+    let mut cached_map = MapMetadata {
+        key,
+        hash: version.hash.clone(),
         song_name: map.metadata.song_name.clone(),
         song_sub_name: map.metadata.song_sub_name.clone(),
         song_author_name: map.metadata.song_author_name.clone(),
         level_author_name: map.metadata.level_author_name.clone(),
-        duration: u32::try_from(map.metadata.duration).ok().unwrap(),
-        uploaded: u32::try_from(map.last_published_at?.timestamp())
-            .ok()
-            .unwrap(),
-        last_updated: u32::try_from(map.updated_at?.timestamp()).ok().unwrap(),
-        mods: generate_protobuf_map_mods(&map.versions[0]),
+        duration: u32::try_from(map.metadata.duration).unwrap_or(0),
+        uploaded: published_at.timestamp(),
+        last_updated: updated_at.timestamp(),
+        created: map.created_at.map(|created_at| created_at.timestamp()),
+        mods,
         curator_name: generate_protobuf_curator(map),
         votes: generate_protobuf_votes(map.stats.upvotes, map.stats.downvotes),
-        difficulties: generate_protobuf_diffs(&map.versions[0]),
+        plays: u32::try_from(map.stats.plays).ok(),
+        downloads: u32::try_from(map.stats.downloads).ok(),
+        score: Some(map.stats.score as f32),
+        characteristics: generate_protobuf_characteristics(&difficulties),
+        difficulties,
+        recently_played: None,
+        tags: map.tags.clone(),
+        bpm: Some(map.metadata.bpm as f32),
+        uploader_id: u32::try_from(map.uploader.id).ok(),
+        uploader_name: Some(map.uploader.name.clone()),
+        collaborators: generate_protobuf_collaborators(map),
+        required_mods: None,
+        suggested_mods: None,
+        cover_url: Some(version.cover_url.clone()),
+        preview_url: Some(version.preview_url.clone()),
+        download_url: Some(version.download_url.clone()),
+        versions: generate_protobuf_versions(map),
+        curated_on_bsaber: None,
+        bsaber_review: None,
+        other_requirements: Vec::new(),
     };
 
-    Some(cached_map)
+    // the models only know which mods a difficulty uses, not whether it needs them, and only
+    // the mods with a bit
+    add_requirements(&mut cached_map, raw);
+    select_fields(&mut cached_map, &profile.fields);
+
+    span.record("outcome", "cached");
+    Ok(cached_map)
 }
 
-pub async fn init_cache(client: &BeatSaverClient) -> MapList {
-    let mut caching = true;
-    let mut current_time = chrono::Utc::now();
-    let mut last_map: Option<MapDetail> = None;
+/// The newest upload time in the list, in unix seconds.
+pub fn newest_upload(map_list: &MapList) -> Option<i64> {
+    map_list.map_metadata.values().map(|map| map.uploaded).max()
+}
 
-    let mut map_list: MapList = MapList {
-        map_metadata: HashMap::new(),
-    };
+/// Roughly when the first maps went up on BeatSaver. Only used to split a full scrape into
+/// windows, the oldest window is left open-ended anyway.
+const BEATSAVER_EPOCH: i64 = 1525132800;
 
-    while caching {
-        let params = BeatSaverMapSearchBuilder::new()
-            .before(current_time)
-            .page_size(100)
-            .automapper(false)
-            .build();
+/// A slice of time scraped by one worker, newest maps first.
+struct ScrapeWindow {
+    after: Option<DateTime<Utc>>,
+    before: DateTime<Utc>,
+}
 
-        let res = client.latest(&params).await;
+/// Splits (after, before] into `count` equally long windows, newest first.
+fn split_windows(after: Option<i64>, before: DateTime<Utc>, count: usize) -> Vec<ScrapeWindow> {
+    let start = after.unwrap_or(BEATSAVER_EPOCH);
+    let span = (before.timestamp() - start).max(0) / count as i64;
 
-        match res {
-            Ok(data) => {
-                debug!("Obtained {} maps", data.docs.len());
+    (0..count)
+        .map(|i| {
+            let window_before = before.timestamp() - span * i as i64;
+            let window_after = before.timestamp() - span * (i as i64 + 1);
 
-                if data.docs.is_empty() {
-                    info!("[Scraper] No maps left!");
-                    caching = false;
+            ScrapeWindow {
+                // the oldest window keeps whatever lower bound we were given, which may be none
+                after: if i + 1 == count {
+                    after.and_then(|after| DateTime::from_timestamp(after, 0))
                 } else {
-                    for map_data in data.docs {
-                        let map_key = map_data.id.clone();
+                    DateTime::from_timestamp(window_after, 0)
+                },
+                before: DateTime::from_timestamp(window_before, 0).unwrap_or(before),
+            }
+        })
+        .collect()
+}
 
-                        if let Some(cached_map) = cache_map_data(&map_data) {
-                            map_list.map_metadata.insert(map_key.clone(), cached_map);
-                            last_map = Some(map_data);
-                        }
-                    }
+/// How a scrape ended, when it didn't fail outright.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScrapeOutcome {
+    Finished,
+    /// Someone hit Ctrl-C. Whatever was scraped so far is in the store.
+    Interrupted,
+}
+
+/// One cache to build from a scrape: which maps go in it, and with which fields.
+#[derive(Default)]
+pub struct Profile {
+    pub filters: FilterPipeline,
+    pub fields: FieldConfig,
+}
+
+/// Knobs for `init_cache`.
+pub struct ScrapeOptions {
+    /// Only fetch maps uploaded after this (unix seconds).
+    pub stop_at: Option<i64>,
+    /// Only fetch maps uploaded before this (unix seconds). Defaults to now.
+    pub start_at: Option<i64>,
+    /// How many windows to fetch at the same time.
+    pub concurrency: usize,
+    /// How many times a single page can fail before the scrape gives up.
+    pub max_retries: u32,
+    /// What to build, one store each. Every profile comes out of the same pass over the API.
+    pub profiles: Vec<Profile>,
+    /// Where to dump maps that couldn't be converted, if anywhere.
+    pub quarantine_dir: Option<String>,
+    /// Only scrape the maps in this listing, instead of everything.
+    pub listing: Option<Listing>,
+}
+
+/// A subset of BeatSaver that's paged through by number instead of by upload time.
+#[derive(Debug, Clone)]
+pub enum Listing {
+    /// Everything one mapper uploaded, by user ID. The time range doesn't apply.
+    Uploader(u32),
+    /// What a search turns up.
+    Search(SearchQuery),
+}
 
-                    info!("[Scraper] Cached {} maps", map_list.map_metadata.len(),);
+impl Default for ScrapeOptions {
+    fn default() -> Self {
+        ScrapeOptions {
+            stop_at: None,
+            start_at: None,
+            concurrency: 1,
+            max_retries: 8,
+            profiles: vec![Profile::default()],
+            quarantine_dir: None,
+            listing: None,
+        }
+    }
+}
+
+/// What happened during a scrape, for the run summary.
+#[derive(Debug, Default, Serialize)]
+pub struct ScrapeStats {
+    pub new_maps: u64,
+    /// Maps that were already in the store and got replaced.
+    pub updated_maps: u64,
+    /// Maps taken out of the store, because BeatSaver doesn't have them or they don't pass the
+    /// filters anymore. Only refreshes do this.
+    pub removed_maps: u64,
+    /// Maps left out, by `SkipReason`.
+    pub skipped: BTreeMap<&'static str, u64>,
+    /// Failed requests to BeatSaver, by `ApiError::kind`.
+    pub api_errors: BTreeMap<&'static str, u64>,
+    /// Broken maps written to the quarantine directory. They're in `skipped` too.
+    pub quarantined: u64,
+}
+
+impl ScrapeStats {
+    fn merge(&mut self, other: ScrapeStats) {
+        self.new_maps += other.new_maps;
+        self.updated_maps += other.updated_maps;
+        self.removed_maps += other.removed_maps;
+        self.quarantined += other.quarantined;
+
+        for (reason, count) in other.skipped {
+            *self.skipped.entry(reason).or_insert(0) += count;
+        }
+
+        for (kind, count) in other.api_errors {
+            *self.api_errors.entry(kind).or_insert(0) += count;
+        }
+    }
+}
+
+/// One page worth of maps, on its way from a window to the stores.
+struct ScrapedPage {
+    /// The maps each profile kept, in profile order.
+    maps: Vec<Vec<(String, MapMetadata)>>,
+    /// Skips and errors since the window's previous page.
+    stats: ScrapeStats,
+    /// How many maps BeatSaver sent, cacheable or not, leaving out ones from the previous page.
+    fetched: usize,
+    /// Where the window's cursor ended up after this page.
+    cursor: DateTime<Utc>,
+}
 
-                    if let Some(ref map) = last_map {
-                        debug!("Currently at {}", map.id);
-                        current_time = map.uploaded;
+/// How far back over the previous page each request reaches, so maps uploaded in the same second
+/// as the last one on a page aren't lost between pages. Whatever comes back twice is dropped.
+const PAGE_OVERLAP: TimeDelta = TimeDelta::seconds(1);
+
+/// Maps per page, the most `/maps/latest` hands out.
+const PAGE_SIZE: u32 = 100;
+
+/// How many pages in a row can fail to move the cursor before the window is given up on.
+const MAX_STALLS: u32 = 4;
+
+/// Writes a broken map to the quarantine directory, if there is one. Not being able to is logged
+/// and otherwise ignored, it's no reason to stop the scrape.
+fn quarantine(dir: Option<&str>, name: &str, reason: &str, raw: &Value, stats: &mut ScrapeStats) {
+    let Some(dir) = dir else {
+        return;
+    };
 
-                        debug!("current_time set to {}", current_time);
+    match quarantine_map(dir, name, reason, raw) {
+        Ok(()) => stats.quarantined += 1,
+        Err(e) => error!("Couldn't quarantine {}: {}", name, e),
+    }
+}
+
+/// Runs every map on the page through every profile, quarantining the broken ones. `label` names
+/// quarantined maps that don't even have an ID. Hands back what each profile kept, in profile
+/// order.
+fn convert_page(
+    data: &LatestPage,
+    options: &ScrapeOptions,
+    stats: &mut ScrapeStats,
+    label: &str,
+) -> Vec<Vec<(String, MapMetadata)>> {
+    let quarantine_dir = options.quarantine_dir.as_deref();
+    let mut page: Vec<Vec<_>> = options.profiles.iter().map(|_| Vec::new()).collect();
+
+    for (index, map_data) in data.docs.iter().enumerate() {
+        let raw = data.raw.get(index).unwrap_or(&Value::Null);
+        let mut malformed = None;
+
+        for (profile, maps) in options.profiles.iter().zip(&mut page) {
+            match cache_map_data(map_data, raw, profile) {
+                Ok(cached_map) => {
+                    maps.push((map_data.id.clone(), cached_map));
+                }
+                Err(reason) => {
+                    *stats.skipped.entry(reason.as_str()).or_insert(0) += 1;
+
+                    if reason.is_malformed() {
+                        malformed = Some(reason);
                     }
+                }
+            }
+        }
+
+        // once per map, however many profiles tripped over it
+        if let Some(reason) = malformed {
+            quarantine(quarantine_dir, &map_data.id, reason.as_str(), raw, stats);
+        }
+    }
+
+    for (index, rejected) in data.rejected.iter().enumerate() {
+        let reason = SkipReason::Unparseable;
+        *stats.skipped.entry(reason.as_str()).or_insert(0) += 1;
+        record_skipped(reason);
+
+        let name = match rejected.id() {
+            Some(id) => id.to_string(),
+            None => format!("{}-{}", label, index),
+        };
+        let reason = format!("{}: {}", reason.as_str(), rejected.error);
+
+        quarantine(quarantine_dir, &name, &reason, &rejected.raw, stats);
+    }
+
+    page
+}
 
-                    sleep(Duration::from_millis(100)).await;
+/// Logs a failed request and waits before the next try. Hands the error back once there are no
+/// retries left.
+async fn wait_to_retry(err: ApiError, backoff: &mut Backoff) -> Result<(), ApiError> {
+    match &err {
+        ApiError::Http(reqwest_err) => {
+            error!(
+                "Status not 200 (is {:?}), waiting a bit",
+                reqwest_err.status()
+            );
+            error!("{:?}", reqwest_err);
+        }
+        ApiError::Decode(serde_err) => {
+            error!("ERROR: {}", serde_err);
+        }
+        ApiError::RateLimited(retry_after) => {
+            // the limiter already waits as long as BeatSaver asked, and being told to slow down
+            // isn't a failure, so it doesn't use up a retry
+            warn!("Rate limited, BeatSaver wants us to wait {:?}", retry_after);
+            return Ok(());
+        }
+    }
+
+    let Some(delay) = backoff.next_delay() else {
+        return Err(err);
+    };
+
+    debug!("Retrying in {:?}", delay);
+    sleep(delay).await;
+
+    Ok(())
+}
+
+/// Runs every page `source` gives through the profiles, sending what they kept down `pages`.
+async fn scrape_source(
+    source: &mut dyn MapSource,
+    pages: mpsc::Sender<ScrapedPage>,
+    options: &ScrapeOptions,
+) -> Result<(), CacherError> {
+    while let Some(SourcePage {
+        page: data,
+        label,
+        cursor,
+        api_errors,
+    }) = source.next_page().await?
+    {
+        let mut stats = ScrapeStats {
+            api_errors,
+            ..Default::default()
+        };
+        let maps = convert_page(&data, options, &mut stats, &label);
+
+        let page = ScrapedPage {
+            maps,
+            stats,
+            fetched: data.len(),
+            cursor,
+        };
+
+        if pages.send(page).await.is_err() {
+            // nobody's listening anymore
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// Hands the pages coming out of `produce` to the stores, in receiver order, until it's done or
+/// someone hits Ctrl-C.
+async fn drain_pages<S: MapStore>(
+    produce: impl Future<Output = Result<(), CacherError>>,
+    receivers: Vec<mpsc::Receiver<ScrapedPage>>,
+    stores: &mut [S],
+    stats: &mut ScrapeStats,
+    mut progress: ScrapeProgress,
+) -> Result<ScrapeOutcome, CacherError> {
+    let consume = async {
+        for mut receiver in receivers {
+            while let Some(page) = receiver.recv().await {
+                record_cached(page.maps.iter().map(Vec::len).sum());
+                stats.merge(page.stats);
+
+                for (store, maps) in stores.iter_mut().zip(page.maps) {
+                    for (map_key, cached_map) in maps {
+                        if store.insert(map_key, cached_map) {
+                            stats.new_maps += 1;
+                        } else {
+                            stats.updated_maps += 1;
+                        }
+                    }
                 }
+
+                let cached = largest_store(stores);
+                progress.page_done(page.fetched, cached, page.cursor);
+                debug!("[Scraper] Cached {} maps", cached);
             }
-            Err(err) => match err {
-                ClientError::ReqwestError(reqwest_err) => {
-                    error!(
-                        "Status not 200 (is {:?}), waiting a bit",
-                        reqwest_err.status()
-                    );
-                    error!("{:?}", reqwest_err);
-                    sleep(Duration::from_millis(3000)).await;
-                    continue;
+        }
+    };
+
+    let scrape = async {
+        let (produced, _) = tokio::join!(produce, consume);
+        produced.map(|_| ScrapeOutcome::Finished)
+    };
+
+    // dropping the scrape stops every source at its next await, the store keeps what it has
+    let outcome = tokio::select! {
+        outcome = scrape => outcome,
+        _ = tokio::signal::ctrl_c() => {
+            warn!("[Scraper] Interrupted, stopping where we are");
+            Ok(ScrapeOutcome::Interrupted)
+        }
+    };
+    progress.finish();
+    info!("[Scraper] Cached {} maps", largest_store(stores));
+
+    outcome
+}
+
+/// Scrapes BeatSaver from the newest map (or `start_at`) backwards into `stores`, one per profile.
+/// If `stop_at` is set, only maps uploaded after then are fetched.
+///
+/// With `concurrency` above 1, the time range is split into that many windows which are fetched
+/// at the same time. Pages are still handed to the stores in order, newest window first.
+///
+/// Fails if any page runs out of retries, and stops early on Ctrl-C. Either way, whatever was
+/// scraped before that stays in the stores, and what happened to it is in `stats`.
+pub async fn init_cache<S: MapStore>(
+    client: &ApiClient,
+    stores: &mut [S],
+    options: &ScrapeOptions,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeOutcome, CacherError> {
+    // listings are paged by number, not split by time, so there's nothing to fetch side by side
+    let concurrency = match options.listing {
+        Some(_) => 1,
+        None => options.concurrency.max(1),
+    };
+
+    let after = options
+        .stop_at
+        .and_then(|stop_at| DateTime::from_timestamp(stop_at, 0));
+    let before = options
+        .start_at
+        .and_then(|start_at| DateTime::from_timestamp(start_at, 0))
+        .unwrap_or_else(Utc::now);
+    let search = match &options.listing {
+        Some(Listing::Search(search)) => Some(search),
+        _ => None,
+    };
+    let total = if matches!(options.listing, Some(Listing::Uploader(_))) {
+        // the count comes from a search, which can't be narrowed down to one mapper
+        None
+    } else {
+        match client.total_maps(after, before, search).await {
+            Ok(total) => Some(total),
+            Err(e) => {
+                warn!(
+                    "[Scraper] Couldn't get a map count, no ETA this time: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    };
+    let progress = ScrapeProgress::new(total);
+
+    let windows = split_windows(options.stop_at, before, concurrency);
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        windows.iter().map(|_| mpsc::channel(concurrency)).unzip();
+
+    let produce = try_join_all(windows.into_iter().zip(senders).map(
+        |(window, sender)| async move {
+            let max_retries = options.max_retries;
+
+            match &options.listing {
+                Some(listing) => {
+                    let mut source = ListingSource::new(client, listing, window, max_retries);
+                    scrape_source(&mut source, sender, options).await
                 }
-                ClientError::SerdeError(serde_err) => {
-                    error!("ERROR: {}", serde_err);
+                None => {
+                    let mut source = WindowSource::new(client, window, max_retries);
+                    scrape_source(&mut source, sender, options).await
                 }
-                _ => unreachable!(""),
-            },
-        }
+            }
+        },
+    ));
+    let produce = async { produce.await.map(|_| ()) };
+
+    let outcome = drain_pages(produce, receivers, stores, stats, progress).await;
+
+    for (path, count) in client.drift_report() {
+        warn!(
+            "[Drift] {} showed up {} times but isn't in the models, data might be missing",
+            path, count
+        );
     }
 
+    outcome
+}
+
+/// Like `init_cache`, but from somewhere other than BeatSaver, like maps saved from it earlier.
+/// The time range and listing in `options` don't apply, whatever `source` has is used.
+pub async fn scrape_saved<S: MapStore>(
+    source: &mut dyn MapSource,
+    stores: &mut [S],
+    options: &ScrapeOptions,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeOutcome, CacherError> {
+    let (sender, receiver) = mpsc::channel(1);
+    let produce = scrape_source(source, sender, options);
+
+    drain_pages(
+        produce,
+        vec![receiver],
+        stores,
+        stats,
+        ScrapeProgress::new(None),
+    )
+    .await
+}
+
+/// How many maps the fullest store has.
+fn largest_store<S: MapStore>(stores: &[S]) -> usize {
+    stores.iter().map(MapStore::len).max().unwrap_or(0)
+}
+
+/// The newest `last_updated` in the list. Used instead of the wall clock so that identical data
+/// gives an identical file.
+fn snapshot_timestamp(map_list: &MapList) -> i64 {
     map_list
+        .map_metadata
+        .values()
+        .map(|map| map.last_updated)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Encodes map entries (in key order) one at a time, each followed by its checksum, then the
+/// schema version. With `intern_names` each map also comes after any names it needs in the string
+/// table. Only one map is ever held in memory. `encode_to_vec()` would give the same `MapList`,
+/// but with the table and the checksums after all the maps. Hands back how many bytes that was.
+fn encode_entries_streaming<W, K, M>(
+    entries: impl Iterator<Item = std::io::Result<(K, M)>>,
+    intern_names: bool,
+    writer: &mut W,
+) -> std::io::Result<u64>
+where
+    W: Write,
+    K: Borrow<String>,
+    M: Borrow<MapMetadata>,
+{
+    let mut buf = Vec::new();
+    // clients that don't know about the table would read every name as empty, so it's opt-in
+    let mut table = intern_names.then(StringTable::default);
+    let mut written = 0;
+
+    // a protobuf map is just a repeated { key = 1; value = 2; } message under the map's field number
+    for entry in entries {
+        let (key, map) = entry?;
+        let (key, map) = (key.borrow(), map.borrow());
+
+        buf.clear();
+
+        let interned;
+        let map = match &mut table {
+            Some(table) => {
+                interned = table.intern_map(map, &mut buf);
+                &interned
+            }
+            None => map,
+        };
+        let entry_len = string::encoded_len(1, key) + message::encoded_len(2, map);
+
+        encode_key(1, WireType::LengthDelimited, &mut buf);
+        encode_varint(entry_len as u64, &mut buf);
+        let entry_start = buf.len();
+        string::encode(1, key, &mut buf);
+        message::encode(2, map, &mut buf);
+
+        // right after the entry, so `repair` can tell which maps are still good
+        let checksum = crc32fast::hash(&buf[entry_start..]);
+        fixed32::encode(3, &checksum, &mut buf);
+
+        writer.write_all(&buf)?;
+        written += buf.len() as u64;
+    }
+
+    buf.clear();
+    uint32::encode(2, &u32::from(envelope::FORMAT_VERSION), &mut buf);
+    writer.write_all(&buf)?;
+    written += buf.len() as u64;
+
+    Ok(written)
+}
+
+/// Streams the cache into a temporary file next to `path`, then moves it into place. Hands back
+/// how big the payload was before it was compressed.
+pub(crate) fn write_cache_file<K, M>(
+    entries: impl Iterator<Item = std::io::Result<(K, M)>>,
+    map_count: usize,
+    created: i64,
+    intern_names: bool,
+    path: &str,
+) -> std::io::Result<u64>
+where
+    K: Borrow<String>,
+    M: Borrow<MapMetadata>,
+{
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+
+    // placeholder, the real header needs the payload's checksum and length
+    file.write_all(&[0; envelope::HEADER_LEN])?;
+
+    let mut payload = envelope::PayloadWriter::new(file);
+
+    // no mtime/filename in the gzip header, otherwise every run differs
+    let mut gz = GzBuilder::new()
+        .mtime(0)
+        .write(&mut payload, Compression::default());
+    let uncompressed_len = encode_entries_streaming(entries, intern_names, &mut gz)?;
+    gz.finish()?;
+
+    let (mut file, checksum, payload_len) = payload.finish();
+    let header = envelope::encode_header(map_count, created, checksum, payload_len);
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.flush()?;
+    drop(file);
+
+    usage::record_written(envelope::HEADER_LEN as u64 + payload_len);
+
+    fs::rename(&tmp_path, path)?;
+
+    Ok(uncompressed_len)
+}
+
+/// How big `write_cache` would make the file, by compressing it and throwing the result away.
+pub fn estimate_cache_size(map_list: &MapList, intern_names: bool) -> std::io::Result<u64> {
+    let mut payload = envelope::PayloadWriter::new(std::io::sink());
+
+    let mut gz = GzBuilder::new()
+        .mtime(0)
+        .write(&mut payload, Compression::default());
+    encode_entries_streaming(map_list.map_metadata.iter().map(Ok), intern_names, &mut gz)?;
+    gz.finish()?;
+
+    let (_, _, payload_len) = payload.finish();
+
+    Ok(envelope::HEADER_LEN as u64 + payload_len)
 }
 
-// [TODO] better return type
 // [TODO] validation on this
-pub async fn write_cache(map_list: &MapList, path: &str) -> bool {
-    let buf = Vec::new();
+/// With `intern_names` the names go in the string table, which only readers that know format
+/// version 5 can resolve. Hands back how big the payload was before it was compressed, string
+/// table and checksums included.
+pub async fn write_cache(
+    map_list: &MapList,
+    path: &str,
+    intern_names: bool,
+) -> Result<u64, CacherError> {
+    let entries = map_list.map_metadata.iter().map(Ok);
+
+    let uncompressed_len = write_cache_file(
+        entries,
+        map_list.map_metadata.len(),
+        snapshot_timestamp(map_list),
+        intern_names,
+        path,
+    )?;
+    info!("Saved to {}", path);
+
+    Ok(uncompressed_len)
+}
 
-    let mut gz = GzEncoder::new(buf, Compression::default());
-    let _ = gz.write_all(&map_list.encode_to_vec());
+/// Reads a cache written by `write_cache`, checking the envelope before decoding anything.
+pub fn read_cache(path: &str) -> Result<MapList, CacherError> {
+    decode_cache(&fs::read(path)?)
+}
 
-    let compressed = gz.finish().unwrap();
+/// Same as `read_cache`, for a cache that's already in memory.
+pub fn decode_cache(data: &[u8]) -> Result<MapList, CacherError> {
+    if envelope::is_legacy(data) {
+        return migrate::decode_legacy_cache(data);
+    }
 
-    match fs::write(path, compressed) {
-        Ok(_) => {
-            info!("Saved to {}", path);
-        }
-        Err(e) => {
-            error!("{:?}", e);
-            return false;
-        }
+    let (header, payload) =
+        envelope::unwrap(data).map_err(|e| CacherError::Envelope(e.to_string()))?;
+
+    let mut decoded = Vec::new();
+    GzDecoder::new(payload).read_to_end(&mut decoded)?;
+
+    // version 1 caches have uint32 timestamps, which are varints just like int64, so they decode
+    // the same way
+    let mut map_list = MapList::decode(decoded.as_slice())?;
+    resolve_strings(&mut map_list)?;
+
+    if map_list.map_metadata.len() != header.map_count as usize {
+        return Err(CacherError::Envelope(format!(
+            "cache has {} maps, header says {}",
+            map_list.map_metadata.len(),
+            header.map_count
+        )));
     }
 
-    true
+    migrate::upgrade(&mut map_list, header.format_version);
+
+    Ok(map_list)
 }
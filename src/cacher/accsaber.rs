@@ -0,0 +1,77 @@
+// AccSaber's ranked categories, e.g. "True Acc" or "Tech Acc"
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{config::HttpConfig, mapdata::MapList};
+
+pub const ACCSABER_API_URL: &str = "https://api.accsaber.com";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RankedMap {
+    song_hash: String,
+    /// Lowercase, e.g. "expertplus".
+    difficulty: String,
+    category_display_name: String,
+}
+
+/// Every ranked difficulty's categories, by (lowercase hash, lowercase difficulty). AccSaber only
+/// ranks Standard, so there's no characteristic.
+pub struct AccSaberCategories {
+    categories: HashMap<(String, String), Vec<String>>,
+}
+
+/// Fetches AccSaber's ranked maps, which all come back in one go.
+pub async fn fetch_categories(http: &HttpConfig) -> anyhow::Result<AccSaberCategories> {
+    let maps: Vec<RankedMap> = http
+        .client()?
+        .get(format!("{}/ranked-maps", ACCSABER_API_URL))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    info!("[AccSaber] Fetched {} ranked difficulties", maps.len());
+
+    let mut categories: HashMap<_, Vec<String>> = HashMap::new();
+
+    for map in maps {
+        let key = (map.song_hash.to_lowercase(), map.difficulty.to_lowercase());
+        categories
+            .entry(key)
+            .or_default()
+            .push(map.category_display_name);
+    }
+
+    Ok(AccSaberCategories { categories })
+}
+
+/// Sets every difficulty's AccSaber categories. Returns how many difficulties are in at least one.
+pub fn apply_categories(map_list: &mut MapList, categories: &AccSaberCategories) -> usize {
+    let mut ranked = 0;
+
+    for map in map_list.map_metadata.values_mut() {
+        let hash = map.hash.to_lowercase();
+
+        for diff in &mut map.difficulties {
+            diff.accsaber_categories.clear();
+
+            if diff.characteristic_name != "Standard" {
+                continue;
+            }
+
+            let key = (hash.clone(), diff.difficulty_name.to_lowercase());
+
+            if let Some(found) = categories.categories.get(&key) {
+                diff.accsaber_categories = found.clone();
+                ranked += 1;
+            }
+        }
+    }
+
+    ranked
+}
@@ -0,0 +1,529 @@
+// talking to BeatSaver ourselves, so we can see the raw responses and not just the parsed ones
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use beatsaver_api::models::map::MapDetail;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{
+    cacher::{
+        ratelimit::{RateLimiter, parse_retry_after},
+        usage::record_downloaded,
+    },
+    config::HttpConfig,
+};
+
+pub const BEATSAVER_API_URL: &str = "https://api.beatsaver.com";
+
+/// The part of a `/search/text` response we care about, for counting maps up front.
+#[derive(Deserialize)]
+struct SearchPage {
+    info: SearchInfo,
+}
+
+#[derive(Deserialize)]
+struct SearchInfo {
+    total: u64,
+}
+
+/// What to search BeatSaver for.
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    /// Free text, matched against names, authors and descriptions.
+    pub text: String,
+    /// Tags every map has to have.
+    pub tags: Vec<String>,
+}
+
+impl SearchQuery {
+    fn push_params(&self, query: &mut Vec<(&'static str, String)>) {
+        if !self.text.is_empty() {
+            query.push(("q", self.text.clone()));
+        }
+
+        if !self.tags.is_empty() {
+            query.push(("tags", self.tags.join(",")));
+        }
+    }
+}
+
+/// How long to back off on a 429 that doesn't say how long to wait.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// One page of `/maps/latest`, or of any other endpoint that lists maps the same way.
+#[derive(Deserialize)]
+pub struct LatestPage {
+    pub docs: Vec<MapDetail>,
+    /// The same maps as BeatSaver sent them, in the same order, for quarantining the ones that
+    /// don't convert. Empty if the page couldn't be read as plain JSON for some reason.
+    #[serde(skip)]
+    pub raw: Vec<serde_json::Value>,
+    /// Maps on the page the models couldn't read. Only ever filled in when the page as a whole
+    /// didn't parse and had to be salvaged.
+    #[serde(skip)]
+    pub rejected: Vec<RejectedMap>,
+}
+
+impl LatestPage {
+    /// Reads maps one at a time, so the ones that don't fit the models end up in `rejected`
+    /// instead of failing the lot.
+    pub fn from_docs(docs: Vec<serde_json::Value>) -> Self {
+        let mut page = LatestPage {
+            docs: Vec::new(),
+            raw: Vec::new(),
+            rejected: Vec::new(),
+        };
+
+        for doc in docs {
+            match MapDetail::deserialize(&doc) {
+                Ok(map) => {
+                    page.docs.push(map);
+                    page.raw.push(doc);
+                }
+                Err(error) => page.rejected.push(RejectedMap { raw: doc, error }),
+            }
+        }
+
+        page
+    }
+
+    /// Drops the maps in `seen`, keeping `raw` lined up with `docs`.
+    pub fn drop_seen(&mut self, seen: &HashSet<String>) {
+        if self.raw.len() == self.docs.len() {
+            let mut docs = self.docs.iter();
+            self.raw
+                .retain(|_| docs.next().is_some_and(|map| !seen.contains(&map.id)));
+        } else {
+            self.raw.clear();
+        }
+
+        self.docs.retain(|map| !seen.contains(&map.id));
+        self.rejected
+            .retain(|map| map.id().is_none_or(|id| !seen.contains(id)));
+    }
+
+    /// How many maps there are, readable or not.
+    pub fn len(&self) -> usize {
+        self.docs.len() + self.rejected.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// When the oldest map that says when it was uploaded was.
+    pub fn oldest_upload(&self) -> Option<DateTime<Utc>> {
+        self.docs
+            .iter()
+            .map(|map| map.uploaded)
+            .chain(self.rejected.iter().filter_map(RejectedMap::uploaded))
+            .min()
+    }
+}
+
+/// A map BeatSaver sent that doesn't fit the models.
+pub struct RejectedMap {
+    pub raw: serde_json::Value,
+    pub error: serde_json::Error,
+}
+
+impl RejectedMap {
+    pub fn id(&self) -> Option<&str> {
+        self.raw.get("id").and_then(serde_json::Value::as_str)
+    }
+
+    /// When it was uploaded, if that much of it is readable.
+    pub fn uploaded(&self) -> Option<DateTime<Utc>> {
+        let uploaded = self.raw.get("uploaded")?.as_str()?;
+
+        DateTime::parse_from_rfc3339(uploaded)
+            .ok()
+            .map(|uploaded| uploaded.with_timezone(&Utc))
+    }
+}
+
+/// `/maps/ids` and `/maps/hash` answer with the map itself when asked for just one, and an object
+/// keyed by whatever was asked for otherwise.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdsResponse {
+    Many(BTreeMap<String, MapDetail>),
+    One(Box<MapDetail>),
+}
+
+/// The most keys or hashes BeatSaver takes in one lookup.
+pub const MAX_IDS_PER_REQUEST: usize = 50;
+
+/// What to look maps up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupBy {
+    Key,
+    Hash,
+}
+
+impl LookupBy {
+    fn path(self) -> &'static str {
+        match self {
+            LookupBy::Key => "/maps/ids",
+            LookupBy::Hash => "/maps/hash",
+        }
+    }
+}
+
+/// `/maps/latest` without the typed models.
+#[derive(Deserialize)]
+struct RawPage {
+    docs: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("request failed")]
+    Http(#[source] reqwest::Error),
+    #[error("couldn't parse the response")]
+    Decode(#[source] serde_json::Error),
+    /// BeatSaver asked us to slow down. The limiter has already been paused for this long.
+    #[error("rate limited for {0:?}")]
+    RateLimited(Duration),
+}
+
+impl ApiError {
+    /// Short name for the kind of failure, for log fields.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::Http(_) => "http_error",
+            ApiError::Decode(_) => "decode_error",
+            ApiError::RateLimited(_) => "rate_limited",
+        }
+    }
+}
+
+/// Where every page of maps gets saved as BeatSaver sent it, for `reprocess`.
+struct Archive {
+    dir: PathBuf,
+    /// When the scrape started, so pages from different runs sort in order and don't clash.
+    run: String,
+    pages: AtomicU64,
+}
+
+impl Archive {
+    fn save(&self, body: &str) {
+        let page = self.pages.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{:06}.json", self.run, page));
+
+        if let Err(e) = fs::write(&path, body) {
+            warn!("[Archive] Couldn't save {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub struct ApiClient {
+    http: reqwest::Client,
+    base_url: String,
+    limiter: RateLimiter,
+    /// Fields BeatSaver sent that the typed models don't know about, and how often they showed up.
+    drift: Mutex<BTreeMap<String, u64>>,
+    /// Whether to ask BeatSaver for automapped maps too.
+    automapped: bool,
+    archive: Option<Archive>,
+}
+
+/// `docs.12.versions.0.foo` and `docs.3.versions.1.foo` are the same field as far as we care.
+fn normalize_path(path: &str) -> String {
+    path.split('.')
+        .map(|segment| {
+            if segment.chars().all(|c| c.is_ascii_digit()) {
+                "[]"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+impl ApiClient {
+    pub fn new(limiter: RateLimiter, config: &HttpConfig) -> anyhow::Result<Self> {
+        Ok(ApiClient {
+            http: config.client()?,
+            base_url: BEATSAVER_API_URL.to_string(),
+            limiter,
+            drift: Mutex::new(BTreeMap::new()),
+            automapped: false,
+            archive: None,
+        })
+    }
+
+    /// Also fetch automapped maps, which BeatSaver leaves out unless asked.
+    pub fn include_automapped(mut self, include: bool) -> Self {
+        self.automapped = include;
+        self
+    }
+
+    /// Save every page of maps into `dir` as it comes in, if given.
+    pub fn archive_pages(mut self, dir: Option<&str>) -> std::io::Result<Self> {
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+
+            self.archive = Some(Archive {
+                dir: PathBuf::from(dir),
+                run: Utc::now().format("%Y%m%dT%H%M%S").to_string(),
+                pages: AtomicU64::new(0),
+            });
+        }
+
+        Ok(self)
+    }
+
+    /// Deserializes a response, noting down any fields the models silently dropped.
+    fn decode<T: for<'de> Deserialize<'de>>(&self, body: &str) -> Result<T, ApiError> {
+        let mut ignored = Vec::new();
+        let deserializer = &mut serde_json::Deserializer::from_str(body);
+
+        let decoded = serde_ignored::deserialize(deserializer, |path| {
+            ignored.push(normalize_path(&path.to_string()));
+        })
+        .map_err(ApiError::Decode)?;
+
+        if !ignored.is_empty() {
+            let mut drift = self.drift.lock().unwrap();
+
+            for path in ignored {
+                let count = drift.entry(path.clone()).or_insert(0);
+                if *count == 0 {
+                    warn!(
+                        "[Drift] BeatSaver sent a field we don't know about: {}",
+                        path
+                    );
+                }
+                *count += 1;
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    /// Reads a page of maps, salvaging what it can if some of them don't parse. Without
+    /// `track_drift`, fields the models don't know about aren't noted down.
+    fn decode_page(&self, body: &str, track_drift: bool) -> Result<LatestPage, ApiError> {
+        if let Some(archive) = &self.archive {
+            archive.save(body);
+        }
+
+        let decoded = if track_drift {
+            self.decode::<LatestPage>(body)
+        } else {
+            serde_json::from_str(body).map_err(ApiError::Decode)
+        };
+
+        let mut page = match decoded {
+            Ok(page) => page,
+            Err(ApiError::Decode(error)) => return Self::salvage(body, error),
+            Err(e) => return Err(e),
+        };
+        page.raw = serde_json::from_str::<RawPage>(body)
+            .map(|raw| raw.docs)
+            .unwrap_or_default();
+
+        Ok(page)
+    }
+
+    /// Goes through a page the models choked on one map at a time, so one odd map doesn't take the
+    /// rest of the page down with it. Still fails if the page isn't even a list of docs.
+    fn salvage(body: &str, error: serde_json::Error) -> Result<LatestPage, ApiError> {
+        let Ok(raw_page) = serde_json::from_str::<RawPage>(body) else {
+            return Err(ApiError::Decode(error));
+        };
+
+        warn!(
+            "Couldn't parse the whole page ({}), going map by map",
+            error
+        );
+
+        // no drift tracking here, paths inside a single map wouldn't line up with the usual ones
+        Ok(LatestPage::from_docs(raw_page.docs))
+    }
+
+    /// GETs `path` and hands back the body, pausing the limiter if BeatSaver says to slow down.
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<String, ApiError> {
+        self.limiter.wait().await;
+
+        let res = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await
+            .map_err(ApiError::Http)?;
+
+        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = parse_retry_after(res.headers()).unwrap_or(DEFAULT_RETRY_AFTER);
+            self.limiter.pause(retry_after).await;
+
+            return Err(ApiError::RateLimited(retry_after));
+        }
+
+        let body = res
+            .error_for_status()
+            .map_err(ApiError::Http)?
+            .text()
+            .await
+            .map_err(ApiError::Http)?;
+
+        record_downloaded(body.len() as u64);
+
+        Ok(body)
+    }
+
+    /// Fetches a page of the newest maps uploaded before `before` (and after `after`, if given).
+    pub async fn latest(
+        &self,
+        before: DateTime<Utc>,
+        after: Option<DateTime<Utc>>,
+        page_size: u32,
+    ) -> Result<LatestPage, ApiError> {
+        let mut query = vec![
+            (
+                "before",
+                before.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ),
+            ("pageSize", page_size.to_string()),
+            ("automapper", self.automapped.to_string()),
+        ];
+
+        if let Some(after) = after {
+            query.push(("after", after.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        }
+
+        let body = self.get("/maps/latest", &query).await?;
+
+        self.decode_page(&body, true)
+    }
+
+    /// Fetches one page of the maps a mapper uploaded, newest first. Pages start at 0.
+    pub async fn uploader_maps(&self, uploader: u32, page: u32) -> Result<LatestPage, ApiError> {
+        let body = self
+            .get(&format!("/maps/uploader/{}/{}", uploader, page), &[])
+            .await?;
+
+        self.decode_page(&body, true)
+    }
+
+    /// Fetches one page of search results, newest first, optionally limited to maps uploaded
+    /// between `after` and `before`. Pages start at 0.
+    pub async fn search(
+        &self,
+        search: &SearchQuery,
+        page: u32,
+        after: Option<DateTime<Utc>>,
+        before: DateTime<Utc>,
+    ) -> Result<LatestPage, ApiError> {
+        let mut query = vec![
+            ("order", "Latest".to_string()),
+            ("automapper", self.automapped.to_string()),
+            ("to", before.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ];
+
+        if let Some(after) = after {
+            query.push(("from", after.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        }
+
+        search.push_params(&mut query);
+
+        let body = self.get(&format!("/search/text/{}", page), &query).await?;
+
+        // search pages come with an `info` block the models don't cover, drift there is noise
+        self.decode_page(&body, false)
+    }
+
+    /// Asks BeatSaver how many maps a scrape (from `after` onwards, if given) up to `before`
+    /// should come across, only counting what `search` finds if given. Only an estimate, it's for
+    /// the progress bar.
+    pub async fn total_maps(
+        &self,
+        after: Option<DateTime<Utc>>,
+        before: DateTime<Utc>,
+        search: Option<&SearchQuery>,
+    ) -> Result<u64, ApiError> {
+        let mut query = vec![
+            ("pageSize", "1".to_string()),
+            ("automapper", self.automapped.to_string()),
+            ("to", before.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        ];
+
+        if let Some(after) = after {
+            query.push(("from", after.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        }
+
+        if let Some(search) = search {
+            search.push_params(&mut query);
+        }
+
+        let body = self.get("/search/text/0", &query).await?;
+
+        // not going through decode, the search docs aren't what we're after so drift there is noise
+        let page: SearchPage = serde_json::from_str(&body).map_err(ApiError::Decode)?;
+
+        Ok(page.info.total)
+    }
+
+    /// Fetches up to `MAX_IDS_PER_REQUEST` maps by key or hash, keyed by whatever they were looked
+    /// up by (lowercase), each with the JSON it came from. Ones BeatSaver doesn't know (deleted
+    /// maps, mostly) are just missing from the result.
+    pub async fn lookup_maps(
+        &self,
+        by: LookupBy,
+        ids: &[String],
+    ) -> Result<BTreeMap<String, (MapDetail, serde_json::Value)>, ApiError> {
+        let path = format!("{}/{}", by.path(), ids.join(","));
+
+        let body = match self.get(&path, &[]).await {
+            Ok(body) => body,
+            // asking for one map that doesn't exist is a 404 instead of an empty answer
+            Err(ApiError::Http(e)) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                return Ok(BTreeMap::new());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let maps = match self.decode(&body)? {
+            IdsResponse::Many(maps) => maps,
+            IdsResponse::One(map) => BTreeMap::from([(ids[0].to_lowercase(), *map)]),
+        };
+
+        // the same maps as plain JSON, for what the models leave out
+        let mut raw = match serde_json::from_str(&body) {
+            Ok(serde_json::Value::Object(one)) if one.contains_key("id") => {
+                serde_json::Map::from_iter([(ids[0].to_lowercase(), one.into())])
+            }
+            Ok(serde_json::Value::Object(many)) => many,
+            _ => serde_json::Map::new(),
+        };
+
+        Ok(maps
+            .into_iter()
+            .map(|(id, map)| {
+                let raw = raw.remove(&id).unwrap_or_default();
+                (id, (map, raw))
+            })
+            .collect())
+    }
+
+    /// Every unknown field seen so far, with how many times it showed up.
+    pub fn drift_report(&self) -> BTreeMap<String, u64> {
+        self.drift.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,169 @@
+// BeatSaver's copy of BeatLeader's ranked data lags behind, so this asks BeatLeader directly
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::{
+    cacher::ratelimit::RateLimiter,
+    config::HttpConfig,
+    mapdata::{MapList, RankedState, RankedValue},
+};
+
+pub const BEATLEADER_API_URL: &str = "https://api.beatleader.com";
+
+/// Leaderboards per page, the most BeatLeader hands out.
+const PAGE_SIZE: u32 = 100;
+
+/// BeatLeader's ranking statuses, the ones that matter here anyway.
+const STATUS_NOMINATED: i32 = 1;
+const STATUS_QUALIFIED: i32 = 2;
+const STATUS_RANKED: i32 = 3;
+
+#[derive(Deserialize)]
+struct LeaderboardPage {
+    metadata: PageMetadata,
+    data: Vec<Leaderboard>,
+}
+
+#[derive(Deserialize)]
+struct PageMetadata {
+    total: u32,
+}
+
+#[derive(Deserialize)]
+struct Leaderboard {
+    id: String,
+    song: Song,
+    difficulty: LeaderboardDifficulty,
+}
+
+#[derive(Deserialize)]
+struct Song {
+    hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardDifficulty {
+    difficulty_name: String,
+    mode_name: String,
+    status: i32,
+    stars: Option<f32>,
+    pass_rating: Option<f32>,
+    acc_rating: Option<f32>,
+    tech_rating: Option<f32>,
+}
+
+/// Every ranked, qualified and nominated leaderboard on BeatLeader, by (lowercase hash,
+/// characteristic, difficulty).
+pub struct BeatLeaderRanking {
+    leaderboards: HashMap<(String, String, String), Leaderboard>,
+}
+
+/// Pages through every leaderboard BeatLeader is ranking (or thinking about ranking).
+pub async fn fetch_ranking(http: &HttpConfig) -> anyhow::Result<BeatLeaderRanking> {
+    let client = http.client()?;
+    let limiter = RateLimiter::new(5.0, 1);
+    let mut leaderboards = HashMap::new();
+    let mut page = 1;
+
+    loop {
+        limiter.wait().await;
+
+        let res: LeaderboardPage = client
+            .get(format!("{}/leaderboards", BEATLEADER_API_URL))
+            .query(&[
+                ("page", page.to_string()),
+                ("count", PAGE_SIZE.to_string()),
+                ("type", "ranking".to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        debug!(
+            "[BeatLeader] Got page {} ({} leaderboards)",
+            page,
+            res.data.len()
+        );
+
+        let done = res.data.is_empty() || page * PAGE_SIZE >= res.metadata.total;
+
+        for leaderboard in res.data {
+            let key = (
+                leaderboard.song.hash.to_lowercase(),
+                leaderboard.difficulty.mode_name.clone(),
+                leaderboard.difficulty.difficulty_name.clone(),
+            );
+            leaderboards.insert(key, leaderboard);
+        }
+
+        if done {
+            break;
+        }
+
+        page += 1;
+    }
+
+    info!("[BeatLeader] Fetched {} leaderboards", leaderboards.len());
+
+    Ok(BeatLeaderRanking { leaderboards })
+}
+
+/// Replaces BeatSaver's idea of every difficulty's BeatLeader ranking with BeatLeader's own.
+/// Difficulties BeatLeader isn't ranking come out unranked, whatever BeatSaver thought. Returns
+/// how many difficulties changed.
+pub fn apply_ranking(map_list: &mut MapList, ranking: &BeatLeaderRanking) -> usize {
+    let mut changed = 0;
+
+    for map in map_list.map_metadata.values_mut() {
+        let hash = map.hash.to_lowercase();
+
+        for diff in &mut map.difficulties {
+            let key = (
+                hash.clone(),
+                diff.characteristic_name.clone(),
+                diff.difficulty_name.clone(),
+            );
+
+            let ranked = match ranking.leaderboards.get(&key) {
+                Some(leaderboard) => {
+                    let difficulty = &leaderboard.difficulty;
+                    let state = match difficulty.status {
+                        STATUS_NOMINATED => RankedState::Nominated,
+                        STATUS_QUALIFIED => RankedState::Qualified,
+                        STATUS_RANKED => RankedState::Ranked,
+                        _ => RankedState::Unranked,
+                    };
+
+                    RankedValue {
+                        is_ranked: state == RankedState::Ranked,
+                        stars: difficulty.stars.unwrap_or(0.0),
+                        state: Some(state as i32),
+                        leaderboard_id: Some(leaderboard.id.clone()),
+                        pass_rating: difficulty.pass_rating,
+                        acc_rating: difficulty.acc_rating,
+                        tech_rating: difficulty.tech_rating,
+                    }
+                }
+                None => RankedValue {
+                    is_ranked: false,
+                    stars: 0.0,
+                    state: Some(RankedState::Unranked as i32),
+                    ..Default::default()
+                },
+            };
+
+            if diff.ranked.beat_leader != ranked {
+                diff.ranked.beat_leader = ranked;
+                changed += 1;
+            }
+        }
+    }
+
+    changed
+}
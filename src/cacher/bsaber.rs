@@ -0,0 +1,127 @@
+// BeastSaber's curator picks and review scores, a second opinion next to BeatSaver's curator flag
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+use crate::{cacher::ratelimit::RateLimiter, config::HttpConfig, mapdata::MapList};
+
+pub const BSABER_API_URL: &str = "https://bsaber.com/wp-json/bsaber-api";
+
+/// The bookmark list BeastSaber's curators put their picks in.
+const CURATED_LIST: &str = "curatorrecommended";
+
+#[derive(Deserialize)]
+struct SongPage {
+    songs: Vec<Song>,
+    next_page: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Song {
+    song_key: String,
+}
+
+#[derive(Deserialize)]
+struct Ratings {
+    overall_rating: Option<f32>,
+}
+
+/// What BeastSaber thinks of the maps it knows about, by key.
+pub struct BeastSaberCuration {
+    curated: HashSet<String>,
+    reviews: HashMap<String, f32>,
+}
+
+/// Fetches BeastSaber's curated list, then the review score of every curated map in `keys`.
+/// Asking about every map in a cache would take days at BeastSaber's pace, so maps that aren't
+/// curated don't get a review score.
+pub async fn fetch_curation(
+    http: &HttpConfig,
+    keys: &HashSet<String>,
+) -> anyhow::Result<BeastSaberCuration> {
+    let client = http.client()?;
+    let limiter = RateLimiter::new(2.0, 1);
+    let mut curated = HashSet::new();
+    let mut page = 1;
+
+    loop {
+        limiter.wait().await;
+
+        let res: SongPage = client
+            .get(format!("{}/songs/", BSABER_API_URL))
+            .query(&[
+                ("bookmarked_by", CURATED_LIST.to_string()),
+                ("page", page.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        debug!("[BeastSaber] Got page {} ({} songs)", page, res.songs.len());
+
+        curated.extend(
+            res.songs
+                .into_iter()
+                .map(|song| song.song_key.to_lowercase()),
+        );
+
+        match res.next_page {
+            Some(next) if next > page => page = next,
+            _ => break,
+        }
+    }
+
+    info!("[BeastSaber] Fetched {} curated maps", curated.len());
+
+    let mut reviews = HashMap::new();
+
+    for key in curated.intersection(keys) {
+        limiter.wait().await;
+
+        let res = client
+            .get(format!("{}/songs/{}/ratings", BSABER_API_URL, key))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+
+        // one missing score isn't worth throwing the curated list away
+        let ratings: Ratings = match res {
+            Ok(res) => res.json().await?,
+            Err(e) => {
+                warn!("[BeastSaber] Couldn't fetch the review of {}: {}", key, e);
+                continue;
+            }
+        };
+
+        if let Some(rating) = ratings.overall_rating {
+            reviews.insert(key.clone(), rating);
+        }
+    }
+
+    info!("[BeastSaber] Fetched {} review scores", reviews.len());
+
+    Ok(BeastSaberCuration { curated, reviews })
+}
+
+/// Marks every map in `map_list` as curated on BeastSaber or not, with its review score if it
+/// has one. Returns how many maps are curated.
+pub fn apply_curation(map_list: &mut MapList, curation: &BeastSaberCuration) -> usize {
+    let mut curated = 0;
+
+    for (key, map) in map_list.map_metadata.iter_mut() {
+        let is_curated = curation.curated.contains(key);
+
+        map.curated_on_bsaber = Some(is_curated);
+        map.bsaber_review = curation.reviews.get(key).copied();
+
+        if is_curated {
+            curated += 1;
+        }
+    }
+
+    curated
+}
@@ -0,0 +1,62 @@
+// sidecar files so people can check they actually got the cache we made
+
+use std::{fs, io, path::Path};
+
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::cacher::error::CacherError;
+
+/// SHA-256 of a file, hex-encoded.
+pub fn sha256_file(path: &str) -> io::Result<String> {
+    Ok(hex::encode(Sha256::digest(fs::read(path)?)))
+}
+
+/// Writes `<path>.sha256` in the same format `sha256sum` uses, so `sha256sum -c` just works.
+pub fn write_checksum(path: &str) -> Result<(), CacherError> {
+    let hash = sha256_file(path)?;
+
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    let sidecar = format!("{}.sha256", path);
+
+    fs::write(&sidecar, format!("{}  {}\n", hash, file_name))?;
+    info!("Saved checksum to {}", sidecar);
+
+    Ok(())
+}
+
+/// Loads an ed25519 signing key from a file containing the hex-encoded 32-byte seed.
+fn load_signing_key(key_path: &str) -> Result<SigningKey, CacherError> {
+    let contents = fs::read_to_string(key_path)?;
+
+    let seed: [u8; 32] = hex::decode(contents.trim())
+        .ok()
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or_else(|| CacherError::SigningKey {
+            path: key_path.to_string(),
+        })?;
+
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Signs the file with the given key, writing the hex-encoded signature to `<path>.sig`.
+pub fn write_signature(path: &str, key_path: &str) -> Result<(), CacherError> {
+    let signing_key = load_signing_key(key_path)?;
+    let data = fs::read(path)?;
+
+    let signature = signing_key.sign(&data);
+    let sidecar = format!("{}.sig", path);
+
+    fs::write(&sidecar, hex::encode(signature.to_bytes()))?;
+    info!(
+        "Saved signature to {} (public key {})",
+        sidecar,
+        hex::encode(signing_key.verifying_key().to_bytes())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+// the cache cut into fixed-size pieces, so clients on flaky connections can download it a bit at
+// a time and only fetch the pieces that changed
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::cacher::{checksum::sha256_file, error::CacherError, usage::record_written};
+
+#[derive(Serialize, Deserialize)]
+struct ChunkIndex {
+    /// The cache's file name.
+    file: String,
+    size: u64,
+    sha256: String,
+    chunk_size: u64,
+    /// In order. Each one is at `<sha256>.chunk` next to the index.
+    chunks: Vec<ChunkEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkEntry {
+    sha256: String,
+    size: u64,
+}
+
+fn read_index(path: &Path) -> Option<ChunkIndex> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+/// Every chunk any index in `dir` points to.
+fn referenced_chunks(dir: &Path) -> Result<HashSet<String>, CacherError> {
+    let mut referenced = HashSet::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|ext| ext == "json")
+            && let Some(index) = read_index(&path)
+        {
+            referenced.extend(index.chunks.into_iter().map(|chunk| chunk.sha256));
+        }
+    }
+
+    Ok(referenced)
+}
+
+/// Cuts the cache at `cache_path` into `chunk_size` pieces in `dir`, named by their SHA-256 so
+/// pieces that didn't change keep their name, and writes `<cache file name>.json` listing them.
+/// Several caches can share a directory. Chunks nothing points to anymore are deleted, except
+/// the ones the index being replaced used, for clients that are halfway through downloading it.
+pub fn write_chunks(cache_path: &str, dir: &str, chunk_size: u64) -> Result<(), CacherError> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+
+    let file = Path::new(cache_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| cache_path.to_string());
+    let index_path = dir.join(format!("{}.json", file));
+    let previous = read_index(&index_path);
+
+    let mut cache = File::open(cache_path)?;
+    let mut buf = vec![0; chunk_size as usize];
+    let mut index = ChunkIndex {
+        file,
+        size: 0,
+        sha256: sha256_file(cache_path)?,
+        chunk_size,
+        chunks: Vec::new(),
+    };
+    let mut written = 0;
+
+    loop {
+        // read() can come back short, and a chunk has to be full unless it's the last one
+        let mut len = 0;
+
+        while len < buf.len() {
+            match cache.read(&mut buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+
+        if len == 0 {
+            break;
+        }
+
+        let chunk = &buf[..len];
+        let sha256 = hex::encode(Sha256::digest(chunk));
+        let chunk_path = dir.join(format!("{}.chunk", sha256));
+
+        if !chunk_path.exists() {
+            fs::write(&chunk_path, chunk)?;
+            record_written(len as u64);
+            written += 1;
+        }
+
+        index.size += len as u64;
+        index.chunks.push(ChunkEntry {
+            sha256,
+            size: len as u64,
+        });
+    }
+
+    fs::write(&index_path, serde_json::to_string_pretty(&index).unwrap())?;
+    info!(
+        "Cut {} into {} chunks in {} ({} new)",
+        cache_path,
+        index.chunks.len(),
+        dir.display(),
+        written
+    );
+
+    let mut keep = referenced_chunks(dir)?;
+
+    if let Some(previous) = previous {
+        keep.extend(previous.chunks.into_iter().map(|chunk| chunk.sha256));
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(sha256) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".chunk"))
+        else {
+            continue;
+        };
+
+        if !keep.contains(sha256) {
+            fs::remove_file(&path)?;
+            debug!("Deleted old chunk {}", sha256);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+// map covers shrunk down to thumbnails and packed next to the cache, so in-game UIs can show
+// artwork without going to the CDN for every map
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    path::Path,
+};
+
+use futures::stream::{self, StreamExt};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::{
+    cacher::usage::record_written,
+    config::{CoverConfig, HttpConfig},
+    mapdata::{MapList, MapMetadata},
+};
+
+/// What the pack's `index.json` says.
+#[derive(Serialize)]
+struct CoverIndex<'a> {
+    /// Width and height of every thumbnail.
+    size: u32,
+    /// Map hash to the SHA-256 of its thumbnail, which is also its file name in the pack.
+    maps: &'a BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CoverStats {
+    pub downloaded: usize,
+    /// Thumbnails already there from an earlier run.
+    pub reused: usize,
+    pub failed: usize,
+    /// Distinct thumbnails in the pack, maps sharing a cover share one.
+    pub packed: usize,
+}
+
+/// The cover's URL, or where BeatSaver's CDN keeps it for caches made without `urls`.
+fn cover_url(map: &MapMetadata) -> String {
+    map.cover_url
+        .clone()
+        .unwrap_or_else(|| format!("https://cdn.beatsaver.com/{}.jpg", map.hash.to_lowercase()))
+}
+
+/// Crops a cover to a square and scales it down to `size`.
+fn make_thumbnail(cover: &[u8], size: u32, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let thumbnail = image::load_from_memory(cover)?
+        .resize_to_fill(size, size, FilterType::Lanczos3)
+        .to_rgb8();
+
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&thumbnail)?;
+
+    Ok(encoded)
+}
+
+async fn fetch_thumbnail(
+    client: &reqwest::Client,
+    url: &str,
+    size: u32,
+    quality: u8,
+) -> anyhow::Result<Vec<u8>> {
+    let cover = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    // decoding and resizing is CPU work, so it stays off the runtime's threads
+    tokio::task::spawn_blocking(move || make_thumbnail(&cover, size, quality)).await?
+}
+
+/// Downloads the thumbnail of every map in `map_lists` that doesn't have one in the thumbnail
+/// directory yet, then packs all of them into a tar at `path` with an `index.json`. Covers that
+/// fail to download are left out of the pack, and retried on the next run.
+pub async fn write_cover_pack(
+    map_lists: &[&MapList],
+    config: &CoverConfig,
+    http: &HttpConfig,
+    path: &str,
+) -> anyhow::Result<CoverStats> {
+    // a different size is a different set of thumbnails
+    let dir = Path::new(&config.dir).join(config.size.to_string());
+    fs::create_dir_all(&dir)?;
+
+    let client = http.client()?;
+    let mut stats = CoverStats::default();
+    let mut seen = HashSet::new();
+    let mut missing = Vec::new();
+
+    for map in map_lists.iter().flat_map(|maps| maps.map_metadata.values()) {
+        let hash = map.hash.to_lowercase();
+
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let file = dir.join(format!("{}.jpg", hash));
+
+        if file.exists() {
+            stats.reused += 1;
+        } else {
+            missing.push((hash, cover_url(map), file));
+        }
+    }
+
+    info!(
+        "[Covers] {} thumbnails to download, {} already there",
+        missing.len(),
+        stats.reused
+    );
+
+    let mut downloads = stream::iter(missing)
+        .map(|(hash, url, file)| {
+            let client = &client;
+
+            async move {
+                let thumbnail = fetch_thumbnail(client, &url, config.size, config.quality).await;
+                (hash, file, thumbnail)
+            }
+        })
+        .buffer_unordered(config.concurrency.max(1));
+
+    while let Some((hash, file, thumbnail)) = downloads.next().await {
+        match thumbnail.and_then(|thumbnail| Ok(fs::write(&file, thumbnail)?)) {
+            Ok(()) => stats.downloaded += 1,
+            Err(e) => {
+                warn!("[Covers] Couldn't get the cover of {}: {:?}", hash, e);
+                stats.failed += 1;
+            }
+        }
+    }
+
+    stats.packed = pack_thumbnails(&dir, seen, config.size, path)?;
+
+    info!(
+        "[Covers] Packed {} thumbnails into {} ({} downloaded, {} failed)",
+        stats.packed, path, stats.downloaded, stats.failed
+    );
+
+    Ok(stats)
+}
+
+/// Writes every thumbnail in `dir` for `hashes` into a tar, named by its own SHA-256.
+fn pack_thumbnails(
+    dir: &Path,
+    hashes: HashSet<String>,
+    size: u32,
+    path: &str,
+) -> anyhow::Result<usize> {
+    let mut builder = tar::Builder::new(File::create(path)?);
+    let mut index = BTreeMap::new();
+    let mut packed = HashSet::new();
+
+    // sorted, so the same thumbnails always make the same pack
+    let mut hashes: Vec<String> = hashes.into_iter().collect();
+    hashes.sort();
+
+    for hash in hashes {
+        let Ok(thumbnail) = fs::read(dir.join(format!("{}.jpg", hash))) else {
+            continue;
+        };
+        let digest = hex::encode(Sha256::digest(&thumbnail));
+
+        if packed.insert(digest.clone()) {
+            append(&mut builder, &format!("{}.jpg", digest), &thumbnail)?;
+        } else {
+            debug!("[Covers] {} has the same cover as another map", hash);
+        }
+
+        index.insert(hash, digest);
+    }
+
+    let index = serde_json::to_vec(&CoverIndex { size, maps: &index }).unwrap();
+    append(&mut builder, "index.json", &index)?;
+    builder.into_inner()?;
+
+    record_written(fs::metadata(path)?.len());
+
+    Ok(packed.len())
+}
+
+fn append(builder: &mut tar::Builder<File>, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    // no mtime, same reason as sorting
+    header.set_mtime(0);
+
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
@@ -0,0 +1,169 @@
+// what changed between two versions of a cache, so clients only download that
+
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, GzBuilder, read::GzDecoder};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    cacher::{encode_entries_streaming, error::CacherError, usage::record_written},
+    mapdata::{MapList, MapListDelta},
+};
+
+/// Identifies a version of a cache by what's in it: the SHA-256 of the encoded `MapList`,
+/// hex-encoded. Unlike the file's hash, it doesn't care how the cache was compressed.
+pub fn cache_version(map_list: &MapList) -> String {
+    let mut hasher = Sha256::new();
+
+    // hashing never fails
+    encode_entries_streaming(map_list.map_metadata.iter().map(Ok), false, &mut hasher).unwrap();
+
+    hex::encode(hasher.finalize())
+}
+
+/// Every map that's new or different in `target`, and every key that's gone.
+pub fn diff_caches(base: &MapList, target: &MapList) -> MapListDelta {
+    let upserted = target
+        .map_metadata
+        .iter()
+        .filter(|(key, map)| base.map_metadata.get(*key) != Some(map))
+        .map(|(key, map)| (key.clone(), map.clone()))
+        .collect();
+
+    let removed = base
+        .map_metadata
+        .keys()
+        .filter(|key| !target.map_metadata.contains_key(*key))
+        .cloned()
+        .collect();
+
+    MapListDelta {
+        base_version: cache_version(base),
+        target_version: cache_version(target),
+        upserted,
+        removed,
+    }
+}
+
+/// Writes the delta from `base` to `target` as `<dir>/<base version>.delta.gz`, so a client
+/// looks for the one named after the version it has, and keeps going until there isn't one.
+pub fn write_delta(base: &MapList, target: &MapList, dir: &str) -> Result<PathBuf, CacherError> {
+    let delta = diff_caches(base, target);
+    let path = Path::new(dir).join(format!("{}.delta.gz", delta.base_version));
+
+    fs::create_dir_all(dir)?;
+
+    let mut gz = GzBuilder::new()
+        .mtime(0)
+        .write(Vec::new(), Compression::default());
+    gz.write_all(&delta.encode_to_vec())?;
+    let compressed = gz.finish()?;
+
+    fs::write(&path, &compressed)?;
+    record_written(compressed.len() as u64);
+    info!(
+        "Saved delta to {} ({} changed, {} removed)",
+        path.display(),
+        delta.upserted.len(),
+        delta.removed.len()
+    );
+
+    Ok(path)
+}
+
+pub fn read_delta(path: &str) -> Result<MapListDelta, CacherError> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(fs::read(path)?.as_slice()).read_to_end(&mut decoded)?;
+
+    Ok(MapListDelta::decode(decoded.as_slice())?)
+}
+
+/// Brings `map_list` up to the delta's target version. Fails without touching it if it isn't the
+/// version the delta starts from, but if it doesn't end up at the target, it's left half-patched
+/// and shouldn't be written out.
+pub fn apply_delta(map_list: &mut MapList, delta: MapListDelta) -> Result<(), CacherError> {
+    let version = cache_version(map_list);
+
+    if version != delta.base_version {
+        return Err(CacherError::Delta(format!(
+            "it's for version {}, the cache is {}",
+            delta.base_version, version
+        )));
+    }
+
+    for key in &delta.removed {
+        map_list.map_metadata.remove(key);
+    }
+
+    map_list.map_metadata.extend(delta.upserted);
+
+    let version = cache_version(map_list);
+
+    if version != delta.target_version {
+        return Err(CacherError::Delta(format!(
+            "ended up at version {} instead of {}",
+            version, delta.target_version
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cacher::fixture::{change_fixture, generate_fixture, temp_path};
+
+    #[test]
+    fn takes_the_base_to_the_target() {
+        let base = generate_fixture(50, 1);
+        let target = change_fixture(&base);
+
+        let delta = diff_caches(&base, &target);
+        assert_eq!(delta.upserted.len(), 2);
+        assert_eq!(delta.removed.len(), 1);
+
+        let mut patched = base.clone();
+        apply_delta(&mut patched, delta).unwrap();
+        assert_eq!(patched.map_metadata, target.map_metadata);
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let dir = temp_path("delta-round-trip");
+        let base = generate_fixture(50, 2);
+        let target = change_fixture(&base);
+
+        let path = write_delta(&base, &target, &dir).unwrap();
+        assert_eq!(
+            path.file_name().unwrap().to_string_lossy(),
+            format!("{}.delta.gz", cache_version(&base))
+        );
+        assert_eq!(
+            read_delta(&path.to_string_lossy()).unwrap(),
+            diff_caches(&base, &target)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_cache_it_isnt_for() {
+        let base = generate_fixture(50, 3);
+        let target = change_fixture(&base);
+        let delta = diff_caches(&base, &target);
+
+        let mut other = generate_fixture(50, 4);
+        assert!(matches!(
+            apply_delta(&mut other, delta),
+            Err(CacherError::Delta(_))
+        ));
+        assert_eq!(other, generate_fixture(50, 4));
+    }
+}
@@ -0,0 +1,32 @@
+// the compiled .proto files, so generic protobuf tools can read a cache without this repo. with
+// the envelope and gzip stripped, `protoc --decode=CachedBeatSaverData.MapList
+// --descriptor_set_in=mapData.desc` prints it
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+use crate::cacher::{error::CacherError, usage::record_written};
+
+/// Every message in mapData.proto and mapService.proto, as a `FileDescriptorSet`.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "\\mapData.desc"));
+
+/// `mapData.desc` next to the cache.
+pub fn descriptor_path(cache_path: &str) -> PathBuf {
+    Path::new(cache_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join("mapData.desc")
+}
+
+/// Writes the descriptor set to `path`.
+pub fn write_descriptor_set(path: &Path) -> Result<(), CacherError> {
+    fs::write(path, FILE_DESCRIPTOR_SET)?;
+    record_written(FILE_DESCRIPTOR_SET.len() as u64);
+    info!("Saved the protobuf descriptor set to {}", path.display());
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// reading a cache without writing protobuf code for it, mostly for debugging the cacher itself
+
+use std::io::{self, Write};
+
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{
+    cacher::mods::{ModFlags, name_mods_in_json},
+    mapdata::{Difficulty, MapList, MapMetadata, RankedValue},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DumpFormat {
+    /// Every field, as JSON lines
+    Json,
+    /// A few lines per map, for reading
+    Text,
+}
+
+fn map_json(key: &str, map: &MapMetadata) -> io::Result<Value> {
+    let mut json = serde_json::to_value(map)?;
+    name_mods_in_json(&mut json);
+    json["key"] = key.into();
+
+    Ok(json)
+}
+
+fn date(secs: i64) -> String {
+    DateTime::from_timestamp(secs, 0).map_or_else(|| secs.to_string(), |at| at.to_rfc3339())
+}
+
+fn joined<S: AsRef<str>>(names: &[S]) -> String {
+    if names.is_empty() {
+        return "-".to_string();
+    }
+
+    names
+        .iter()
+        .map(|name| name.as_ref())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// The mods with a bit and whatever else BeatSaver says is needed, together.
+fn mods(bits: u32, other_requirements: &[String]) -> String {
+    let mods = ModFlags::from(bits);
+
+    match (mods.is_empty(), other_requirements.is_empty()) {
+        (_, true) => mods.to_string(),
+        (true, false) => joined(other_requirements),
+        (false, false) => format!("{}, {}", mods, joined(other_requirements)),
+    }
+}
+
+fn stars(ranked: &RankedValue) -> String {
+    if ranked.is_ranked {
+        format!("{:.2}*", ranked.stars)
+    } else {
+        "-".to_string()
+    }
+}
+
+fn write_difficulty(out: &mut impl Write, diff: &Difficulty) -> io::Result<()> {
+    writeln!(
+        out,
+        "    {:<12} {:<11} njs {:>5.2}  notes {:>5}  ss {:>7}  bl {:>7}  mods {}",
+        diff.characteristic_name,
+        diff.difficulty_name,
+        diff.njs,
+        diff.notes,
+        stars(&diff.ranked.score_saber),
+        stars(&diff.ranked.beat_leader),
+        mods(diff.mods, &diff.other_requirements)
+    )
+}
+
+fn write_text(out: &mut impl Write, key: &str, map: &MapMetadata) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}  {} - {} ({})",
+        key,
+        map.song_author_name(),
+        map.song_name(),
+        map.level_author_name()
+    )?;
+    writeln!(out, "  hash      {}", map.hash)?;
+    writeln!(
+        out,
+        "  uploaded  {}  updated {}",
+        date(map.uploaded),
+        date(map.last_updated)
+    )?;
+    writeln!(
+        out,
+        "  length    {}:{:02}  bpm {}",
+        map.duration / 60,
+        map.duration % 60,
+        map.bpm()
+    )?;
+    writeln!(
+        out,
+        "  votes     {} up, {} down  score {:.3}",
+        map.votes.up,
+        map.votes.down,
+        map.score()
+    )?;
+    writeln!(
+        out,
+        "  mods      {}",
+        mods(map.mods, &map.other_requirements)
+    )?;
+    writeln!(out, "  tags      {}", joined(&map.tags))?;
+
+    for diff in &map.difficulties {
+        write_difficulty(out, diff)?;
+    }
+
+    writeln!(out)
+}
+
+/// Prints `limit` maps from the `offset`th on, in key order.
+pub fn dump_maps(
+    map_list: &MapList,
+    offset: usize,
+    limit: usize,
+    format: DumpFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for (key, map) in map_list.map_metadata.iter().skip(offset).take(limit) {
+        match format {
+            DumpFormat::Json => writeln!(out, "{}", map_json(key, map)?)?,
+            DumpFormat::Text => write_text(out, key, map)?,
+        }
+    }
+
+    Ok(())
+}
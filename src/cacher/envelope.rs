@@ -0,0 +1,241 @@
+// a tiny header in front of the compressed protobuf, so readers know what they're looking at
+//
+// layout (all little-endian):
+//   magic          [u8; 4]  "DRMC"
+//   format_version u16
+//   header_len     u16      size of the whole header, so newer headers can grow
+//   created        i64      unix seconds of the newest map update, not the wall clock
+//   map_count      u32
+//   checksum       u32      CRC32 of the payload
+//   payload_len    u64
+//   payload        gzipped `MapList`
+//
+// format versions:
+//   0  no envelope at all, the file is just the gzipped `MapList` (written before there was one)
+//   1  timestamps in the payload are uint32
+//   2  timestamps in the payload are int64
+//   3  the payload has its own schemaVersion, and requiredMods/suggestedMods
+//   4  every map in the payload is followed by a CRC32 of it
+//   5  characteristic, difficulty and environment names can be in a string table in the payload
+//      (--intern-names), every name in it followed by a CRC32 of it
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+};
+
+use anyhow::{Result, bail};
+
+pub const MAGIC: &[u8; 4] = b"DRMC";
+pub const FORMAT_VERSION: u16 = 5;
+
+pub const HEADER_LEN: usize = 4 + 2 + 2 + 8 + 4 + 4 + 8;
+
+/// What every gzip stream starts with, and so every cache from before the envelope.
+const GZIP_MAGIC: &[u8; 2] = &[0x1f, 0x8b];
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeHeader {
+    pub format_version: u16,
+    pub created: i64,
+    pub map_count: u32,
+    pub checksum: u32,
+    pub payload_len: u64,
+}
+
+/// Builds the envelope header for a payload that has already been written.
+pub fn encode_header(map_count: usize, created: i64, checksum: u32, payload_len: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN);
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+    out.extend_from_slice(&created.to_le_bytes());
+    out.extend_from_slice(&(map_count as u32).to_le_bytes());
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out.extend_from_slice(&payload_len.to_le_bytes());
+
+    out
+}
+
+/// Passes the payload through to `inner`, keeping track of its checksum and length on the way,
+/// so the header can be filled in afterwards without buffering the payload.
+pub struct PayloadWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    len: u64,
+}
+
+impl<W: Write> PayloadWriter<W> {
+    pub fn new(inner: W) -> Self {
+        PayloadWriter {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+            len: 0,
+        }
+    }
+
+    /// Hands back the inner writer, the payload checksum and the payload length.
+    pub fn finish(self) -> (W, u32, u64) {
+        (self.inner, self.hasher.finalize(), self.len)
+    }
+}
+
+impl<W: Write> Write for PayloadWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether `data` is a cache from before the envelope (format version 0). Those have no header,
+/// so nothing about them can be checked before decoding.
+pub fn is_legacy(data: &[u8]) -> bool {
+    data.starts_with(GZIP_MAGIC)
+}
+
+/// Same as `is_legacy`, for the cache at `path`, reading only as much of it as that takes.
+pub fn is_legacy_file(path: &str) -> io::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)?
+        .take(GZIP_MAGIC.len() as u64)
+        .read_to_end(&mut start)?;
+
+    Ok(is_legacy(&start))
+}
+
+/// Reads only the header, without touching the payload.
+pub fn read_header(data: &[u8]) -> Result<EnvelopeHeader> {
+    if data.len() < 8 || &data[0..4] != MAGIC {
+        bail!("Not a cache file (bad magic bytes)");
+    }
+
+    let format_version = u16::from_le_bytes([data[4], data[5]]);
+    if format_version > FORMAT_VERSION {
+        bail!(
+            "Cache is format version {}, but this build only understands up to {}",
+            format_version,
+            FORMAT_VERSION
+        );
+    }
+
+    let header_len = u16::from_le_bytes([data[6], data[7]]) as usize;
+    if header_len < HEADER_LEN || data.len() < header_len {
+        bail!("Cache header is truncated");
+    }
+
+    Ok(EnvelopeHeader {
+        format_version,
+        created: i64::from_le_bytes(data[8..16].try_into()?),
+        map_count: u32::from_le_bytes(data[16..20].try_into()?),
+        checksum: u32::from_le_bytes(data[20..24].try_into()?),
+        payload_len: u64::from_le_bytes(data[24..32].try_into()?),
+    })
+}
+
+/// Validates the envelope and hands back the header and the (still compressed) payload.
+pub fn unwrap(data: &[u8]) -> Result<(EnvelopeHeader, &[u8])> {
+    let header = read_header(data)?;
+    let header_len = u16::from_le_bytes([data[6], data[7]]) as usize;
+
+    let payload = &data[header_len..];
+    if payload.len() as u64 != header.payload_len {
+        bail!(
+            "Cache payload is {} bytes, header says {}",
+            payload.len(),
+            header.payload_len
+        );
+    }
+
+    if crc32fast::hash(payload) != header.checksum {
+        bail!("Cache payload checksum mismatch, file is probably corrupt");
+    }
+
+    Ok((header, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        cacher::{
+            decode_cache,
+            fixture::{generate_fixture, temp_path},
+            write_cache_file,
+        },
+        mapdata::MapList,
+    };
+
+    /// What `write_cache` writes for `map_list`.
+    fn written(map_list: &MapList, name: &str) -> Vec<u8> {
+        let path = temp_path(name);
+        let entries = map_list.map_metadata.iter().map(Ok);
+        write_cache_file(
+            entries,
+            map_list.map_metadata.len(),
+            1600000000,
+            false,
+            &path,
+        )
+        .unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn round_trips_a_fixture() {
+        let map_list = generate_fixture(100, 1);
+        let data = written(&map_list, "envelope-round-trip");
+
+        let header = read_header(&data).unwrap();
+        assert_eq!(header.format_version, FORMAT_VERSION);
+        assert_eq!(header.created, 1600000000);
+        assert_eq!(header.map_count, 100);
+        assert!(!is_legacy(&data));
+
+        let (_, payload) = unwrap(&data).unwrap();
+        assert_eq!(payload.len() as u64, header.payload_len);
+        assert_eq!(
+            decode_cache(&data).unwrap().map_metadata,
+            map_list.map_metadata
+        );
+    }
+
+    #[test]
+    fn rejects_a_damaged_payload() {
+        let mut data = written(&generate_fixture(20, 2), "envelope-damaged");
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+
+        assert!(unwrap(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_a_truncated_payload() {
+        let mut data = written(&generate_fixture(20, 3), "envelope-truncated");
+        data.truncate(data.len() - 10);
+
+        assert!(unwrap(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_a_newer_format_version() {
+        let mut header = encode_header(0, 0, 0, 0);
+        header[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert!(read_header(&header).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+// one error type for everything the cacher can fail at, so callers (and exit codes) can tell
+// failures apart
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::cacher::api::ApiError;
+
+#[derive(Debug, Error)]
+pub enum CacherError {
+    /// A page kept failing until it ran out of retries.
+    #[error("gave up on the page before {before} after {attempts} retries")]
+    Api {
+        before: DateTime<Utc>,
+        attempts: u32,
+        #[source]
+        source: ApiError,
+    },
+    /// A batch of maps fetched by key kept failing until it ran out of retries.
+    #[error("gave up on the {count} maps starting at {first} after {attempts} retries")]
+    Batch {
+        first: String,
+        count: usize,
+        attempts: u32,
+        #[source]
+        source: ApiError,
+    },
+    /// BeatSaver kept sending pages that didn't get the scrape any further back, even after
+    /// nudging the cursor.
+    #[error("stuck at {before}, {pages} pages in a row didn't move the cursor")]
+    Stalled { before: DateTime<Utc>, pages: u32 },
+    /// The cache decompressed fine, but isn't a valid `MapList`.
+    #[error("couldn't decode the cache")]
+    Decode(#[from] prost::DecodeError),
+    /// The envelope around the cache is missing, truncated or doesn't match its payload.
+    #[error("not a valid cache file: {0}")]
+    Envelope(String),
+    /// A difficulty or characteristic points past the end of the cache's string table.
+    #[error("name {index} isn't in the string table, which only has {len}")]
+    UnresolvedName { index: u32, len: usize },
+    /// A journal that's cut short before its header, or from a newer version.
+    #[error("not a valid journal: {0}")]
+    Journal(String),
+    /// A delta that's for a different version of the cache, or didn't end up where it said.
+    #[error("delta doesn't apply: {0}")]
+    Delta(String),
+    /// A `.bplist` that isn't valid playlist JSON.
+    #[error("{path} isn't a valid playlist")]
+    Playlist {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// Maps saved from BeatSaver earlier that aren't BeatSaver's JSON.
+    #[error("{path} isn't a saved page of maps")]
+    SavedMaps {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// The maps couldn't be turned into one of the export formats.
+    #[error("couldn't encode the cache: {0}")]
+    Encode(String),
+    #[error("signing key {path} isn't a 32-byte hex seed")]
+    SigningKey { path: String },
+    /// The search index couldn't be built, opened or searched.
+    #[error("search index failed")]
+    Search(#[from] tantivy::TantivyError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl CacherError {
+    /// What the process exits with when a run fails with this.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CacherError::Api { .. } | CacherError::Batch { .. } | CacherError::Stalled { .. } => 3,
+            CacherError::Decode(_)
+            | CacherError::Envelope(_)
+            | CacherError::UnresolvedName { .. }
+            | CacherError::Journal(_)
+            | CacherError::Delta(_)
+            | CacherError::Playlist { .. }
+            | CacherError::SavedMaps { .. } => 4,
+            CacherError::Encode(_) | CacherError::SigningKey { .. } => 5,
+            CacherError::Search(_) | CacherError::Io(_) => 6,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// "don't suggest that, we just played it" lists from the request bot
+
+use std::{collections::HashSet, fs};
+
+use tracing::info;
+
+use crate::mapdata::MapList;
+
+/// Parses a feed, which is either a JSON array of strings or one key/hash per line (`#` comments
+/// allowed). Everything is lowercased so keys and hashes match however the bot writes them.
+fn parse_feed(contents: &str) -> anyhow::Result<HashSet<String>> {
+    let entries: Vec<String> = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(contents)?
+    } else {
+        contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect()
+    };
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| entry.trim().to_lowercase())
+        .collect())
+}
+
+/// Loads an exclusion feed from a file, or from an HTTP(S) endpoint if `source` looks like a URL.
+pub async fn load_exclusion_feed(source: &str) -> anyhow::Result<HashSet<String>> {
+    let contents = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(source)
+            .await?
+            .error_for_status()?
+            .text()
+            .await?
+    } else {
+        fs::read_to_string(source)?
+    };
+
+    let excluded = parse_feed(&contents)?;
+    info!(
+        "Loaded {} recently played maps from {}",
+        excluded.len(),
+        source
+    );
+
+    Ok(excluded)
+}
+
+/// Flags every map whose key or hash is in `excluded`. Returns how many got flagged.
+pub fn flag_recently_played(map_list: &mut MapList, excluded: &HashSet<String>) -> usize {
+    let mut flagged = 0;
+
+    for (key, map) in map_list.map_metadata.iter_mut() {
+        let recently_played =
+            excluded.contains(&key.to_lowercase()) || excluded.contains(&map.hash.to_lowercase());
+
+        map.recently_played = Some(recently_played);
+
+        if recently_played {
+            flagged += 1;
+        }
+    }
+
+    flagged
+}
@@ -0,0 +1,56 @@
+// for people who'd rather not touch protobuf
+
+use std::fs;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    cacher::{error::CacherError, usage::record_written},
+    mapdata::MapList,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// MessagePack, with field names kept as map keys
+    Msgpack,
+    /// CBOR (RFC 8949)
+    Cbor,
+}
+
+/// Serializes the map list into one of the serde-backed binary formats.
+fn encode_map_list(map_list: &MapList, format: ExportFormat) -> Result<Vec<u8>, CacherError> {
+    match format {
+        ExportFormat::Msgpack => {
+            rmp_serde::to_vec_named(map_list).map_err(|e| CacherError::Encode(e.to_string()))
+        }
+        ExportFormat::Cbor => {
+            let mut buf = Vec::new();
+            ciborium::into_writer(map_list, &mut buf)
+                .map_err(|e| CacherError::Encode(e.to_string()))?;
+            Ok(buf)
+        }
+    }
+}
+
+/// Writes the map list to `path` in the given format.
+pub fn export_cache(
+    map_list: &MapList,
+    format: ExportFormat,
+    path: &str,
+) -> Result<(), CacherError> {
+    let encoded = encode_map_list(map_list, format)?;
+
+    fs::write(path, &encoded)?;
+    record_written(encoded.len() as u64);
+    info!(
+        "Exported {} maps as {:?} to {}",
+        map_list.map_metadata.len(),
+        format,
+        path
+    );
+
+    Ok(())
+}
@@ -0,0 +1,584 @@
+// a small language for picking maps, like `ss_stars > 7 && mods.chroma && duration < 240`
+//
+//   expr     = and ("||" and)*
+//   and      = unary ("&&" unary)*
+//   unary    = "!" unary | "(" expr ")" | compare
+//   compare  = operand (("==" | "!=" | "<" | "<=" | ">" | ">=" | "~") operand)?
+//   operand  = number | "string" | 'string' | true | false | field
+//
+// text compares ignore case, and `~` is "contains". a field a map doesn't have (no bpm, say)
+// makes every compare it's in false.
+
+use std::fmt;
+
+use crate::{
+    cacher::mods::ModFlags,
+    mapdata::{Difficulty, MapMetadata, RankedValue},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    Number,
+    Text,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Number => "a number",
+            Type::Text => "text",
+            Type::Bool => "true/false",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Field {
+    Key,
+    Hash,
+    SongName,
+    SongSubName,
+    SongAuthor,
+    Mapper,
+    Uploader,
+    Curator,
+    Duration,
+    Bpm,
+    Uploaded,
+    Created,
+    Updated,
+    Upvotes,
+    Downvotes,
+    Score,
+    Plays,
+    Downloads,
+    BsaberReview,
+    Difficulties,
+    SsStars,
+    BlStars,
+    Njs,
+    Nps,
+    Notes,
+    Ranked,
+    SsRanked,
+    BlRanked,
+    Curated,
+    BsaberCurated,
+    Vanilla,
+    Mod(ModFlags),
+    Tag(String),
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        if let Some(name) = name.strip_prefix("mods.") {
+            return ModFlags::from_name(name).map(Field::Mod);
+        }
+
+        if let Some(tag) = name.strip_prefix("tags.") {
+            return Some(Field::Tag(tag.to_string()));
+        }
+
+        Some(match name {
+            "key" => Field::Key,
+            "hash" => Field::Hash,
+            "song_name" => Field::SongName,
+            "song_sub_name" => Field::SongSubName,
+            "song_author" => Field::SongAuthor,
+            "mapper" => Field::Mapper,
+            "uploader" => Field::Uploader,
+            "curator" => Field::Curator,
+            "duration" => Field::Duration,
+            "bpm" => Field::Bpm,
+            "uploaded" => Field::Uploaded,
+            "created" => Field::Created,
+            "updated" => Field::Updated,
+            "upvotes" => Field::Upvotes,
+            "downvotes" => Field::Downvotes,
+            "score" => Field::Score,
+            "plays" => Field::Plays,
+            "downloads" => Field::Downloads,
+            "bsaber_review" => Field::BsaberReview,
+            "difficulties" => Field::Difficulties,
+            "ss_stars" => Field::SsStars,
+            "bl_stars" => Field::BlStars,
+            "njs" => Field::Njs,
+            "nps" => Field::Nps,
+            "notes" => Field::Notes,
+            "ranked" => Field::Ranked,
+            "ss_ranked" => Field::SsRanked,
+            "bl_ranked" => Field::BlRanked,
+            "curated" => Field::Curated,
+            "bsaber_curated" => Field::BsaberCurated,
+            "vanilla" => Field::Vanilla,
+            _ => return None,
+        })
+    }
+
+    fn value_type(&self) -> Type {
+        match self {
+            Field::Key
+            | Field::Hash
+            | Field::SongName
+            | Field::SongSubName
+            | Field::SongAuthor
+            | Field::Mapper
+            | Field::Uploader
+            | Field::Curator => Type::Text,
+            Field::Ranked
+            | Field::SsRanked
+            | Field::BlRanked
+            | Field::Curated
+            | Field::BsaberCurated
+            | Field::Vanilla
+            | Field::Mod(_)
+            | Field::Tag(_) => Type::Bool,
+            _ => Type::Number,
+        }
+    }
+
+    fn value(&self, key: &str, map: &MapMetadata) -> Option<Value> {
+        let text = |text: Option<&String>| text.map(|text| Value::Text(text.to_lowercase()));
+        let number = |number: f64| Some(Value::Number(number));
+        // highest of something over every difficulty, missing when there are none
+        let highest = |get: &dyn Fn(&Difficulty) -> Option<f64>| {
+            map.difficulties
+                .iter()
+                .filter_map(get)
+                .reduce(f64::max)
+                .map(Value::Number)
+        };
+        let stars = |ranked: &RankedValue| ranked.is_ranked.then_some(ranked.stars as f64);
+
+        match self {
+            Field::Key => Some(Value::Text(key.to_lowercase())),
+            Field::Hash => Some(Value::Text(map.hash.to_lowercase())),
+            Field::SongName => text(map.song_name.as_ref()),
+            Field::SongSubName => text(map.song_sub_name.as_ref()),
+            Field::SongAuthor => text(map.song_author_name.as_ref()),
+            Field::Mapper => text(map.level_author_name.as_ref()),
+            Field::Uploader => text(map.uploader_name.as_ref()),
+            Field::Curator => text(map.curator_name.as_ref()),
+            Field::Duration => number(map.duration as f64),
+            Field::Bpm => map.bpm.map(|bpm| Value::Number(bpm as f64)),
+            Field::Uploaded => number(map.uploaded as f64),
+            Field::Created => map.created.map(|created| Value::Number(created as f64)),
+            Field::Updated => number(map.last_updated as f64),
+            Field::Upvotes => number(map.votes.up as f64),
+            Field::Downvotes => number(map.votes.down as f64),
+            Field::Score => map.score.map(|score| Value::Number(score as f64)),
+            Field::Plays => map.plays.map(|plays| Value::Number(plays as f64)),
+            Field::Downloads => map
+                .downloads
+                .map(|downloads| Value::Number(downloads as f64)),
+            Field::BsaberReview => map.bsaber_review.map(|review| Value::Number(review as f64)),
+            Field::Difficulties => number(map.difficulties.len() as f64),
+            Field::SsStars => highest(&|diff| stars(&diff.ranked.score_saber)),
+            Field::BlStars => highest(&|diff| stars(&diff.ranked.beat_leader)),
+            Field::Njs => highest(&|diff| Some(diff.njs as f64)),
+            Field::Nps => highest(&|diff| diff.nps.map(|nps| nps as f64)),
+            Field::Notes => highest(&|diff| Some(diff.notes as f64)),
+            Field::Ranked => Some(Value::Bool(map.difficulties.iter().any(|diff| {
+                diff.ranked.score_saber.is_ranked || diff.ranked.beat_leader.is_ranked
+            }))),
+            Field::SsRanked => Some(Value::Bool(
+                map.difficulties
+                    .iter()
+                    .any(|diff| diff.ranked.score_saber.is_ranked),
+            )),
+            Field::BlRanked => Some(Value::Bool(
+                map.difficulties
+                    .iter()
+                    .any(|diff| diff.ranked.beat_leader.is_ranked),
+            )),
+            Field::Curated => Some(Value::Bool(map.curator_name.is_some())),
+            Field::BsaberCurated => Some(Value::Bool(map.curated_on_bsaber.unwrap_or(false))),
+            // older caches don't have requiredMods, and mods is the closest thing
+            Field::Vanilla => Some(Value::Bool(map.required_mods.unwrap_or(map.mods) == 0)),
+            Field::Mod(flag) => Some(Value::Bool(ModFlags::from(map.mods).contains(*flag))),
+            Field::Tag(tag) => Some(Value::Bool(
+                map.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(f64),
+    /// Lowercased, literals included, so compares ignore case.
+    Text(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Literal(Value),
+    /// Keeps the name it was written as, for errors.
+    Field(String, Field),
+}
+
+impl Operand {
+    fn value_type(&self) -> Type {
+        match self {
+            Operand::Literal(Value::Number(_)) => Type::Number,
+            Operand::Literal(Value::Text(_)) => Type::Text,
+            Operand::Literal(Value::Bool(_)) => Type::Bool,
+            Operand::Field(_, field) => field.value_type(),
+        }
+    }
+
+    fn value(&self, key: &str, map: &MapMetadata) -> Option<Value> {
+        match self {
+            Operand::Literal(value) => Some(value.clone()),
+            Operand::Field(_, field) => field.value(key, map),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Literal(Value::Number(number)) => write!(f, "{}", number),
+            Operand::Literal(Value::Text(text)) => write!(f, "{:?}", text),
+            Operand::Literal(Value::Bool(value)) => write!(f, "{}", value),
+            Operand::Field(name, _) => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    /// A true/false field on its own, like `mods.chroma`.
+    Test(Operand),
+}
+
+impl Expr {
+    fn eval(&self, key: &str, map: &MapMetadata) -> bool {
+        match self {
+            Expr::Or(left, right) => left.eval(key, map) || right.eval(key, map),
+            Expr::And(left, right) => left.eval(key, map) && right.eval(key, map),
+            Expr::Not(expr) => !expr.eval(key, map),
+            Expr::Test(operand) => operand.value(key, map) == Some(Value::Bool(true)),
+            Expr::Compare(left, op, right) => {
+                let (Some(left), Some(right)) = (left.value(key, map), right.value(key, map))
+                else {
+                    return false;
+                };
+
+                match (left, right) {
+                    (Value::Number(left), Value::Number(right)) => match op {
+                        Op::Eq => left == right,
+                        Op::Ne => left != right,
+                        Op::Lt => left < right,
+                        Op::Le => left <= right,
+                        Op::Gt => left > right,
+                        Op::Ge => left >= right,
+                        Op::Contains => false,
+                    },
+                    (Value::Text(left), Value::Text(right)) => match op {
+                        Op::Eq => left == right,
+                        Op::Ne => left != right,
+                        Op::Contains => left.contains(&right),
+                        _ => false,
+                    },
+                    (left, right) => match op {
+                        Op::Eq => left == right,
+                        Op::Ne => left != right,
+                        _ => false,
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Ident(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let (token, len) = match (c, next) {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('~', _) => (Token::Op(Op::Contains), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('"' | '\'', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&d| d == c)
+                    .ok_or_else(|| format!("unterminated string at {}", i))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+
+                (Token::Text(text.to_lowercase()), end + 2)
+            }
+            _ if c.is_ascii_digit() || c == '.' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|d| d.is_ascii_digit() || **d == '.')
+                    .count();
+                let text: String = chars[i..i + len].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("{} isn't a number", text))?;
+
+                (Token::Number(number), len)
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|d| d.is_ascii_alphanumeric() || **d == '_' || **d == '.')
+                    .count();
+
+                (Token::Ident(chars[i..i + len].iter().collect()), len)
+            }
+            _ => return Err(format!("unexpected {:?} at {}", c, i)),
+        };
+
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            let expr = self.or()?;
+
+            return match self.next() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err("missing )".to_string()),
+            };
+        }
+
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+
+        let Some(Token::Op(op)) = self.peek().cloned() else {
+            if left.value_type() != Type::Bool {
+                return Err(format!(
+                    "{} is {}, it needs comparing to something",
+                    left,
+                    left.value_type()
+                ));
+            }
+
+            return Ok(Expr::Test(left));
+        };
+
+        self.pos += 1;
+        let right = self.operand()?;
+        let (left_type, right_type) = (left.value_type(), right.value_type());
+
+        if left_type != right_type {
+            return Err(format!("can't compare {} with {}", left_type, right_type));
+        }
+
+        match (op, left_type) {
+            (Op::Lt | Op::Le | Op::Gt | Op::Ge, Type::Text | Type::Bool) => Err(format!(
+                "only numbers can be compared with < or >, not {}",
+                left_type
+            )),
+            (Op::Contains, Type::Number | Type::Bool) => {
+                Err(format!("~ only works on text, not {}", left_type))
+            }
+            _ => Ok(Expr::Compare(left, op, right)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Operand::Literal(Value::Number(number))),
+            Some(Token::Text(text)) => Ok(Operand::Literal(Value::Text(text))),
+            Some(Token::Ident(ident)) if ident == "true" || ident == "false" => {
+                Ok(Operand::Literal(Value::Bool(ident == "true")))
+            }
+            Some(Token::Ident(ident)) => match Field::parse(&ident) {
+                Some(field) => Ok(Operand::Field(ident, field)),
+                None => Err(format!("unknown field {}", ident)),
+            },
+            Some(token) => Err(format!("expected a value, got {:?}", token)),
+            None => Err("ends too early".to_string()),
+        }
+    }
+}
+
+/// A parsed `--filter`, checked for unknown fields and mismatched types up front so a typo fails
+/// before anything gets read.
+#[derive(Clone, Debug)]
+pub struct FilterExpr(Expr);
+
+impl FilterExpr {
+    pub fn matches(&self, key: &str, map: &MapMetadata) -> bool {
+        self.0.eval(key, map)
+    }
+}
+
+/// Parses a filter expression, see the grammar at the top.
+pub fn parse_filter(input: &str) -> Result<FilterExpr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.or()?;
+
+    match parser.peek() {
+        None => Ok(FilterExpr(expr)),
+        Some(token) => Err(format!("unexpected {:?} after the end", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapdata::Votes;
+
+    fn map() -> MapMetadata {
+        MapMetadata {
+            song_name: Some("Ghost Rush".to_string()),
+            duration: 142,
+            votes: Votes { up: 12, down: 3 },
+            tags: vec!["tech".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn matches(input: &str) -> bool {
+        parse_filter(input).unwrap().matches("1a2b", &map())
+    }
+
+    #[test]
+    fn matches_maps() {
+        assert!(matches("duration < 240 && upvotes >= 10"));
+        assert!(matches("song_name ~ \"ghost\""));
+        assert!(matches("song_name == 'GHOST RUSH'"));
+        assert!(matches("key == '1A2B'"));
+        assert!(matches("!(duration < 60) || tags.tech"));
+        assert!(matches("(downvotes > 5 || upvotes > 5) && !tags.dance"));
+        assert!(!matches("duration >= 142.5"));
+    }
+
+    #[test]
+    fn fields_a_map_doesnt_have_never_match() {
+        assert!(!matches("bpm > 100"));
+        assert!(!matches("bpm <= 100"));
+    }
+
+    #[test]
+    fn rejects_bad_input_without_panicking() {
+        for input in [
+            "",
+            "'",
+            "\"",
+            "song_name == \"ghost",
+            "(",
+            "(duration < 240",
+            "duration < 240 )",
+            "duration <",
+            "duration",
+            "song_name < 3",
+            "song_name < 'a'",
+            "duration ~ 3",
+            "nope == 1",
+            "mods.nope",
+            "1.2.3 > 1",
+            "duration < 240 #",
+            "&&",
+        ] {
+            assert!(parse_filter(input).is_err(), "{:?} parsed", input);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+// leaving fields out of the cache for clients that don't need them
+
+use crate::{
+    config::FieldConfig,
+    mapdata::{MapMetadata, Ranked, Votes},
+};
+
+/// Resets every field `fields` turns off to its protobuf default.
+pub fn select_fields(map: &mut MapMetadata, fields: &FieldConfig) {
+    if !fields.song_sub_name {
+        map.song_sub_name = None;
+    }
+
+    if !fields.song_author_name {
+        map.song_author_name = None;
+    }
+
+    if !fields.level_author_name {
+        map.level_author_name = None;
+    }
+
+    if !fields.curator {
+        map.curator_name = None;
+        map.curated_on_bsaber = None;
+        map.bsaber_review = None;
+    }
+
+    if !fields.uploader {
+        map.uploader_id = None;
+        map.uploader_name = None;
+        map.collaborators.clear();
+    }
+
+    if !fields.votes {
+        map.votes = Votes::default();
+        map.plays = None;
+        map.downloads = None;
+        map.score = None;
+    }
+
+    if !fields.tags {
+        map.tags.clear();
+    }
+
+    if !fields.urls {
+        map.cover_url = None;
+        map.preview_url = None;
+        map.download_url = None;
+    }
+
+    if !fields.versions {
+        map.versions.clear();
+    }
+
+    if !fields.characteristics {
+        map.characteristics.clear();
+    }
+
+    if !fields.difficulties {
+        map.difficulties.clear();
+        return;
+    }
+
+    for diff in &mut map.difficulties {
+        if !fields.ranked {
+            diff.ranked = Ranked::default();
+            diff.hitbloq_pools.clear();
+            diff.accsaber_categories.clear();
+        }
+
+        if !fields.environments {
+            diff.environment_name.clear();
+        }
+    }
+}
@@ -0,0 +1,602 @@
+// deciding which maps make it into the cache, put together from the config
+
+use std::{collections::HashSet, fs};
+
+use anyhow::Context;
+use beatsaver_api::models::{
+    enums::{AIDeclarationType, MapState},
+    map::{Map, MapDifficulty},
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{cacher::current_version, config::FilterConfig};
+
+/// Why a map was left out of the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    Unpublished,
+    VersionUnpublished,
+    AiGenerated,
+    Automapped,
+    /// No difficulty has ScoreSaber or BeatLeader stars.
+    Unranked,
+    LowScore,
+    TooFewUpvotes,
+    /// None of the tags in `include_tags`.
+    MissingTags,
+    /// One of the tags in `exclude_tags`.
+    ExcludedTag,
+    /// Uploaded by someone on the mapper blocklist.
+    BlockedMapper,
+    /// Uploaded by someone who isn't on the mapper allowlist.
+    MapperNotAllowed,
+    NotCurated,
+    /// The uploader isn't a verified mapper.
+    UnverifiedMapper,
+    /// Every difficulty was excluded by characteristic or name.
+    NoWantedDifficulties,
+    /// Every difficulty needs a mod from `exclude_mods`.
+    NeedsExcludedMod,
+    /// The song is shorter or longer than allowed.
+    DurationOutOfRange,
+    /// No difficulty has a notes-per-second within the allowed range.
+    NpsOutOfRange,
+    /// Missing data we can't do without.
+    Incomplete,
+    /// The map ID isn't hex, or doesn't fit in a u32.
+    BadKey,
+    /// BeatSaver sent something the models can't read.
+    Unparseable,
+}
+
+impl SkipReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::Unpublished => "unpublished",
+            SkipReason::VersionUnpublished => "version_unpublished",
+            SkipReason::AiGenerated => "ai_generated",
+            SkipReason::Automapped => "automapped",
+            SkipReason::Unranked => "unranked",
+            SkipReason::LowScore => "low_score",
+            SkipReason::TooFewUpvotes => "too_few_upvotes",
+            SkipReason::MissingTags => "missing_tags",
+            SkipReason::ExcludedTag => "excluded_tag",
+            SkipReason::BlockedMapper => "blocked_mapper",
+            SkipReason::MapperNotAllowed => "mapper_not_allowed",
+            SkipReason::NotCurated => "not_curated",
+            SkipReason::UnverifiedMapper => "unverified_mapper",
+            SkipReason::NoWantedDifficulties => "no_wanted_difficulties",
+            SkipReason::NeedsExcludedMod => "needs_excluded_mod",
+            SkipReason::DurationOutOfRange => "duration_out_of_range",
+            SkipReason::NpsOutOfRange => "nps_out_of_range",
+            SkipReason::Incomplete => "incomplete",
+            SkipReason::BadKey => "bad_key",
+            SkipReason::Unparseable => "unparseable",
+        }
+    }
+
+    /// Whether the map itself is broken, rather than just not wanted.
+    pub fn is_malformed(self) -> bool {
+        matches!(
+            self,
+            SkipReason::Incomplete | SkipReason::BadKey | SkipReason::Unparseable
+        )
+    }
+}
+
+/// The difficulties of the version that would get cached, none if there's no version at all.
+fn current_diffs(map: &Map) -> &[MapDifficulty] {
+    current_version(map).map_or(&[], |version| &version.diffs)
+}
+
+/// One rule a map has to pass to be cached.
+pub trait MapFilter: Send + Sync {
+    /// Why `map` should be left out, or `None` if it can stay.
+    fn check(&self, map: &Map) -> Option<SkipReason>;
+}
+
+/// Leaves out maps that aren't (or whose latest version isn't) published.
+struct PublishedOnly;
+
+impl MapFilter for PublishedOnly {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        // not published yet
+        if map.last_published_at.is_none() {
+            info!("{} hasn't been published before, ignoring", map.id);
+            return Some(SkipReason::Unpublished);
+        }
+
+        // no version of the map is published (anymore)
+        if !current_version(map).is_some_and(|version| version.state == MapState::Published) {
+            info!("No version of {} is published, ignoring", map.id);
+            return Some(SkipReason::VersionUnpublished);
+        }
+
+        None
+    }
+}
+
+/// Who declared a map AI-generated, as named in the config. BeatSaver's own names, lowercase
+/// works too.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum AiDeclaration {
+    #[serde(alias = "admin")]
+    Admin,
+    #[serde(alias = "uploader")]
+    Uploader,
+    #[serde(alias = "sagescore")]
+    SageScore,
+}
+
+impl AiDeclaration {
+    /// The declaration on a map, `None` if it wasn't declared AI-generated at all.
+    fn of(map: &Map) -> Option<Self> {
+        match map.declared_ai {
+            AIDeclarationType::Admin => Some(AiDeclaration::Admin),
+            AIDeclarationType::Uploader => Some(AiDeclaration::Uploader),
+            AIDeclarationType::SageScore => Some(AiDeclaration::SageScore),
+            AIDeclarationType::None => None,
+        }
+    }
+}
+
+/// Leaves out maps where the map or the song was declared AI-generated, except for the
+/// declarations in `allowed`.
+struct AiPolicy {
+    allowed: Vec<AiDeclaration>,
+}
+
+impl MapFilter for AiPolicy {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let declaration = AiDeclaration::of(map)?;
+
+        if self.allowed.contains(&declaration) {
+            return None;
+        }
+
+        info!("{} has been declared as AI-generated, ignoring", map.id);
+        Some(SkipReason::AiGenerated)
+    }
+}
+
+struct NoAutomapped;
+
+impl MapFilter for NoAutomapped {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if map.automapper {
+            info!("{} is automapped, ignoring", map.id);
+            return Some(SkipReason::Automapped);
+        }
+
+        None
+    }
+}
+
+/// Only keeps maps ranked on ScoreSaber or BeatLeader, in at least one difficulty.
+struct RankedOnly;
+
+impl MapFilter for RankedOnly {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let ranked = current_diffs(map)
+            .iter()
+            .any(|diff| diff.ss_stars.is_some() || diff.bl_stars.is_some());
+
+        if !ranked {
+            return Some(SkipReason::Unranked);
+        }
+
+        None
+    }
+}
+
+/// Leaves out maps whose BeatSaver rating (0 to 1) is below the threshold.
+struct MinScore(f64);
+
+impl MapFilter for MinScore {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if (map.stats.score as f64) < self.0 {
+            return Some(SkipReason::LowScore);
+        }
+
+        None
+    }
+}
+
+struct MinUpvotes(i32);
+
+impl MapFilter for MinUpvotes {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if map.stats.upvotes < self.0 {
+            return Some(SkipReason::TooFewUpvotes);
+        }
+
+        None
+    }
+}
+
+/// Keeps maps with at least one of `include`, and none of `exclude`. Tags are compared
+/// case-insensitively.
+struct Tags {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl MapFilter for Tags {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let has = |wanted: &String| map.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted));
+
+        if !self.include.is_empty() && !self.include.iter().any(has) {
+            return Some(SkipReason::MissingTags);
+        }
+
+        if self.exclude.iter().any(has) {
+            return Some(SkipReason::ExcludedTag);
+        }
+
+        None
+    }
+}
+
+/// Uploaders from a mapper list file, by BeatSaver user ID or (case-insensitive) name.
+struct MapperList {
+    ids: HashSet<i32>,
+    names: HashSet<String>,
+}
+
+impl MapperList {
+    /// One ID or name per line. Blank lines and `#` comments are skipped.
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read mapper list {}", path))?;
+
+        let mut list = MapperList {
+            ids: HashSet::new(),
+            names: HashSet::new(),
+        };
+
+        for line in contents.lines() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+
+            if entry.is_empty() {
+                continue;
+            }
+
+            match entry.parse() {
+                Ok(id) => list.ids.insert(id),
+                Err(_) => list.names.insert(entry.to_lowercase()),
+            };
+        }
+
+        Ok(list)
+    }
+
+    fn contains(&self, map: &Map) -> bool {
+        self.ids.contains(&map.uploader.id)
+            || self.names.contains(&map.uploader.name.to_lowercase())
+    }
+}
+
+struct MapperBlocklist(MapperList);
+
+impl MapFilter for MapperBlocklist {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if self.0.contains(map) {
+            info!(
+                "{} is by blocked mapper {}, ignoring",
+                map.id, map.uploader.name
+            );
+            return Some(SkipReason::BlockedMapper);
+        }
+
+        None
+    }
+}
+
+struct MapperAllowlist(MapperList);
+
+impl MapFilter for MapperAllowlist {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if !self.0.contains(map) {
+            return Some(SkipReason::MapperNotAllowed);
+        }
+
+        None
+    }
+}
+
+struct CuratedOnly;
+
+impl MapFilter for CuratedOnly {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if map.curator.is_none() {
+            return Some(SkipReason::NotCurated);
+        }
+
+        None
+    }
+}
+
+struct VerifiedMappersOnly;
+
+impl MapFilter for VerifiedMappersOnly {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if !map.uploader.verified_mapper {
+            return Some(SkipReason::UnverifiedMapper);
+        }
+
+        None
+    }
+}
+
+/// Which difficulties get cached. Unlike the filters, these trim maps instead of dropping them.
+#[derive(Clone, Default)]
+struct DifficultyRules {
+    /// Lowercase characteristic names.
+    characteristics: Vec<String>,
+    /// Lowercase difficulty names, either alone or as `characteristic/difficulty`.
+    difficulties: Vec<String>,
+}
+
+impl DifficultyRules {
+    fn is_empty(&self) -> bool {
+        self.characteristics.is_empty() && self.difficulties.is_empty()
+    }
+
+    fn keeps(&self, characteristic: &str, difficulty: &str) -> bool {
+        let characteristic = characteristic.to_lowercase();
+        let difficulty = difficulty.to_lowercase();
+        let qualified = format!("{}/{}", characteristic, difficulty);
+
+        !self.characteristics.contains(&characteristic)
+            && !self.difficulties.contains(&difficulty)
+            && !self.difficulties.contains(&qualified)
+    }
+}
+
+/// Leaves out maps that wouldn't have any difficulties left once they're trimmed.
+struct HasWantedDifficulties(DifficultyRules);
+
+impl MapFilter for HasWantedDifficulties {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let wanted = current_diffs(map)
+            .iter()
+            .any(|diff| self.0.keeps(diff.characteristic.name(), &diff.difficulty));
+
+        if !wanted {
+            return Some(SkipReason::NoWantedDifficulties);
+        }
+
+        None
+    }
+}
+
+/// A mod a map can use, as named in the config.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mod {
+    Cinema,
+    MappingExtensions,
+    Chroma,
+    NoodleExtensions,
+    Vivify,
+}
+
+impl Mod {
+    fn in_difficulty(self, diff: &MapDifficulty) -> bool {
+        match self {
+            Mod::Cinema => diff.cinema,
+            Mod::MappingExtensions => diff.me,
+            Mod::Chroma => diff.chroma,
+            Mod::NoodleExtensions => diff.ne,
+            Mod::Vivify => diff.vivify,
+        }
+    }
+}
+
+/// Leaves out maps that can't be played without one of these mods, i.e. every difficulty
+/// needs at least one of them.
+struct NoExcludedMods(Vec<Mod>);
+
+impl MapFilter for NoExcludedMods {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let diffs = current_diffs(map);
+
+        // most maps don't use any of them at all
+        let uses_excluded = diffs
+            .iter()
+            .any(|diff| self.0.iter().any(|excluded| excluded.in_difficulty(diff)));
+        if !uses_excluded {
+            return None;
+        }
+
+        let playable = diffs
+            .iter()
+            .any(|diff| !self.0.iter().any(|excluded| excluded.in_difficulty(diff)));
+
+        if !playable {
+            info!("{} needs a mod we don't want, ignoring", map.id);
+            return Some(SkipReason::NeedsExcludedMod);
+        }
+
+        None
+    }
+}
+
+/// Leaves out songs shorter than `min` or longer than `max` seconds.
+struct DurationRange {
+    min: Option<u32>,
+    max: Option<u32>,
+}
+
+impl MapFilter for DurationRange {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        let duration = u32::try_from(map.metadata.duration).unwrap_or(0);
+
+        if self.min.is_some_and(|min| duration < min) || self.max.is_some_and(|max| duration > max)
+        {
+            return Some(SkipReason::DurationOutOfRange);
+        }
+
+        None
+    }
+}
+
+/// Keeps maps with at least one difficulty whose notes per second are within range.
+struct NpsRange {
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl NpsRange {
+    fn contains(&self, diff: &MapDifficulty) -> bool {
+        // a difficulty without a length can't be judged, so it gets the benefit of the doubt
+        if diff.seconds <= 0.0 {
+            return true;
+        }
+
+        let nps = diff.notes as f64 / diff.seconds;
+
+        self.min.is_none_or(|min| nps >= min) && self.max.is_none_or(|max| nps <= max)
+    }
+}
+
+impl MapFilter for NpsRange {
+    fn check(&self, map: &Map) -> Option<SkipReason> {
+        if !current_diffs(map).iter().any(|diff| self.contains(diff)) {
+            return Some(SkipReason::NpsOutOfRange);
+        }
+
+        None
+    }
+}
+
+/// Every filter a map goes through, in order. The first one to say no wins.
+pub struct FilterPipeline {
+    filters: Vec<Box<dyn MapFilter>>,
+    difficulty_rules: DifficultyRules,
+}
+
+impl FilterPipeline {
+    /// Keeps every map whole, for when they were picked by hand.
+    pub fn none() -> Self {
+        FilterPipeline {
+            filters: Vec::new(),
+            difficulty_rules: DifficultyRules::default(),
+        }
+    }
+
+    /// Builds the pipeline, reading any mapper lists the config points at.
+    pub fn from_config(config: &FilterConfig) -> anyhow::Result<Self> {
+        let mut pipeline = FilterPipeline {
+            filters: Vec::new(),
+            difficulty_rules: DifficultyRules {
+                characteristics: lowercase(&config.exclude_characteristics),
+                difficulties: lowercase(&config.exclude_difficulties),
+            },
+        };
+
+        if config.published_only {
+            pipeline.push(PublishedOnly);
+        }
+
+        if config.exclude_ai {
+            pipeline.push(AiPolicy {
+                allowed: config.allow_ai.clone(),
+            });
+        }
+
+        if config.exclude_automapped {
+            pipeline.push(NoAutomapped);
+        }
+
+        if config.ranked_only {
+            pipeline.push(RankedOnly);
+        }
+
+        if config.curated_only {
+            pipeline.push(CuratedOnly);
+        }
+
+        if config.verified_mappers_only {
+            pipeline.push(VerifiedMappersOnly);
+        }
+
+        if let Some(min_score) = config.min_score {
+            pipeline.push(MinScore(min_score));
+        }
+
+        if let Some(min_upvotes) = config.min_upvotes {
+            pipeline.push(MinUpvotes(i32::try_from(min_upvotes).unwrap_or(i32::MAX)));
+        }
+
+        if !config.include_tags.is_empty() || !config.exclude_tags.is_empty() {
+            pipeline.push(Tags {
+                include: config.include_tags.clone(),
+                exclude: config.exclude_tags.clone(),
+            });
+        }
+
+        if config.min_duration.is_some() || config.max_duration.is_some() {
+            pipeline.push(DurationRange {
+                min: config.min_duration,
+                max: config.max_duration,
+            });
+        }
+
+        if config.min_nps.is_some() || config.max_nps.is_some() {
+            pipeline.push(NpsRange {
+                min: config.min_nps,
+                max: config.max_nps,
+            });
+        }
+
+        if !config.exclude_mods.is_empty() {
+            pipeline.push(NoExcludedMods(config.exclude_mods.clone()));
+        }
+
+        if !pipeline.difficulty_rules.is_empty() {
+            pipeline.push(HasWantedDifficulties(pipeline.difficulty_rules.clone()));
+        }
+
+        if let Some(path) = &config.mapper_blocklist {
+            pipeline.push(MapperBlocklist(MapperList::load(path)?));
+        }
+
+        if let Some(path) = &config.mapper_allowlist {
+            pipeline.push(MapperAllowlist(MapperList::load(path)?));
+        }
+
+        Ok(pipeline)
+    }
+
+    pub fn push(&mut self, filter: impl MapFilter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Why `map` should be left out, if any filter thinks it should.
+    pub fn check(&self, map: &Map) -> Option<SkipReason> {
+        self.filters.iter().find_map(|filter| filter.check(map))
+    }
+
+    /// Whether any difficulties get trimmed at all.
+    pub fn trims_difficulties(&self) -> bool {
+        !self.difficulty_rules.is_empty()
+    }
+
+    /// Whether a difficulty survives trimming.
+    pub fn keeps_difficulty(&self, characteristic: &str, difficulty: &str) -> bool {
+        self.difficulty_rules.keeps(characteristic, difficulty)
+    }
+}
+
+fn lowercase(names: &[String]) -> Vec<String> {
+    names.iter().map(|name| name.to_lowercase()).collect()
+}
+
+impl Default for FilterPipeline {
+    fn default() -> Self {
+        FilterPipeline::from_config(&FilterConfig::default())
+            .expect("the default filters don't read any files")
+    }
+}
@@ -0,0 +1,282 @@
+// fake caches for load testing, so nobody has to scrape BeatSaver just to get a big file
+
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+use crate::{
+    cacher::protogen::generate_protobuf_characteristics,
+    mapdata::{
+        Difficulty, MapList, MapMetadata, Mapper, ParitySummary, Ranked, RankedState, RankedValue,
+        Version, Votes,
+    },
+};
+
+const WORDS: &[&str] = &[
+    "Night", "Star", "Crystal", "Echo", "Neon", "Heart", "Fire", "Dream", "Storm", "Light",
+    "Ghost", "Rain", "Electric", "Moon", "Shadow", "Paradise", "Gravity", "Rush", "Sky", "Zero",
+];
+const ARTISTS: &[&str] = &[
+    "Camellia",
+    "Kobaryo",
+    "Laur",
+    "Tokyo Machine",
+    "Muse",
+    "Panic! At The Disco",
+    "xi",
+    "Nekomata Master",
+    "Virtual Riot",
+    "Porter Robinson",
+];
+const MAPPERS: &[&str] = &[
+    "Joetastic",
+    "Fatbeanzoop",
+    "Teuflum",
+    "Skyler Wallace",
+    "Hexagonial",
+    "Timbo",
+    "Nolan121405",
+    "Rustic",
+    "cerret",
+    "Puds",
+];
+const CURATORS: &[&str] = &["Ruckus", "Sonic", "Electrostats", "Jabob"];
+const CHARACTERISTICS: &[&str] = &["Standard", "OneSaber", "NoArrows", "Lawless", "360Degree"];
+const DIFFICULTIES: &[&str] = &["Easy", "Normal", "Hard", "Expert", "ExpertPlus"];
+const LABELS: &[&str] = &["Tech", "Poodle", "Dance", "Speed", "Lightshow"];
+const ENVIRONMENTS: &[&str] = &[
+    "DefaultEnvironment",
+    "BigMirrorEnvironment",
+    "TimbalandEnvironment",
+    "BillieEnvironment",
+    "WeaveEnvironment",
+];
+
+const TAGS: &[&str] = &[
+    "electronic",
+    "dance",
+    "rock",
+    "anime",
+    "pop",
+    "tech",
+    "speed",
+    "accuracy",
+    "balanced",
+    "challenge",
+    "meme",
+];
+
+/// Fake account IDs are this plus the mapper's index in `MAPPERS`.
+const FIRST_MAPPER_ID: u32 = 4000;
+
+/// `2018-05-08`, about when the first maps showed up.
+const FIRST_UPLOAD: i64 = 1525737600;
+/// Roughly how many seconds pass between uploads on the real site.
+const UPLOAD_SPACING: i64 = 400;
+
+fn song_title(rng: &mut StdRng) -> String {
+    let words = rng.random_range(1..=3);
+
+    (0..words)
+        .map(|_| *WORDS.choose(rng).unwrap())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn ranked_value(rng: &mut StdRng, chance: f64) -> RankedValue {
+    if rng.random_bool(chance) {
+        RankedValue {
+            is_ranked: true,
+            stars: rng.random_range(1.0..14.0),
+            state: Some(RankedState::Ranked as i32),
+            ..Default::default()
+        }
+    } else {
+        RankedValue {
+            is_ranked: false,
+            stars: 0.0,
+            state: Some(RankedState::Unranked as i32),
+            ..Default::default()
+        }
+    }
+}
+
+fn difficulties(rng: &mut StdRng, duration: u32, mods: u32, required_mods: u32) -> Vec<Difficulty> {
+    let mut diffs = Vec::new();
+
+    // almost everything is Standard, some maps throw in another characteristic or two
+    let mut characteristics = vec!["Standard"];
+    if rng.random_bool(0.15) {
+        characteristics.push(CHARACTERISTICS[rng.random_range(1..CHARACTERISTICS.len())]);
+    }
+
+    for characteristic in characteristics {
+        let first = rng.random_range(0..DIFFICULTIES.len());
+
+        for difficulty in &DIFFICULTIES[first..] {
+            let nps: f32 = rng.random_range(1.0..12.0);
+            let notes = (nps * duration as f32) as u32;
+
+            diffs.push(Difficulty {
+                njs: rng.random_range(10.0..24.0),
+                notes,
+                characteristic_name: characteristic.to_string(),
+                difficulty_name: difficulty.to_string(),
+                mods,
+                environment_name: ENVIRONMENTS.choose(rng).unwrap().to_string(),
+                ranked: Ranked {
+                    score_saber: ranked_value(rng, 0.03),
+                    beat_leader: ranked_value(rng, 0.05),
+                },
+                seconds: Some(duration as f32),
+                nps: Some(nps),
+                bombs: Some(rng.random_range(0..200)),
+                obstacles: Some(rng.random_range(0..300)),
+                events: Some(rng.random_range(0..5000)),
+                // what a full combo of `notes` is worth, multiplier ramp included
+                max_score: Some(115 * (8 * notes).saturating_sub(63)),
+                parity: Some(ParitySummary {
+                    errors: rng.random_range(0..10),
+                    warns: rng.random_range(0..30),
+                    resets: rng.random_range(0..5),
+                }),
+                label: rng
+                    .random_bool(0.1)
+                    .then(|| LABELS.choose(rng).unwrap().to_string()),
+                required_mods: Some(required_mods),
+                suggested_mods: Some(mods & !required_mods),
+                hitbloq_pools: Vec::new(),
+                accsaber_categories: Vec::new(),
+                other_requirements: Vec::new(),
+                characteristic_index: None,
+                difficulty_index: None,
+                environment_index: None,
+            });
+        }
+    }
+
+    diffs
+}
+
+fn fake_map(rng: &mut StdRng, key: u32) -> MapMetadata {
+    let duration = rng.random_range(60..600);
+    let uploaded = FIRST_UPLOAD + key as i64 * UPLOAD_SPACING + rng.random_range(0..UPLOAD_SPACING);
+    let last_updated = uploaded + rng.random_range(0..86400 * 30);
+    // most maps go up the day they're made, some sit around unpublished or get republished
+    let created = if rng.random_bool(0.1) {
+        uploaded - rng.random_range(0..86400 * 365)
+    } else {
+        uploaded
+    };
+
+    // each mod bit is rare on its own
+    let mods = (0..5)
+        .filter(|_| rng.random_bool(0.05))
+        .fold(0, |mods, bit| mods | (1 << bit));
+
+    // and about half of the ones a map uses it can't do without
+    let required_mods = mods & rng.random_range(0..32);
+    let difficulties = difficulties(rng, duration, mods, required_mods);
+    let tag_count = rng.random_range(0..=3);
+    let hash: String = (0..40)
+        .map(|_| char::from_digit(rng.random_range(0..16), 16).unwrap())
+        .collect();
+    let uploader = rng.random_range(0..MAPPERS.len());
+    let collaborators: Vec<Mapper> = if rng.random_bool(0.1) {
+        let collaborator = rng.random_range(0..MAPPERS.len());
+
+        vec![Mapper {
+            id: FIRST_MAPPER_ID + collaborator as u32,
+            name: MAPPERS[collaborator].to_string(),
+        }]
+    } else {
+        Vec::new()
+    };
+
+    MapMetadata {
+        key,
+        hash: hash.clone(),
+        song_name: Some(song_title(rng)),
+        song_sub_name: rng.random_bool(0.2).then(|| song_title(rng)),
+        song_author_name: Some(ARTISTS.choose(rng).unwrap().to_string()),
+        level_author_name: Some(MAPPERS[uploader].to_string()),
+        duration,
+        uploaded,
+        last_updated,
+        created: Some(created),
+        mods,
+        curator_name: rng
+            .random_bool(0.1)
+            .then(|| CURATORS.choose(rng).unwrap().to_string()),
+        votes: Votes {
+            up: rng.random_range(0..2000),
+            down: rng.random_range(0..200),
+        },
+        // BeatSaver doesn't really count plays, they're 0 on the real site too
+        plays: Some(0),
+        downloads: Some(rng.random_range(0..50000)),
+        score: Some(rng.random_range(0.4..0.98)),
+        characteristics: generate_protobuf_characteristics(&difficulties),
+        difficulties,
+        recently_played: None,
+        tags: TAGS
+            .choose_multiple(rng, tag_count)
+            .map(|tag| tag.to_string())
+            .collect(),
+        bpm: Some(rng.random_range(80.0..220.0)),
+        uploader_id: Some(FIRST_MAPPER_ID + uploader as u32),
+        uploader_name: Some(MAPPERS[uploader].to_string()),
+        collaborators,
+        required_mods: Some(required_mods),
+        suggested_mods: Some(mods & !required_mods),
+        cover_url: Some(format!("https://cdn.beatsaver.com/{}.jpg", hash)),
+        preview_url: Some(format!("https://cdn.beatsaver.com/{}.mp3", hash)),
+        download_url: Some(format!("https://r2cdn.beatsaver.com/{}.zip", hash)),
+        versions: vec![Version {
+            hash,
+            state: "Published".to_string(),
+            created: uploaded,
+        }],
+        curated_on_bsaber: None,
+        bsaber_review: None,
+        other_requirements: Vec::new(),
+    }
+}
+
+/// Makes a cache of `count` random but plausible maps. The same seed always gives the same cache.
+pub fn generate_fixture(count: u32, seed: u64) -> MapList {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map_list = MapList::default();
+
+    for key in 1..=count {
+        map_list
+            .map_metadata
+            .insert(format!("{:x}", key), fake_map(&mut rng, key));
+    }
+
+    map_list
+}
+
+/// `map_list` with one map changed, one gone and one new, for tests that need a next version.
+#[cfg(test)]
+pub(crate) fn change_fixture(map_list: &MapList) -> MapList {
+    let mut changed = map_list.clone();
+    let mut keys = map_list.map_metadata.keys();
+    let (first, second) = (keys.next().unwrap(), keys.next().unwrap());
+
+    changed.map_metadata.get_mut(first).unwrap().votes.up += 1;
+    changed.map_metadata.remove(second);
+
+    let mut new = map_list.map_metadata[first].clone();
+    new.key = 0xfffff;
+    changed.map_metadata.insert("fffff".to_string(), new);
+
+    changed
+}
+
+/// A path in the temp directory that no other test (or test run) writes to.
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("drm-cacher-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .to_string()
+}
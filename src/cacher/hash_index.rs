@@ -0,0 +1,127 @@
+// request managers look maps up by hash, but the cache is keyed by BeatSaver key
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    cacher::{error::CacherError, usage::record_written},
+    mapdata::{MapList, MapMetadata},
+};
+
+// layout (all little-endian):
+//   magic    [u8; 4]  "DRMH"
+//   version  u16
+//   count    u32
+//   entries  count × (hash [u8; 20], key u32), sorted by hash so readers can binary search it
+//            without loading the whole thing
+const MAGIC: &[u8; 4] = b"DRMH";
+const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 4 + 2 + 4;
+const ENTRY_LEN: usize = 20 + 4;
+
+/// Every hash a map has had, current and older versions, to its key.
+pub struct HashIndex {
+    keys: HashMap<String, String>,
+}
+
+impl HashIndex {
+    pub fn build(map_list: &MapList) -> Self {
+        let mut keys = HashMap::new();
+
+        for (key, map) in &map_list.map_metadata {
+            for version in &map.versions {
+                keys.insert(version.hash.to_lowercase(), key.clone());
+            }
+
+            // the current hash wins if an old version somehow shares it with another map
+            keys.insert(map.hash.to_lowercase(), key.clone());
+        }
+
+        HashIndex { keys }
+    }
+
+    pub fn key(&self, hash: &str) -> Option<&str> {
+        self.keys.get(&hash.to_lowercase()).map(String::as_str)
+    }
+
+    /// The map with this key or hash, whichever it is.
+    pub fn find<'a>(&self, map_list: &'a MapList, id: &str) -> Option<&'a MapMetadata> {
+        let id = id.to_lowercase();
+        let key = self.key(&id).unwrap_or(&id);
+
+        map_list.map_metadata.get(key)
+    }
+}
+
+/// Writes the hash index as a compact binary file next to the cache, see the layout above.
+pub fn write_hash_index(map_list: &MapList, path: &str) -> Result<(), CacherError> {
+    let mut entries: Vec<([u8; 20], u32)> = Vec::new();
+
+    for (hash, key) in HashIndex::build(map_list).keys {
+        let parsed = hex::decode(&hash)
+            .ok()
+            .and_then(|hash| hash.try_into().ok());
+        let key_number = u32::from_str_radix(&key, 16).ok();
+
+        match (parsed, key_number) {
+            (Some(hash), Some(key)) => entries.push((hash, key)),
+            _ => warn!("Leaving {} ({}) out of the hash index", hash, key),
+        }
+    }
+
+    entries.sort_unstable();
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&(entries.len() as u32).to_le_bytes())?;
+
+    for (hash, key) in &entries {
+        file.write_all(hash)?;
+        file.write_all(&key.to_le_bytes())?;
+    }
+
+    file.flush()?;
+    drop(file);
+
+    record_written(fs::metadata(path)?.len());
+    info!("Saved hash index ({} hashes) to {}", entries.len(), path);
+
+    Ok(())
+}
+
+/// Reads a hash index written by `write_hash_index` back into (hash, key) pairs, both hex like
+/// they are everywhere else.
+pub fn read_hash_index(path: &str) -> Result<Vec<(String, String)>, CacherError> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut header = [0; HEADER_LEN];
+    file.read_exact(&mut header)?;
+
+    if &header[0..4] != MAGIC || u16::from_le_bytes([header[4], header[5]]) != VERSION {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} isn't a hash index this build understands", path),
+        )
+        .into());
+    }
+
+    let count = u32::from_le_bytes(header[6..10].try_into().unwrap());
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut entry = [0; ENTRY_LEN];
+
+    for _ in 0..count {
+        file.read_exact(&mut entry)?;
+
+        let key = u32::from_le_bytes(entry[20..24].try_into().unwrap());
+        entries.push((hex::encode(&entry[..20]), format!("{:x}", key)));
+    }
+
+    Ok(entries)
+}
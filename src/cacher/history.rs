@@ -0,0 +1,144 @@
+// every hash we've ever seen, so old replays can still be matched to a map
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    cacher::error::CacherError,
+    mapdata::{MapList, MapMetadata},
+};
+
+/// One line of the history file. A later line for the same hash takes over from an earlier one,
+/// that's how a hash gets its `current_until` once a newer version shows up, so the file never
+/// has to be rewritten.
+#[derive(Serialize, Deserialize)]
+struct HashRecord {
+    hash: String,
+    key: String,
+    /// When this version was created.
+    current_from: i64,
+    /// When the next version was created, `None` while this one is still the latest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current_until: Option<i64>,
+    /// When the cacher first saw this version.
+    first_seen: i64,
+}
+
+/// Every record in the history file, skipping lines that don't parse.
+fn read_history(path: &str) -> Vec<HashRecord> {
+    let mut records = Vec::new();
+
+    if !Path::new(path).exists() {
+        return records;
+    }
+
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            for (line_number, line) in contents.lines().enumerate() {
+                match serde_json::from_str::<HashRecord>(line) {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!("Skipping bad history line {}: {}", line_number + 1, e),
+                }
+            }
+        }
+        Err(e) => error!("Couldn't read hash history {}: {:?}", path, e),
+    }
+
+    records
+}
+
+/// The latest record of each hash already in the history file.
+fn known_hashes(path: &str) -> HashMap<String, HashRecord> {
+    read_history(path)
+        .into_iter()
+        .map(|record| (record.hash.clone(), record))
+        .collect()
+}
+
+/// Every (key, hash) pair recorded in the history file.
+pub(crate) fn recorded_key_hashes(path: &str) -> HashSet<(String, String)> {
+    read_history(path)
+        .into_iter()
+        .map(|record| (record.key, record.hash))
+        .collect()
+}
+
+/// Every published version of `map` as (hash, created, next version created), oldest first.
+/// Testplays and the like were never current, so they don't get a span. Maps cached without
+/// their published versions only have the current hash to go by.
+fn version_spans(map: &MapMetadata) -> Vec<(&str, i64, Option<i64>)> {
+    let mut versions: Vec<(&str, i64)> = map
+        .versions
+        .iter()
+        .filter(|version| version.state == "Published")
+        .map(|version| (version.hash.as_str(), version.created))
+        .collect();
+
+    if versions.is_empty() {
+        versions.push((map.hash.as_str(), map.uploaded));
+    }
+
+    versions.sort_by_key(|(_, created)| *created);
+
+    versions
+        .iter()
+        .enumerate()
+        .map(|(i, (hash, created))| {
+            let until = versions.get(i + 1).map(|(_, created)| *created);
+            (*hash, *created, until)
+        })
+        .collect()
+}
+
+/// Appends every version in the map list that isn't in the history file yet, and another line
+/// for each one that's been replaced since it was recorded.
+pub fn append_hash_history(map_list: &MapList, path: &str) -> Result<(), CacherError> {
+    let known = known_hashes(path);
+    let now = chrono::Utc::now().timestamp();
+
+    let mut new_lines = String::new();
+    let (mut new_count, mut ended_count) = (0, 0);
+
+    for map in map_list.map_metadata.values() {
+        for (hash, current_from, current_until) in version_spans(map) {
+            let first_seen = match known.get(hash) {
+                None => {
+                    new_count += 1;
+                    now
+                }
+                Some(record) if record.current_until.is_none() && current_until.is_some() => {
+                    ended_count += 1;
+                    record.first_seen
+                }
+                Some(_) => continue,
+            };
+
+            let record = HashRecord {
+                hash: hash.to_string(),
+                key: format!("{:x}", map.key),
+                current_from,
+                current_until,
+                first_seen,
+            };
+
+            new_lines.push_str(&serde_json::to_string(&record).unwrap());
+            new_lines.push('\n');
+        }
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(new_lines.as_bytes())?;
+    info!(
+        "Added {} new hashes to {}, {} more stopped being current",
+        new_count, path, ended_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+// Hitbloq's map pools, so pool-based tools can read them out of the cache
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::{cacher::ratelimit::RateLimiter, config::HttpConfig, mapdata::MapList};
+
+pub const HITBLOQ_API_URL: &str = "https://hitbloq.com/api";
+
+#[derive(Deserialize)]
+struct Pool {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct RankedList {
+    leaderboard_id_list: Vec<String>,
+}
+
+/// Every pool each difficulty is in, by (lowercase hash, characteristic, difficulty).
+pub struct HitbloqPools {
+    pools: HashMap<(String, String, String), Vec<String>>,
+}
+
+/// Splits a Hitbloq leaderboard ID, e.g. `<hash>|_ExpertPlus_SoloStandard`, into the same key the
+/// cache's difficulties have.
+fn leaderboard_key(id: &str) -> Option<(String, String, String)> {
+    let (hash, difficulty) = id.split_once('|')?;
+    let (difficulty, mode) = difficulty.trim_start_matches('_').split_once('_')?;
+
+    Some((
+        hash.to_lowercase(),
+        mode.trim_start_matches("Solo").to_string(),
+        difficulty.to_string(),
+    ))
+}
+
+/// Fetches every Hitbloq pool and the leaderboards ranked in it.
+pub async fn fetch_pools(http: &HttpConfig) -> anyhow::Result<HitbloqPools> {
+    let client = http.client()?;
+    let limiter = RateLimiter::new(5.0, 1);
+    let mut pools: HashMap<_, Vec<String>> = HashMap::new();
+
+    limiter.wait().await;
+
+    let pool_list: Vec<Pool> = client
+        .get(format!("{}/map_pools_detailed", HITBLOQ_API_URL))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    for pool in &pool_list {
+        let mut page = 0;
+
+        loop {
+            limiter.wait().await;
+
+            let res: RankedList = client
+                .get(format!(
+                    "{}/ranked_list/{}/{}",
+                    HITBLOQ_API_URL, pool.id, page
+                ))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            debug!(
+                "[Hitbloq] Got {} page {} ({} leaderboards)",
+                pool.id,
+                page,
+                res.leaderboard_id_list.len()
+            );
+
+            if res.leaderboard_id_list.is_empty() {
+                break;
+            }
+
+            for key in res
+                .leaderboard_id_list
+                .iter()
+                .filter_map(|id| leaderboard_key(id))
+            {
+                pools.entry(key).or_default().push(pool.id.clone());
+            }
+
+            page += 1;
+        }
+    }
+
+    info!(
+        "[Hitbloq] Fetched {} pools, {} leaderboards",
+        pool_list.len(),
+        pools.len()
+    );
+
+    Ok(HitbloqPools { pools })
+}
+
+/// Sets every difficulty's Hitbloq pools. Returns how many difficulties are in at least one.
+pub fn apply_pools(map_list: &mut MapList, pools: &HitbloqPools) -> usize {
+    let mut pooled = 0;
+
+    for map in map_list.map_metadata.values_mut() {
+        let hash = map.hash.to_lowercase();
+
+        for diff in &mut map.difficulties {
+            let key = (
+                hash.clone(),
+                diff.characteristic_name.clone(),
+                diff.difficulty_name.clone(),
+            );
+
+            diff.hitbloq_pools = pools.pools.get(&key).cloned().unwrap_or_default();
+
+            if !diff.hitbloq_pools.is_empty() {
+                pooled += 1;
+            }
+        }
+    }
+
+    pooled
+}
@@ -0,0 +1,220 @@
+// making sure everything we're about to publish agrees with itself
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, bail};
+use tracing::info;
+
+use crate::{
+    cacher::{
+        checksum::sha256_file,
+        delta::{apply_delta, cache_version, read_delta},
+        hash_index::read_hash_index,
+        history::recorded_key_hashes,
+        read_cache,
+        shard::ShardManifest,
+    },
+    mapdata::MapList,
+};
+
+/// Everything a run wrote, so it can be checked before it goes anywhere.
+#[derive(Default)]
+pub struct Artifacts<'a> {
+    pub cache: Option<String>,
+    pub shard_dir: Option<String>,
+    pub hash_history: Option<String>,
+    pub hash_index: Option<String>,
+    /// The delta, and the map list it goes on top of.
+    pub delta: Option<(String, &'a MapList)>,
+}
+
+/// Key → hash for every map in a list.
+fn key_hashes(map_list: &MapList) -> BTreeMap<String, String> {
+    map_list
+        .map_metadata
+        .iter()
+        .map(|(key, map)| (key.clone(), map.hash.clone()))
+        .collect()
+}
+
+/// Explains the first few differences between two key → hash maps.
+fn compare(
+    what: &str,
+    expected: &BTreeMap<String, String>,
+    actual: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let missing: Vec<_> = expected
+        .keys()
+        .filter(|key| !actual.contains_key(*key))
+        .collect();
+    let extra: Vec<_> = actual
+        .keys()
+        .filter(|key| !expected.contains_key(*key))
+        .collect();
+    let changed: Vec<_> = expected
+        .iter()
+        .filter(|(key, hash)| actual.get(*key).is_some_and(|actual| actual != *hash))
+        .map(|(key, _)| key)
+        .collect();
+
+    if missing.is_empty() && extra.is_empty() && changed.is_empty() {
+        return Ok(());
+    }
+
+    bail!(
+        "{} doesn't match what was scraped: {} missing (e.g. {:?}), {} extra (e.g. {:?}), {} with a different hash (e.g. {:?})",
+        what,
+        missing.len(),
+        missing.iter().take(5).collect::<Vec<_>>(),
+        extra.len(),
+        extra.iter().take(5).collect::<Vec<_>>(),
+        changed.len(),
+        changed.iter().take(5).collect::<Vec<_>>()
+    )
+}
+
+fn verify_cache(path: &str, expected: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let written = read_cache(path).with_context(|| format!("Couldn't read back {}", path))?;
+
+    compare(path, expected, &key_hashes(&written))
+}
+
+fn verify_shards(dir: &str, expected: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let manifest_path = Path::new(dir).join("manifest.json");
+    let manifest: ShardManifest = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+
+    if manifest.total_maps != expected.len() {
+        bail!(
+            "Shard manifest says {} maps, but {} were scraped",
+            manifest.total_maps,
+            expected.len()
+        );
+    }
+
+    let mut combined = BTreeMap::new();
+
+    for shard in &manifest.shards {
+        let path = Path::new(dir)
+            .join(&shard.file)
+            .to_string_lossy()
+            .to_string();
+
+        if sha256_file(&path)? != shard.sha256 {
+            bail!("Shard {} doesn't match the hash in the manifest", path);
+        }
+
+        let written = read_cache(&path).with_context(|| format!("Couldn't read back {}", path))?;
+
+        if written.map_metadata.len() != shard.maps {
+            bail!(
+                "Shard {} has {} maps, manifest says {}",
+                path,
+                written.map_metadata.len(),
+                shard.maps
+            );
+        }
+
+        for (key, hash) in key_hashes(&written) {
+            if combined.insert(key.clone(), hash).is_some() {
+                bail!("{} shows up in more than one shard", key);
+            }
+        }
+    }
+
+    compare("Shards", expected, &combined)
+}
+
+fn verify_hash_history(path: &str, expected: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let recorded: HashSet<(String, String)> = recorded_key_hashes(path);
+
+    // keys in the cache are BeatSaver's hex ids, same as in the history
+    let missing: Vec<_> = expected
+        .iter()
+        .filter(|(key, hash)| !recorded.contains(&((*key).clone(), (*hash).clone())))
+        .map(|(key, _)| key)
+        .collect();
+
+    if !missing.is_empty() {
+        bail!(
+            "Hash history {} is missing {} current hashes (e.g. {:?})",
+            path,
+            missing.len(),
+            missing.iter().take(5).collect::<Vec<_>>()
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that the hash index has every map's current hash, pointing at its key, and nothing for
+/// maps that aren't in the cache.
+fn verify_hash_index(path: &str, expected: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let index = read_hash_index(path).with_context(|| format!("Couldn't read back {}", path))?;
+    let mut indexed = BTreeMap::new();
+
+    // older hashes are in there too, those only have to point at a map that's in the cache
+    for (hash, key) in index {
+        match expected.get(&key) {
+            Some(current) if current.eq_ignore_ascii_case(&hash) => {
+                indexed.insert(key, current.clone());
+            }
+            Some(_) => {}
+            None => {
+                indexed.insert(key, hash);
+            }
+        }
+    }
+
+    compare(path, expected, &indexed)
+}
+
+/// Checks that the delta takes `base` to exactly what was scraped.
+fn verify_delta(
+    path: &str,
+    base: &MapList,
+    map_list: &MapList,
+    expected: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let delta = read_delta(path).with_context(|| format!("Couldn't read back {}", path))?;
+
+    if delta.target_version != cache_version(map_list) {
+        bail!("Delta {} doesn't end at the version that was scraped", path);
+    }
+
+    let mut patched = base.clone();
+    apply_delta(&mut patched, delta).with_context(|| format!("Delta {} doesn't apply", path))?;
+
+    compare(path, expected, &key_hashes(&patched))
+}
+
+/// Checks every written artifact against the scraped map list, and against each other.
+pub fn verify_artifacts(map_list: &MapList, artifacts: &Artifacts) -> anyhow::Result<()> {
+    let expected = key_hashes(map_list);
+
+    if let Some(path) = &artifacts.cache {
+        verify_cache(path, &expected)?;
+    }
+
+    if let Some(dir) = &artifacts.shard_dir {
+        verify_shards(dir, &expected)?;
+    }
+
+    if let Some(path) = &artifacts.hash_history {
+        verify_hash_history(path, &expected)?;
+    }
+
+    if let Some(path) = &artifacts.hash_index {
+        verify_hash_index(path, &expected)?;
+    }
+
+    if let Some((path, base)) = &artifacts.delta {
+        verify_delta(path, base, map_list, &expected)?;
+    }
+
+    info!("[Integrity] All artifacts agree on {} maps", expected.len());
+    Ok(())
+}
@@ -0,0 +1,196 @@
+// characteristic, difficulty and environment names are the same few dozen strings over and over,
+// so caches write each one once and point at it from every difficulty
+
+use std::collections::HashMap;
+
+use prost::encoding::{fixed32, string};
+
+use crate::{
+    cacher::error::CacherError,
+    mapdata::{MapList, MapMetadata},
+};
+
+/// Field number of `MapList.strings`.
+const STRINGS_TAG: u32 = 4;
+/// Field number of `MapList.stringChecksums`.
+const STRING_CHECKSUMS_TAG: u32 = 5;
+
+/// The names written so far, in the order they went into `MapList.strings`.
+#[derive(Default)]
+pub(crate) struct StringTable {
+    indices: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn intern(&mut self, name: String, buf: &mut Vec<u8>) -> u32 {
+        if let Some(index) = self.indices.get(&name) {
+            return *index;
+        }
+
+        let index = self.indices.len() as u32;
+        string::encode(STRINGS_TAG, &name, buf);
+        // so `repair` can tell a name that's gone bad, and leave out the maps that use it
+        fixed32::encode(STRING_CHECKSUMS_TAG, &crc32fast::hash(name.as_bytes()), buf);
+        self.indices.insert(name, index);
+
+        index
+    }
+
+    /// A copy of `map` with its names swapped for indices into the table. Names the table doesn't
+    /// have yet are added and written to `buf`, so they come before the first map that needs them.
+    pub(crate) fn intern_map(&mut self, map: &MapMetadata, buf: &mut Vec<u8>) -> MapMetadata {
+        let mut map = map.clone();
+
+        for diff in &mut map.difficulties {
+            let characteristic = std::mem::take(&mut diff.characteristic_name);
+            let difficulty = std::mem::take(&mut diff.difficulty_name);
+            let environment = std::mem::take(&mut diff.environment_name);
+
+            diff.characteristic_index = Some(self.intern(characteristic, buf));
+            diff.difficulty_index = Some(self.intern(difficulty, buf));
+            diff.environment_index = Some(self.intern(environment, buf));
+        }
+
+        for summary in &mut map.characteristics {
+            let characteristic = std::mem::take(&mut summary.characteristic_name);
+            summary.characteristic_index = Some(self.intern(characteristic, buf));
+        }
+
+        map
+    }
+}
+
+/// Swaps `index` for the name it points at. `None` in `strings` is a name that didn't survive,
+/// which is as good as one that isn't there.
+fn resolve(
+    name: &mut String,
+    index: &mut Option<u32>,
+    strings: &[Option<String>],
+) -> Result<(), CacherError> {
+    let Some(index) = index.take() else {
+        return Ok(());
+    };
+
+    match strings.get(index as usize) {
+        Some(Some(resolved)) => {
+            *name = resolved.clone();
+            Ok(())
+        }
+        _ => Err(CacherError::UnresolvedName {
+            index,
+            len: strings.len(),
+        }),
+    }
+}
+
+/// Puts the names back where the indices in `map` point. Errors on the first index that doesn't
+/// point at a name, leaving `map` half done, since a map with the wrong names is worse than none.
+pub(crate) fn resolve_map(
+    map: &mut MapMetadata,
+    strings: &[Option<String>],
+) -> Result<(), CacherError> {
+    for diff in &mut map.difficulties {
+        resolve(
+            &mut diff.characteristic_name,
+            &mut diff.characteristic_index,
+            strings,
+        )?;
+        resolve(
+            &mut diff.difficulty_name,
+            &mut diff.difficulty_index,
+            strings,
+        )?;
+        resolve(
+            &mut diff.environment_name,
+            &mut diff.environment_index,
+            strings,
+        )?;
+    }
+
+    for summary in &mut map.characteristics {
+        resolve(
+            &mut summary.characteristic_name,
+            &mut summary.characteristic_index,
+            strings,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Puts every name back in a cache written with a string table, and drops the table. Nothing past
+/// `decode_cache` has to know about it.
+pub fn resolve_strings(map_list: &mut MapList) -> Result<(), CacherError> {
+    let strings: Vec<Option<String>> = std::mem::take(&mut map_list.strings)
+        .into_iter()
+        .map(Some)
+        .collect();
+
+    for map in map_list.map_metadata.values_mut() {
+        resolve_map(map, &strings)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use prost::Message;
+
+    use super::*;
+    use crate::cacher::{encode_entries_streaming, fixture::generate_fixture};
+
+    /// The payload `write_cache` would write for `map_list`, decoded without resolving anything.
+    fn encoded(map_list: &MapList, intern_names: bool) -> MapList {
+        let mut payload = Vec::new();
+        let entries = map_list.map_metadata.iter().map(Ok);
+        encode_entries_streaming(entries, intern_names, &mut payload).unwrap();
+
+        MapList::decode(payload.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn round_trips_interned_names() {
+        let map_list = generate_fixture(100, 1);
+        let mut decoded = encoded(&map_list, true);
+
+        // each name once, however many difficulties use it
+        let unique: HashSet<&String> = decoded.strings.iter().collect();
+        assert_eq!(unique.len(), decoded.strings.len());
+        assert_eq!(decoded.string_checksums.len(), decoded.strings.len());
+        assert!(
+            decoded
+                .map_metadata
+                .values()
+                .flat_map(|map| &map.difficulties)
+                .all(|diff| diff.characteristic_name.is_empty()
+                    && diff.characteristic_index.is_some())
+        );
+
+        resolve_strings(&mut decoded).unwrap();
+        assert!(decoded.strings.is_empty());
+        assert_eq!(decoded.map_metadata, map_list.map_metadata);
+    }
+
+    #[test]
+    fn leaves_names_in_place_without_interning() {
+        let map_list = generate_fixture(100, 2);
+        let decoded = encoded(&map_list, false);
+
+        assert!(decoded.strings.is_empty());
+        assert_eq!(decoded.map_metadata, map_list.map_metadata);
+    }
+
+    #[test]
+    fn fails_on_an_index_past_the_table() {
+        let mut decoded = encoded(&generate_fixture(10, 3), true);
+        decoded.strings.truncate(1);
+
+        assert!(matches!(
+            resolve_strings(&mut decoded),
+            Err(CacherError::UnresolvedName { len: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,405 @@
+// what changed since the cache was last written in full, appended to a file next to it, so the
+// daemon doesn't rewrite hundreds of MB every round and clients can tail the changes
+//
+// layout (all little-endian):
+//   magic          [u8; 4]  "DRMJ"
+//   format_version u16      same as the cache's
+//   base_checksum  u32      the snapshot's payload checksum and length, from its envelope, so
+//   base_len       u64      a journal left over from an older snapshot isn't replayed onto it
+//   records        a varint length and a `JournalRecord` each, in the order they happened
+//
+// a round's records are written and synced in one go. a crash halfway through leaves a torn
+// record at the end, which is dropped when the journal is read
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::{
+    cacher::{
+        decode_cache,
+        delta::diff_caches,
+        envelope::{self, FORMAT_VERSION},
+        error::CacherError,
+        usage::record_written,
+    },
+    mapdata::{JournalRecord, MapList, journal_record::Op},
+};
+
+pub const MAGIC: &[u8; 4] = b"DRMJ";
+
+const HEADER_LEN: usize = 4 + 2 + 4 + 8;
+
+/// `<cache>.journal`.
+pub fn journal_path(cache_path: &str) -> String {
+    format!("{}.journal", cache_path)
+}
+
+struct Journal {
+    base_checksum: u32,
+    base_len: u64,
+    records: Vec<JournalRecord>,
+    /// Where the last whole record ends.
+    len: u64,
+}
+
+/// Which snapshot a journal goes on top of, going by its envelope.
+fn snapshot_id(cache_path: &str) -> Result<(u32, u64), CacherError> {
+    let mut header = [0; envelope::HEADER_LEN];
+    File::open(cache_path)?.read_exact(&mut header)?;
+
+    let header =
+        envelope::read_header(&header).map_err(|e| CacherError::Envelope(e.to_string()))?;
+
+    Ok((header.checksum, header.payload_len))
+}
+
+/// Reads the journal at `path`, `None` if there isn't one.
+fn read_journal(path: &str) -> Result<Option<Journal>, CacherError> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read(path)?;
+    if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+        return Err(CacherError::Journal(format!(
+            "{} has no journal header",
+            path
+        )));
+    }
+
+    let format_version = u16::from_le_bytes([data[4], data[5]]);
+    if format_version > FORMAT_VERSION {
+        return Err(CacherError::Journal(format!(
+            "{} is format version {}, but this build only understands up to {}",
+            path, format_version, FORMAT_VERSION
+        )));
+    }
+
+    let mut journal = Journal {
+        base_checksum: u32::from_le_bytes(data[6..10].try_into().unwrap()),
+        base_len: u64::from_le_bytes(data[10..18].try_into().unwrap()),
+        records: Vec::new(),
+        len: HEADER_LEN as u64,
+    };
+    let mut rest = &data[HEADER_LEN..];
+
+    while !rest.is_empty() {
+        match JournalRecord::decode_length_delimited(&mut rest) {
+            Ok(record) => {
+                journal.records.push(record);
+                journal.len = (data.len() - rest.len()) as u64;
+            }
+            Err(e) => {
+                warn!(
+                    "[Journal] Dropping a torn record at the end of {}: {}",
+                    path, e
+                );
+                break;
+            }
+        }
+    }
+
+    Ok(Some(journal))
+}
+
+/// The journal next to `cache_path`, if there is one and it goes on top of `snapshot`. One for
+/// another snapshot is from a rewrite that didn't get to restart it, the snapshot already has
+/// everything in it, so it's left out until the next full write replaces it.
+fn matching_journal(
+    cache_path: &str,
+    snapshot: (u32, u64),
+) -> Result<Option<Journal>, CacherError> {
+    let path = journal_path(cache_path);
+
+    let Some(journal) = read_journal(&path)? else {
+        return Ok(None);
+    };
+
+    if (journal.base_checksum, journal.base_len) != snapshot {
+        warn!("[Journal] {} is for another snapshot, leaving it out", path);
+        return Ok(None);
+    }
+
+    Ok(Some(journal))
+}
+
+/// Errors if there's a journal next to `cache_path` that doesn't go on top of the snapshot there,
+/// for readers that would rather keep what they have than go without the journal's changes.
+pub fn check_journal(cache_path: &str) -> Result<(), CacherError> {
+    let path = journal_path(cache_path);
+
+    let Some(journal) = read_journal(&path)? else {
+        return Ok(());
+    };
+
+    if (journal.base_checksum, journal.base_len) != snapshot_id(cache_path)? {
+        return Err(CacherError::Journal(format!(
+            "{} isn't for the snapshot in {}",
+            path, cache_path
+        )));
+    }
+
+    Ok(())
+}
+
+/// Starts an empty journal on top of the snapshot that was just written to `cache_path`,
+/// replacing whatever journal was there.
+pub fn start_journal(cache_path: &str) -> Result<(), CacherError> {
+    let (base_checksum, base_len) = snapshot_id(cache_path)?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header.extend_from_slice(&base_checksum.to_le_bytes());
+    header.extend_from_slice(&base_len.to_le_bytes());
+
+    let path = journal_path(cache_path);
+    let mut file = File::create(&path)?;
+    file.write_all(&header)?;
+    file.sync_data()?;
+    record_written(header.len() as u64);
+
+    Ok(())
+}
+
+/// Starts the journal over on top of the snapshot that was just written to `cache_path`, if it
+/// has one. For anything that rewrites a cache it read with `read_cache_with_journal`, whose
+/// records are in the snapshot now.
+pub fn restart_journal(cache_path: &str) -> Result<(), CacherError> {
+    if !Path::new(&journal_path(cache_path)).exists() {
+        return Ok(());
+    }
+
+    start_journal(cache_path)
+}
+
+/// Drops the journal, for when it can't be trusted to go with the snapshot anymore.
+pub fn remove_journal(cache_path: &str) -> Result<(), CacherError> {
+    match fs::remove_file(journal_path(cache_path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Appends what changed from `base` (the snapshot with the journal so far) to `maps`. Hands back
+/// how many records that was, or `None` without writing anything if there's no journal yet, it's
+/// for another snapshot or it already has `compact_after` records, so the snapshot should be
+/// written in full instead.
+pub fn append_journal(
+    cache_path: &str,
+    base: &MapList,
+    maps: &MapList,
+    compact_after: usize,
+) -> Result<Option<usize>, CacherError> {
+    let path = journal_path(cache_path);
+
+    // a cache from before the envelope has nothing to go by, it's written in full first
+    if envelope::is_legacy_file(cache_path)? {
+        return Ok(None);
+    }
+
+    let Some(journal) = matching_journal(cache_path, snapshot_id(cache_path)?)? else {
+        return Ok(None);
+    };
+
+    if journal.records.len() >= compact_after {
+        info!(
+            "[Journal] {} has {} records, folding them into the cache",
+            path,
+            journal.records.len()
+        );
+        return Ok(None);
+    }
+
+    let delta = diff_caches(base, maps);
+    let appended = delta.upserted.len() + delta.removed.len();
+    let mut buf = Vec::new();
+
+    for (key, map) in delta.upserted {
+        let op = if base.map_metadata.contains_key(&key) {
+            Op::Updated
+        } else {
+            Op::Added
+        };
+
+        JournalRecord {
+            op: op as i32,
+            key,
+            map: Some(map),
+        }
+        .encode_length_delimited(&mut buf)
+        .unwrap();
+    }
+
+    for key in delta.removed {
+        JournalRecord {
+            op: Op::Removed as i32,
+            key,
+            map: None,
+        }
+        .encode_length_delimited(&mut buf)
+        .unwrap();
+    }
+
+    let records = journal.records.len();
+    let mut file = OpenOptions::new().write(true).open(&path)?;
+
+    // anything past the last whole record is a torn write from a crash
+    file.set_len(journal.len)?;
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&buf)?;
+    file.sync_data()?;
+    record_written(buf.len() as u64);
+
+    info!(
+        "[Journal] Appended {} records to {}, {} in it now",
+        appended,
+        path,
+        records + appended
+    );
+
+    Ok(Some(appended))
+}
+
+/// Plays the records back onto the snapshot they go on top of.
+fn replay(map_list: &mut MapList, records: Vec<JournalRecord>) {
+    for record in records {
+        let op = record.op();
+
+        match record.map {
+            Some(map) if op != Op::Removed => {
+                map_list.map_metadata.insert(record.key, map);
+            }
+            _ => {
+                map_list.map_metadata.remove(&record.key);
+            }
+        }
+    }
+}
+
+/// Plays the journal next to `cache_path` back onto `map_list`, if it goes on top of `snapshot`.
+fn replay_matching(
+    map_list: &mut MapList,
+    cache_path: &str,
+    snapshot: (u32, u64),
+) -> Result<(), CacherError> {
+    let Some(journal) = matching_journal(cache_path, snapshot)? else {
+        return Ok(());
+    };
+
+    let records = journal.records.len();
+    replay(map_list, journal.records);
+    info!(
+        "[Journal] Replayed {} records from {}",
+        records,
+        journal_path(cache_path)
+    );
+
+    Ok(())
+}
+
+/// Plays the journal next to `cache_path` back onto maps that came out of it some other way than
+/// `read_cache_with_journal`, like `repair`.
+pub fn replay_journal(map_list: &mut MapList, cache_path: &str) -> Result<(), CacherError> {
+    replay_matching(map_list, cache_path, snapshot_id(cache_path)?)
+}
+
+/// Reads the cache at `cache_path` with its journal, if it has one, played back on top. Everything
+/// that reads a cache by path goes through here, the snapshot alone can be behind.
+pub fn read_cache_with_journal(cache_path: &str) -> Result<MapList, CacherError> {
+    let data = fs::read(cache_path)?;
+
+    // journals go by the envelope, so a cache from before it can't have one
+    if envelope::is_legacy(&data) {
+        return decode_cache(&data);
+    }
+
+    let header = envelope::read_header(&data).map_err(|e| CacherError::Envelope(e.to_string()))?;
+    let mut map_list = decode_cache(&data)?;
+
+    // going by the snapshot that was read, in case it's been replaced since
+    replay_matching(
+        &mut map_list,
+        cache_path,
+        (header.checksum, header.payload_len),
+    )?;
+
+    Ok(map_list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cacher::{
+        fixture::{change_fixture, generate_fixture, temp_path},
+        write_cache_file,
+    };
+
+    fn write(map_list: &MapList, path: &str) {
+        let entries = map_list.map_metadata.iter().map(Ok);
+        write_cache_file(entries, map_list.map_metadata.len(), 0, false, path).unwrap();
+    }
+
+    fn clean_up(path: &str) {
+        remove_journal(path).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replays_what_was_appended() {
+        let path = temp_path("journal-replay");
+        let base = generate_fixture(50, 1);
+        write(&base, &path);
+        start_journal(&path).unwrap();
+
+        let maps = change_fixture(&base);
+        assert_eq!(append_journal(&path, &base, &maps, 100).unwrap(), Some(3));
+        assert_eq!(
+            read_cache_with_journal(&path).unwrap().map_metadata,
+            maps.map_metadata
+        );
+
+        clean_up(&path);
+    }
+
+    #[test]
+    fn leaves_out_a_journal_for_another_snapshot() {
+        let path = temp_path("journal-other-snapshot");
+        let base = generate_fixture(50, 2);
+        write(&base, &path);
+        start_journal(&path).unwrap();
+        append_journal(&path, &base, &change_fixture(&base), 100).unwrap();
+
+        // rewritten without starting the journal over
+        let snapshot = generate_fixture(50, 3);
+        write(&snapshot, &path);
+
+        assert_eq!(
+            read_cache_with_journal(&path).unwrap().map_metadata,
+            snapshot.map_metadata
+        );
+        assert!(check_journal(&path).is_err());
+
+        clean_up(&path);
+    }
+
+    #[test]
+    fn asks_for_a_full_write_once_compact_after_is_reached() {
+        let path = temp_path("journal-compact");
+        let base = generate_fixture(50, 4);
+        write(&base, &path);
+        start_journal(&path).unwrap();
+
+        let maps = change_fixture(&base);
+        assert_eq!(append_journal(&path, &base, &maps, 3).unwrap(), Some(3));
+        assert_eq!(append_journal(&path, &maps, &maps, 3).unwrap(), None);
+
+        clean_up(&path);
+    }
+}
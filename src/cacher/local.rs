@@ -0,0 +1,308 @@
+// the maps in a Beat Saber install's CustomLevels folder, hashed the way SongCore does, so they
+// can be checked against the cache
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use tracing::{info, warn};
+
+use crate::{
+    cacher::{
+        error::CacherError, hash_index::HashIndex, history::recorded_key_hashes,
+        usage::record_written,
+    },
+    mapdata::MapList,
+};
+
+/// One folder under CustomLevels.
+pub struct LocalLevel {
+    pub folder: PathBuf,
+    /// `None` for v4 maps, which hash their lightshow and audio files too, same as the mirror.
+    pub hash: Option<String>,
+    /// The BeatSaver key at the start of the folder name, if it looks like one is there. That's
+    /// how BeatSaver and most mod managers name them.
+    pub key_hint: Option<String>,
+}
+
+/// How a local map compares to the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalState {
+    /// The newest version of a map in the cache.
+    Current,
+    /// An older version of a map in the cache.
+    Outdated,
+    /// A map the cache used to have, or whose key is in the folder name, that it doesn't anymore.
+    Deleted,
+    /// Nothing the cache knows about, like WIP maps or ones the filters left out.
+    Missing,
+    /// Couldn't be hashed, so there's nothing to compare.
+    Unhashed,
+}
+
+impl LocalState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LocalState::Current => "current",
+            LocalState::Outdated => "outdated",
+            LocalState::Deleted => "deleted",
+            LocalState::Missing => "missing",
+            LocalState::Unhashed => "unhashed",
+        }
+    }
+}
+
+/// The file called `name` in `folder`, whatever case it's in. Maps made on Windows don't always
+/// get it right.
+fn find_file(folder: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let exact = folder.join(name);
+    if exact.is_file() {
+        return Ok(exact);
+    }
+
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path
+            .file_name()
+            .is_some_and(|file| file.to_string_lossy().eq_ignore_ascii_case(name))
+        {
+            return Ok(path);
+        }
+    }
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} isn't in {}", name, folder.display()),
+    ))
+}
+
+/// SHA-1 of Info.dat followed by every difficulty file it lists, in order, uppercase hex like
+/// BeatSaver has them.
+pub fn level_hash(folder: &Path) -> anyhow::Result<Option<String>> {
+    let info = fs::read(find_file(folder, "Info.dat")?)?;
+    let parsed: Value = serde_json::from_slice(&info)?;
+
+    let Some(sets) = parsed["_difficultyBeatmapSets"].as_array() else {
+        return Ok(None);
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.update(&info);
+
+    let files = sets
+        .iter()
+        .filter_map(|set| set["_difficultyBeatmaps"].as_array())
+        .flatten()
+        .filter_map(|diff| diff["_beatmapFilename"].as_str());
+
+    for file in files {
+        hasher.update(fs::read(find_file(folder, file)?)?);
+    }
+
+    Ok(Some(hex::encode_upper(hasher.finalize())))
+}
+
+/// `1a2b3 (Song - Mapper)` → `1a2b3`.
+fn key_from_folder(folder: &Path) -> Option<String> {
+    let name = folder.file_name()?.to_string_lossy();
+    let key = name.split_whitespace().next()?;
+
+    (key.len() <= 6 && key.chars().all(|c| c.is_ascii_hexdigit())).then(|| key.to_lowercase())
+}
+
+/// Hashes every map folder in `dir`, sorted by folder name. Folders that can't be read are
+/// logged and come back without a hash.
+pub fn scan_custom_levels(dir: &Path) -> Result<Vec<LocalLevel>, CacherError> {
+    let mut folders = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            folders.push(path);
+        }
+    }
+
+    folders.sort();
+
+    Ok(folders
+        .into_iter()
+        .map(|folder| {
+            let hash = level_hash(&folder).unwrap_or_else(|e| {
+                warn!("[Local] Couldn't hash {}: {:?}", folder.display(), e);
+                None
+            });
+
+            LocalLevel {
+                key_hint: key_from_folder(&folder),
+                folder,
+                hash,
+            }
+        })
+        .collect())
+}
+
+/// Windows file times count 100ns ticks from 1601, this many of them before 1970.
+const FILETIME_UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+fn file_time(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    FILETIME_UNIX_EPOCH
+        + since_epoch.as_secs() as i64 * 10_000_000
+        + since_epoch.subsec_nanos() as i64 / 100
+}
+
+/// SongCore's fingerprint of a folder, which it compares to tell whether its cached hash is
+/// still good: every file's creation and write times, name and size, xored together.
+fn directory_hash(folder: &Path) -> std::io::Result<i64> {
+    let mut hash = 0;
+
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let modified = metadata.modified()?;
+        // not every filesystem keeps creation times, SongCore would see the write time there
+        let created = metadata.created().unwrap_or(modified);
+        let name_sum: i64 = entry
+            .file_name()
+            .to_string_lossy()
+            .encode_utf16()
+            .map(i64::from)
+            .sum();
+
+        hash ^= file_time(created);
+        hash ^= file_time(modified);
+        hash ^= name_sum;
+        hash ^= metadata.len() as i64;
+    }
+
+    Ok(hash)
+}
+
+/// One entry of SongHashData.dat, keyed by the level's full path.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SongHashEntry {
+    directory_hash: i64,
+    song_hash: String,
+}
+
+/// The hash to give SongCore for `level`. Maps that couldn't be hashed here fall back to the
+/// cache, but only ones BeatSaver never had a second version of, so the folder can't be an
+/// older one.
+fn song_hash(level: &LocalLevel, maps: &MapList) -> Option<String> {
+    if let Some(hash) = &level.hash {
+        return Some(hash.clone());
+    }
+
+    let map = maps.map_metadata.get(level.key_hint.as_ref()?)?;
+
+    (map.versions.len() <= 1).then(|| map.hash.to_uppercase())
+}
+
+/// Writes the `SongHashData.dat` SongCore keeps in UserData, so the game doesn't have to hash
+/// every map itself on the next start. Paths are as seen from here, so this has to run on the
+/// same machine as the game. Hands back how many levels went in.
+pub fn write_song_hash_data(
+    levels: &[LocalLevel],
+    maps: &MapList,
+    path: &str,
+) -> Result<usize, CacherError> {
+    let mut entries = BTreeMap::new();
+
+    for level in levels {
+        let Some(song_hash) = song_hash(level, maps) else {
+            continue;
+        };
+
+        let full_path = std::path::absolute(&level.folder)?;
+
+        entries.insert(
+            full_path.to_string_lossy().to_string(),
+            SongHashEntry {
+                directory_hash: directory_hash(&level.folder)?,
+                song_hash,
+            },
+        );
+    }
+
+    let json = serde_json::to_vec(&entries).map_err(|e| CacherError::Encode(e.to_string()))?;
+    fs::write(path, &json)?;
+    record_written(json.len() as u64);
+    info!("[Local] Wrote {} levels to {}", entries.len(), path);
+
+    Ok(entries.len())
+}
+
+/// Tells local maps apart by what the cache has. The hash history, if there is one, catches
+/// deleted maps whose folders don't say what their key was.
+pub struct LocalMatcher<'a> {
+    maps: &'a MapList,
+    index: HashIndex,
+    /// Hash to key, for every version the history has seen.
+    history: HashMap<String, String>,
+}
+
+impl<'a> LocalMatcher<'a> {
+    pub fn new(maps: &'a MapList, history: Option<&str>) -> Self {
+        let history = history
+            .map(recorded_key_hashes)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, hash)| (hash.to_lowercase(), key))
+            .collect();
+
+        LocalMatcher {
+            maps,
+            index: HashIndex::build(maps),
+            history,
+        }
+    }
+
+    /// Where `level` stands, and the key of the map it's a version of, if that's known.
+    pub fn classify(&self, level: &LocalLevel) -> (LocalState, Option<String>) {
+        let Some(hash) = &level.hash else {
+            return (LocalState::Unhashed, level.key_hint.clone());
+        };
+
+        if let Some(key) = self.index.key(hash) {
+            let current = self
+                .maps
+                .map_metadata
+                .get(key)
+                .is_some_and(|map| map.hash.eq_ignore_ascii_case(hash));
+            let state = if current {
+                LocalState::Current
+            } else {
+                LocalState::Outdated
+            };
+
+            return (state, Some(key.to_string()));
+        }
+
+        match self
+            .history
+            .get(&hash.to_lowercase())
+            .or(level.key_hint.as_ref())
+        {
+            Some(key) if !self.maps.map_metadata.contains_key(key) => {
+                (LocalState::Deleted, Some(key.clone()))
+            }
+            key => (LocalState::Missing, key.cloned()),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// a tiny file clients can check before deciding to download hundreds of MB of cache
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    cacher::{
+        checksum::sha256_file, delta::cache_version, envelope::FORMAT_VERSION, error::CacherError,
+        usage::ResourceUsage,
+    },
+    mapdata::MapList,
+};
+
+#[derive(Serialize)]
+struct CacheManifest<'a> {
+    /// The envelope's format version.
+    schema_version: u16,
+    /// When the cache was written, in unix seconds.
+    created: i64,
+    /// The cache's file name, it's in the same directory as the manifest.
+    file: &'a str,
+    map_count: usize,
+    sha256: String,
+    /// What `--delta-dir` names deltas after.
+    version: String,
+    compressed_bytes: u64,
+    /// The payload before gzip, string table and checksums included.
+    uncompressed_bytes: u64,
+    /// What the run that wrote the cache used, up to writing it.
+    usage: &'a ResourceUsage,
+}
+
+/// `manifest.json` next to the cache, or `manifest.<profile>.json` for a profile's cache since
+/// several of them can share a directory.
+pub fn manifest_path(cache_path: &str, profile: Option<&str>) -> PathBuf {
+    let name = match profile {
+        Some(profile) => format!("manifest.{}.json", profile),
+        None => "manifest.json".to_string(),
+    };
+
+    Path::new(cache_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(name)
+}
+
+/// Writes a manifest for the cache at `cache_path`, which holds `map_list` and was written by a
+/// run that used `usage`. `uncompressed_bytes` is what writing the cache handed back.
+pub fn write_manifest(
+    map_list: &MapList,
+    cache_path: &str,
+    manifest_path: &Path,
+    uncompressed_bytes: u64,
+    usage: &ResourceUsage,
+) -> Result<(), CacherError> {
+    let file = Path::new(cache_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| cache_path.to_string());
+
+    let manifest = CacheManifest {
+        schema_version: FORMAT_VERSION,
+        created: chrono::Utc::now().timestamp(),
+        file: &file,
+        map_count: map_list.map_metadata.len(),
+        sha256: sha256_file(cache_path)?,
+        version: cache_version(map_list),
+        compressed_bytes: fs::metadata(cache_path)?.len(),
+        uncompressed_bytes,
+        usage,
+    };
+
+    fs::write(
+        manifest_path,
+        serde_json::to_string_pretty(&manifest).unwrap(),
+    )?;
+    info!("Saved manifest to {}", manifest_path.display());
+
+    Ok(())
+}
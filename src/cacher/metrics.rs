@@ -0,0 +1,214 @@
+// counters for the daemon's /metrics, in the Prometheus text format
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+use crate::cacher::{filter::SkipReason, usage::ResourceUsage};
+
+struct Metrics {
+    maps_cached: u64,
+    maps_skipped: BTreeMap<&'static str, u64>,
+    api_errors: BTreeMap<&'static str, u64>,
+    runs: BTreeMap<&'static str, u64>,
+    last_duration_secs: Option<f64>,
+    last_success: Option<i64>,
+    cache_size_bytes: Option<u64>,
+    last_usage: Option<ResourceUsage>,
+    /// Last time a scrape got anywhere, for the watchdog.
+    last_progress: Option<Instant>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    maps_cached: 0,
+    maps_skipped: BTreeMap::new(),
+    api_errors: BTreeMap::new(),
+    runs: BTreeMap::new(),
+    last_duration_secs: None,
+    last_success: None,
+    cache_size_bytes: None,
+    last_usage: None,
+    last_progress: None,
+});
+
+/// Counts maps that made it into a store.
+pub fn record_cached(count: usize) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.maps_cached += count as u64;
+    metrics.last_progress = Some(Instant::now());
+}
+
+/// Notes that a scrape is getting somewhere, without anything to count.
+pub fn record_progress() {
+    METRICS.lock().unwrap().last_progress = Some(Instant::now());
+}
+
+/// Last time a scrape got anywhere.
+pub fn last_progress() -> Option<Instant> {
+    METRICS.lock().unwrap().last_progress
+}
+
+/// When the last successful scrape finished, in unix seconds.
+pub fn last_success() -> Option<i64> {
+    METRICS.lock().unwrap().last_success
+}
+
+/// Counts a map that was left out, and why.
+pub fn record_skipped(reason: SkipReason) {
+    *METRICS
+        .lock()
+        .unwrap()
+        .maps_skipped
+        .entry(reason.as_str())
+        .or_insert(0) += 1;
+}
+
+/// Counts a failed request to BeatSaver, by `ApiError::kind`.
+pub fn record_api_error(kind: &'static str) {
+    *METRICS.lock().unwrap().api_errors.entry(kind).or_insert(0) += 1;
+}
+
+/// Notes down how a scrape went. `cache_size_bytes` is only known for runs that wrote a cache.
+pub fn record_run(duration: Duration, cache_size_bytes: Option<u64>) {
+    let mut metrics = METRICS.lock().unwrap();
+    let result = if cache_size_bytes.is_some() {
+        "success"
+    } else {
+        "failure"
+    };
+
+    *metrics.runs.entry(result).or_insert(0) += 1;
+    metrics.last_duration_secs = Some(duration.as_secs_f64());
+    metrics.last_progress = Some(Instant::now());
+
+    if cache_size_bytes.is_some() {
+        metrics.last_success = Some(Utc::now().timestamp());
+        metrics.cache_size_bytes = cache_size_bytes;
+    }
+}
+
+/// Notes down what the last scrape used, whether it worked or not.
+pub fn record_usage(usage: &ResourceUsage) {
+    METRICS.lock().unwrap().last_usage = Some(usage.clone());
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(String, String)],
+) {
+    let _ = writeln!(out, "# HELP beatsaver_cacher_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE beatsaver_cacher_{} {}", name, kind);
+
+    for (labels, value) in samples {
+        let _ = writeln!(out, "beatsaver_cacher_{}{} {}", name, labels, value);
+    }
+}
+
+fn labelled(label: &str, counts: &BTreeMap<&'static str, u64>) -> Vec<(String, String)> {
+    counts
+        .iter()
+        .map(|(value, count)| (format!("{{{}=\"{}\"}}", label, value), count.to_string()))
+        .collect()
+}
+
+fn single(value: Option<impl ToString>) -> Vec<(String, String)> {
+    value
+        .map(|value| vec![(String::new(), value.to_string())])
+        .unwrap_or_default()
+}
+
+/// Everything so far, ready to be scraped by Prometheus.
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = String::new();
+
+    write_metric(
+        &mut out,
+        "maps_cached_total",
+        "counter",
+        "Maps converted and stored.",
+        &single(Some(metrics.maps_cached)),
+    );
+    write_metric(
+        &mut out,
+        "maps_skipped_total",
+        "counter",
+        "Maps left out of the cache, by reason.",
+        &labelled("reason", &metrics.maps_skipped),
+    );
+    write_metric(
+        &mut out,
+        "api_errors_total",
+        "counter",
+        "Failed requests to BeatSaver, by type.",
+        &labelled("kind", &metrics.api_errors),
+    );
+    write_metric(
+        &mut out,
+        "runs_total",
+        "counter",
+        "Scrapes run, by result.",
+        &labelled("result", &metrics.runs),
+    );
+    write_metric(
+        &mut out,
+        "scrape_duration_seconds",
+        "gauge",
+        "How long the last scrape took.",
+        &single(metrics.last_duration_secs),
+    );
+    write_metric(
+        &mut out,
+        "last_success_timestamp_seconds",
+        "gauge",
+        "When the last successful scrape finished.",
+        &single(metrics.last_success),
+    );
+    write_metric(
+        &mut out,
+        "cache_size_bytes",
+        "gauge",
+        "Size of the cache written by the last successful scrape.",
+        &single(metrics.cache_size_bytes),
+    );
+
+    let usage = metrics.last_usage.as_ref();
+    write_metric(
+        &mut out,
+        "peak_rss_bytes",
+        "gauge",
+        "Most memory the process has held, as of the last scrape.",
+        &single(usage.and_then(|usage| usage.peak_rss_bytes)),
+    );
+    write_metric(
+        &mut out,
+        "scrape_cpu_seconds",
+        "gauge",
+        "CPU time the last scrape took, user and system together.",
+        &single(usage.and_then(|usage| usage.cpu_time_secs)),
+    );
+    write_metric(
+        &mut out,
+        "scrape_downloaded_bytes",
+        "gauge",
+        "Bytes the last scrape downloaded from the APIs.",
+        &single(usage.map(|usage| usage.bytes_downloaded)),
+    );
+    write_metric(
+        &mut out,
+        "scrape_written_bytes",
+        "gauge",
+        "Bytes the last scrape wrote to disk.",
+        &single(usage.map(|usage| usage.bytes_written)),
+    );
+
+    out
+}
@@ -0,0 +1,196 @@
+// bringing caches written by older versions up to the current schema as they're read, so nothing
+// past `decode_cache` has to care which version a cache started out as
+
+use std::{fs::File, io::Read};
+
+use flate2::read::GzDecoder;
+use prost::Message;
+use tracing::{debug, info};
+
+use crate::{
+    cacher::{
+        envelope::{self, FORMAT_VERSION},
+        error::CacherError,
+        journal::{read_cache_with_journal, restart_journal},
+        write_cache,
+    },
+    mapdata::MapList,
+};
+
+/// Upgrades a map list decoded from a cache in format version `from`, in place.
+pub fn upgrade(map_list: &mut MapList, from: u16) {
+    // version 1 only had uint32 timestamps, which decode the same as int64 ones, and before
+    // version 3 there were no requiredMods and suggestedMods, which stay unset like for a map
+    // that doesn't say. nothing in the maps themselves has to change
+    if from < FORMAT_VERSION {
+        debug!("Upgrading a cache from format version {}", from);
+    }
+
+    map_list.schema_version = Some(FORMAT_VERSION.into());
+    // only there for `repair`, they're written fresh every time
+    map_list.record_checksums.clear();
+    map_list.string_checksums.clear();
+}
+
+/// Decodes a cache from before the envelope, which is just the gzipped `MapList`, and upgrades it
+/// like any other.
+pub fn decode_legacy_cache(data: &[u8]) -> Result<MapList, CacherError> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decoded)?;
+
+    // no string table or checksums back then, the names are all in place
+    let mut map_list = MapList::decode(decoded.as_slice())?;
+    upgrade(&mut map_list, 0);
+
+    Ok(map_list)
+}
+
+/// Rewrites the cache at `input` in the current format to `output`, which can be the same file.
+/// Hands back the format version it was in. The journal, if there is one, goes in with the rest.
+pub async fn migrate_cache(input: &str, output: &str) -> Result<u16, CacherError> {
+    let mut start = Vec::new();
+    File::open(input)?
+        .take(envelope::HEADER_LEN as u64)
+        .read_to_end(&mut start)?;
+
+    let from = if envelope::is_legacy(&start) {
+        0
+    } else {
+        envelope::read_header(&start)
+            .map_err(|e| CacherError::Envelope(e.to_string()))?
+            .format_version
+    };
+
+    // upgraded on the way in
+    let map_list = read_cache_with_journal(input)?;
+
+    write_cache(&map_list, output, false).await?;
+    restart_journal(output)?;
+    info!(
+        "Migrated {} maps from format version {} to {}",
+        map_list.map_metadata.len(),
+        from,
+        FORMAT_VERSION
+    );
+
+    Ok(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+    use crate::{
+        cacher::{
+            decode_cache,
+            fixture::{generate_fixture, temp_path},
+            read_cache,
+        },
+        mapdata::{Difficulty, MapMetadata, Ranked, RankedValue, Votes},
+    };
+
+    /// What `write_cache` wrote before the envelope.
+    fn legacy(map_list: &MapList) -> Vec<u8> {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&map_list.encode_to_vec()).unwrap();
+
+        gz.finish().unwrap()
+    }
+
+    /// A map with only the fields the schema had before the envelope, so it encodes exactly the
+    /// way it did back then.
+    fn baseline_map() -> MapMetadata {
+        MapMetadata {
+            key: 0x1a2b,
+            hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            song_name: Some("Ghost Rush".to_string()),
+            level_author_name: Some("Timbo".to_string()),
+            duration: 142,
+            uploaded: 1600000000,
+            last_updated: 1600000500,
+            mods: 0,
+            votes: Votes { up: 12, down: 3 },
+            difficulties: vec![Difficulty {
+                njs: 16.0,
+                notes: 512,
+                characteristic_name: "Standard".to_string(),
+                difficulty_name: "Expert".to_string(),
+                mods: 0,
+                environment_name: "DefaultEnvironment".to_string(),
+                ranked: Ranked {
+                    score_saber: RankedValue {
+                        is_ranked: false,
+                        stars: 0.0,
+                        ..Default::default()
+                    },
+                    beat_leader: RankedValue {
+                        is_ranked: true,
+                        stars: 5.5,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_a_cache_from_before_the_envelope() {
+        let mut map_list = MapList::default();
+        map_list
+            .map_metadata
+            .insert("1a2b".to_string(), baseline_map());
+
+        let decoded = decode_cache(&legacy(&map_list)).unwrap();
+
+        assert_eq!(decoded.map_metadata, map_list.map_metadata);
+        assert_eq!(decoded.schema_version, Some(FORMAT_VERSION.into()));
+    }
+
+    #[test]
+    fn decodes_a_fixture_from_before_the_envelope() {
+        let map_list = generate_fixture(100, 1);
+
+        assert_eq!(
+            decode_cache(&legacy(&map_list)).unwrap().map_metadata,
+            map_list.map_metadata
+        );
+    }
+
+    #[tokio::test]
+    async fn migrates_a_cache_from_before_the_envelope() {
+        let (input, output) = (temp_path("migrate-input"), temp_path("migrate-output"));
+        let map_list = generate_fixture(100, 2);
+        fs::write(&input, legacy(&map_list)).unwrap();
+
+        assert_eq!(migrate_cache(&input, &output).await.unwrap(), 0);
+        assert!(!envelope::is_legacy(&fs::read(&output).unwrap()));
+        assert_eq!(
+            read_cache(&output).unwrap().map_metadata,
+            map_list.map_metadata
+        );
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrating_a_current_cache_keeps_it_the_same() {
+        let (input, output) = (temp_path("migrate-current"), temp_path("migrate-again"));
+        let map_list = generate_fixture(100, 3);
+        write_cache(&map_list, &input, false).await.unwrap();
+
+        assert_eq!(
+            migrate_cache(&input, &output).await.unwrap(),
+            FORMAT_VERSION
+        );
+        assert_eq!(fs::read(&input).unwrap(), fs::read(&output).unwrap());
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+}
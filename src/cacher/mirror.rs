@@ -0,0 +1,222 @@
+// a personal BeatSaver mirror: map zips stored by their map hash, checked against it before
+// they're kept
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use tracing::{info, warn};
+use zip::ZipArchive;
+
+use crate::{
+    cacher::{query::MapQuery, usage::record_written},
+    config::HttpConfig,
+    mapdata::{MapList, MapMetadata},
+};
+
+/// One zip in the mirror, by map hash in `index.json`.
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    key: String,
+    size: u64,
+    /// When it was downloaded, in unix seconds.
+    mirrored_at: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct MirrorStats {
+    pub downloaded: usize,
+    /// Zips already in the mirror from an earlier run.
+    pub existing: usize,
+    pub failed: usize,
+    /// Zips that didn't hash to the map they were downloaded for, and weren't kept.
+    pub mismatched: usize,
+}
+
+/// Where a map's zip goes, under the first two characters of its hash so no directory ends up
+/// with every map in it.
+fn zip_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(&hash[..2]).join(format!("{}.zip", hash))
+}
+
+/// The zip's URL, or where BeatSaver's CDN keeps it for caches made without `urls`.
+fn download_url(map: &MapMetadata) -> String {
+    map.download_url.clone().unwrap_or_else(|| {
+        format!(
+            "https://r2cdn.beatsaver.com/{}.zip",
+            map.hash.to_lowercase()
+        )
+    })
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> anyhow::Result<Vec<u8>> {
+    // zips made by hand don't always get the case right
+    let name = archive
+        .file_names()
+        .find(|file| file.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow::anyhow!("{} isn't in the zip", name))?
+        .to_string();
+
+    let mut data = Vec::new();
+    archive.by_name(&name)?.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// Works out the map hash the way BeatSaver does: SHA-1 of Info.dat followed by every difficulty
+/// file it lists, in order. v4 maps hash their lightshow and audio files too, which isn't worth
+/// reproducing here, so those come back as `None`.
+fn map_hash(zip: &[u8]) -> anyhow::Result<Option<String>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip))?;
+    let info = read_entry(&mut archive, "Info.dat")?;
+    let parsed: Value = serde_json::from_slice(&info)?;
+
+    let Some(sets) = parsed["_difficultyBeatmapSets"].as_array() else {
+        return Ok(None);
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.update(&info);
+
+    let files = sets
+        .iter()
+        .filter_map(|set| set["_difficultyBeatmaps"].as_array())
+        .flatten()
+        .filter_map(|diff| diff["_beatmapFilename"].as_str());
+
+    for file in files {
+        hasher.update(read_entry(&mut archive, file)?);
+    }
+
+    Ok(Some(hex::encode(hasher.finalize())))
+}
+
+/// Downloads a zip and checks it's really the map it says it is. `Ok(None)` means it wasn't.
+async fn fetch_zip(
+    client: &reqwest::Client,
+    url: &str,
+    hash: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let zip = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec();
+
+    let (zip, actual) = tokio::task::spawn_blocking(move || {
+        let actual = map_hash(&zip);
+        (zip, actual)
+    })
+    .await?;
+
+    match actual? {
+        Some(actual) if actual != hash => {
+            warn!("[Mirror] {} came back as {}", hash, actual);
+            Ok(None)
+        }
+        _ => Ok(Some(zip)),
+    }
+}
+
+fn load_index(path: &Path) -> anyhow::Result<BTreeMap<String, IndexEntry>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Downloads every map in `map_list` that matches `query` into `dir`, skipping ones already
+/// there, and updates `dir/index.json`. Zips of maps that stop matching are left alone.
+pub async fn mirror_maps(
+    map_list: &MapList,
+    query: &MapQuery,
+    http: &HttpConfig,
+    dir: &str,
+    concurrency: usize,
+) -> anyhow::Result<MirrorStats> {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+
+    let index_path = dir.join("index.json");
+    let mut index = load_index(&index_path)?;
+    let client = http.client()?;
+    let mut stats = MirrorStats::default();
+    let mut missing = Vec::new();
+
+    for (key, map) in query.select(map_list) {
+        let hash = map.hash.to_lowercase();
+
+        if index.contains_key(&hash) && zip_path(dir, &hash).exists() {
+            stats.existing += 1;
+        } else {
+            missing.push((key.clone(), hash, download_url(map)));
+        }
+    }
+
+    info!(
+        "[Mirror] {} maps to download, {} already mirrored",
+        missing.len(),
+        stats.existing
+    );
+
+    let mut downloads = stream::iter(missing)
+        .map(|(key, hash, url)| {
+            let client = &client;
+
+            async move {
+                let zip = fetch_zip(client, &url, &hash).await;
+                (key, hash, zip)
+            }
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    while let Some((key, hash, zip)) = downloads.next().await {
+        let zip = match zip {
+            Ok(Some(zip)) => zip,
+            Ok(None) => {
+                stats.mismatched += 1;
+                continue;
+            }
+            Err(e) => {
+                warn!("[Mirror] Couldn't download {} ({}): {:?}", key, hash, e);
+                stats.failed += 1;
+                continue;
+            }
+        };
+
+        let path = zip_path(dir, &hash);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, &zip)?;
+        record_written(zip.len() as u64);
+
+        index.insert(
+            hash,
+            IndexEntry {
+                key,
+                size: zip.len() as u64,
+                mirrored_at: chrono::Utc::now().timestamp(),
+            },
+        );
+        stats.downloaded += 1;
+    }
+
+    fs::write(&index_path, serde_json::to_vec_pretty(&index).unwrap())?;
+
+    info!(
+        "[Mirror] {} downloaded, {} failed, {} didn't match their hash",
+        stats.downloaded, stats.failed, stats.mismatched
+    );
+
+    Ok(stats)
+}
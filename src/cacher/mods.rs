@@ -0,0 +1,97 @@
+// names for the bits in the `mods` fields, so nothing people read has to show them as a number
+
+use std::fmt;
+
+use bitflags::bitflags;
+use serde_json::Value;
+
+bitflags! {
+    /// The mods a map or difficulty uses, as the cache's `mods` bitfields have them.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct ModFlags: u32 {
+        const CINEMA = 1;
+        const MAPPING_EXTENSIONS = 1 << 1;
+        const CHROMA = 1 << 2;
+        const NOODLE_EXTENSIONS = 1 << 3;
+        const VIVIFY = 1 << 4;
+    }
+}
+
+impl ModFlags {
+    /// What each mod is called in filters and output.
+    const NAMES: [(ModFlags, &'static str); 5] = [
+        (ModFlags::CINEMA, "cinema"),
+        (ModFlags::MAPPING_EXTENSIONS, "mapping_extensions"),
+        (ModFlags::CHROMA, "chroma"),
+        (ModFlags::NOODLE_EXTENSIONS, "noodle_extensions"),
+        (ModFlags::VIVIFY, "vivify"),
+    ];
+
+    /// The names of the mods that are set, lowest bit first.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .iter()
+            .filter(move |(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+
+    /// The mod called `name`, as `names` has it.
+    pub fn from_name(name: &str) -> Option<ModFlags> {
+        Self::NAMES
+            .iter()
+            .find(|(_, mod_name)| *mod_name == name)
+            .map(|(flag, _)| *flag)
+    }
+
+    /// The mod BeatSaver lists as `requirement`, for the ones with a bit.
+    pub fn from_requirement(requirement: &str) -> Option<ModFlags> {
+        let name = requirement.to_lowercase().replace([' ', '-'], "_");
+
+        Self::from_name(&name)
+    }
+}
+
+/// Bits nothing knows about yet are kept, so they survive a round trip.
+impl From<u32> for ModFlags {
+    fn from(bits: u32) -> Self {
+        ModFlags::from_bits_retain(bits)
+    }
+}
+
+impl From<ModFlags> for u32 {
+    fn from(mods: ModFlags) -> Self {
+        mods.bits()
+    }
+}
+
+/// Swaps the mod bitfields in a map serialized to JSON, and in its difficulties, for lists of
+/// names.
+pub fn name_mods_in_json(map: &mut Value) {
+    fn swap(object: &mut Value) {
+        for field in ["mods", "required_mods", "suggested_mods"] {
+            if let Some(bits) = object.get(field).and_then(Value::as_u64) {
+                let names: Vec<&str> = ModFlags::from(bits as u32).names().collect();
+                object[field] = names.into();
+            }
+        }
+    }
+
+    swap(map);
+
+    if let Some(diffs) = map.get_mut("difficulties").and_then(Value::as_array_mut) {
+        diffs.iter_mut().for_each(swap);
+    }
+}
+
+/// `chroma, noodle_extensions`, or `none`.
+impl fmt::Display for ModFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.names().collect();
+
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join(", "))
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// Beat Saber playlists (.bplist), and plain lists of keys or hashes
+
+use std::{fs, path::Path};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    cacher::{error::CacherError, query::MapQuery, usage::record_written},
+    mapdata::MapList,
+};
+
+#[derive(Deserialize)]
+struct Playlist {
+    songs: Vec<PlaylistSong>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistSong {
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    hash: Option<String>,
+}
+
+/// Maps picked out by hand, by key or by hash.
+#[derive(Debug, Default)]
+pub struct MapSelection {
+    pub keys: Vec<String>,
+    pub hashes: Vec<String>,
+}
+
+/// Map hashes are SHA-1, keys are much shorter.
+fn is_hash(id: &str) -> bool {
+    id.len() == 40 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl MapSelection {
+    /// Adds a key or a hash, whichever it looks like.
+    pub fn push(&mut self, id: &str) {
+        if is_hash(id) {
+            self.hashes.push(id.to_lowercase());
+        } else {
+            self.keys.push(id.to_lowercase());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len() + self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Reads a `.bplist` playlist, or a text file with one key or hash per line. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn read_selection(path: &str, selection: &mut MapSelection) -> Result<(), CacherError> {
+    let contents = fs::read_to_string(path)?;
+
+    if Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == "bplist")
+    {
+        let playlist: Playlist =
+            serde_json::from_str(&contents).map_err(|source| CacherError::Playlist {
+                path: path.to_string(),
+                source,
+            })?;
+
+        // the key gets whatever version is current, which is all a cache can hold anyway
+        for song in playlist.songs {
+            if let Some(id) = song.key.or(song.hash) {
+                selection.push(&id);
+            }
+        }
+
+        return Ok(());
+    }
+
+    for line in contents.lines().map(str::trim) {
+        if !line.is_empty() && !line.starts_with('#') {
+            selection.push(line);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistFile<'a> {
+    playlist_title: &'a str,
+    playlist_author: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    playlist_description: Option<&'a str>,
+    /// A data URI, which is what the game and PlaylistManager read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    songs: Vec<PlaylistEntry<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistEntry<'a> {
+    key: &'a str,
+    hash: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    song_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level_author_name: Option<&'a str>,
+}
+
+/// Metadata for the playlist itself.
+pub struct PlaylistInfo<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+    pub description: Option<&'a str>,
+    /// A PNG or JPEG to use as the cover.
+    pub image: Option<&'a str>,
+}
+
+/// Reads an image into the data URI playlists embed their cover as.
+fn image_data_uri(path: &str) -> Result<String, CacherError> {
+    let mime = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        _ => "image/png",
+    };
+
+    Ok(format!(
+        "data:{};base64,{}",
+        mime,
+        STANDARD.encode(fs::read(path)?)
+    ))
+}
+
+/// Writes a `.bplist` with every map in the cache that matches `query`, newest first. Returns how
+/// many maps went in.
+pub fn write_playlist(
+    map_list: &MapList,
+    query: &MapQuery,
+    info: &PlaylistInfo,
+    path: &str,
+) -> Result<usize, CacherError> {
+    let maps = query.select(map_list);
+
+    let playlist = PlaylistFile {
+        playlist_title: info.title,
+        playlist_author: info.author,
+        playlist_description: info.description,
+        image: info.image.map(image_data_uri).transpose()?,
+        songs: maps
+            .iter()
+            .map(|(key, map)| PlaylistEntry {
+                key,
+                hash: &map.hash,
+                song_name: map.song_name.as_deref(),
+                level_author_name: map.level_author_name.as_deref(),
+            })
+            .collect(),
+    };
+
+    let encoded = serde_json::to_vec_pretty(&playlist).unwrap();
+
+    fs::write(path, &encoded)?;
+    record_written(encoded.len() as u64);
+    info!("Wrote {} maps to playlist {}", playlist.songs.len(), path);
+
+    Ok(playlist.songs.len())
+}
@@ -0,0 +1,176 @@
+// the short song previews, mirrored so request managers can play them offline
+
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use futures::stream::{self, StreamExt};
+use reqwest::{StatusCode, header::RANGE};
+use tracing::{debug, info, warn};
+
+use crate::{
+    cacher::usage::record_written,
+    config::{HttpConfig, PreviewConfig},
+    mapdata::{MapList, MapMetadata},
+};
+
+#[derive(Debug, Default)]
+pub struct PreviewStats {
+    pub downloaded: usize,
+    /// Previews already there from an earlier run.
+    pub existing: usize,
+    pub failed: usize,
+    /// Previews left out because the directory hit its size cap.
+    pub over_cap: usize,
+}
+
+/// The preview's URL, or where BeatSaver's CDN keeps it for caches made without `urls`.
+fn preview_url(map: &MapMetadata) -> String {
+    map.preview_url
+        .clone()
+        .unwrap_or_else(|| format!("https://cdn.beatsaver.com/{}.mp3", map.hash.to_lowercase()))
+}
+
+/// How much is in `dir` already, counting unfinished downloads.
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let mut total = 0;
+
+    for entry in fs::read_dir(dir)? {
+        total += entry?.metadata()?.len();
+    }
+
+    Ok(total)
+}
+
+/// Downloads one preview into `<file>.part`, picking up where an earlier run left off if the CDN
+/// lets us, and renames it into place once it's all there. Returns how many bytes were fetched.
+async fn fetch_preview(client: &reqwest::Client, url: &str, file: &Path) -> anyhow::Result<u64> {
+    let part = file.with_extension("mp3.part");
+    let resume_from = fs::metadata(&part).map(|meta| meta.len()).unwrap_or(0);
+
+    let mut request = client.get(url);
+
+    if resume_from > 0 {
+        request = request.header(RANGE, format!("bytes={}-", resume_from));
+    }
+
+    let mut res = request.send().await?.error_for_status()?;
+
+    // a 200 instead of a 206 means the CDN ignored the range, so start over
+    let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+    let mut out = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)?;
+
+    if resumed {
+        debug!("[Previews] Resuming {} from {} bytes", url, resume_from);
+    }
+
+    let mut fetched = 0;
+
+    while let Some(chunk) = res.chunk().await? {
+        out.write_all(&chunk)?;
+        fetched += chunk.len() as u64;
+    }
+
+    out.sync_all()?;
+    fs::rename(&part, file)?;
+
+    Ok(fetched)
+}
+
+/// Mirrors the preview of every map in `map_lists` into `dir` as `<hash>.mp3`, newest maps first.
+/// Once the directory holds `max_bytes`, no more previews are started, so it can go over by up to
+/// `concurrency` previews.
+pub async fn mirror_previews(
+    map_lists: &[&MapList],
+    config: &PreviewConfig,
+    http: &HttpConfig,
+    dir: &str,
+) -> anyhow::Result<PreviewStats> {
+    fs::create_dir_all(dir)?;
+
+    let client = http.client()?;
+    let mut stats = PreviewStats::default();
+    let mut seen = HashSet::new();
+    let mut maps: Vec<&MapMetadata> = map_lists
+        .iter()
+        .flat_map(|maps| maps.map_metadata.values())
+        .filter(|map| seen.insert(map.hash.to_lowercase()))
+        .collect();
+
+    // when the cap cuts things off, it's the old maps that miss out
+    maps.sort_by_key(|map| std::cmp::Reverse(map.uploaded));
+
+    let mut missing: Vec<(String, PathBuf)> = Vec::new();
+
+    for map in maps {
+        let file = Path::new(dir).join(format!("{}.mp3", map.hash.to_lowercase()));
+
+        if file.exists() {
+            stats.existing += 1;
+        } else {
+            missing.push((preview_url(map), file));
+        }
+    }
+
+    let total = AtomicU64::new(dir_size(Path::new(dir))?);
+
+    info!(
+        "[Previews] {} previews to download, {} already there",
+        missing.len(),
+        stats.existing
+    );
+
+    let mut downloads = stream::iter(missing)
+        .map(|(url, file)| {
+            let client = &client;
+            let total = &total;
+
+            async move {
+                if config
+                    .max_bytes
+                    .is_some_and(|max| total.load(Ordering::Relaxed) >= max)
+                {
+                    return (url, None);
+                }
+
+                let fetched = fetch_preview(client, &url, &file).await;
+
+                if let Ok(bytes) = &fetched {
+                    total.fetch_add(*bytes, Ordering::Relaxed);
+                }
+
+                (url, Some(fetched))
+            }
+        })
+        .buffer_unordered(config.concurrency.max(1));
+
+    while let Some((url, fetched)) = downloads.next().await {
+        match fetched {
+            Some(Ok(bytes)) => {
+                record_written(bytes);
+                stats.downloaded += 1;
+            }
+            Some(Err(e)) => {
+                warn!("[Previews] Couldn't download {}: {:?}", url, e);
+                stats.failed += 1;
+            }
+            None => stats.over_cap += 1,
+        }
+    }
+
+    info!(
+        "[Previews] {} downloaded, {} failed, {} left out by the size cap",
+        stats.downloaded, stats.failed, stats.over_cap
+    );
+
+    Ok(stats)
+}
@@ -0,0 +1,56 @@
+// a progress bar for long scrapes, so there's more to look at than log lines
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+
+const BAR_TEMPLATE: &str =
+    "{spinner} [{elapsed_precise}] {bar:40} {pos}/{len} maps ({eta} left) {msg}";
+const SPINNER_TEMPLATE: &str = "{spinner} [{elapsed_precise}] {pos} maps {msg}";
+
+/// Tracks pages as `init_cache` stores them. Hides itself when stderr isn't a terminal.
+pub struct ScrapeProgress {
+    bar: ProgressBar,
+    pages: u64,
+}
+
+impl ScrapeProgress {
+    /// `total` is how many maps BeatSaver says there are. Without it there's no ETA, just a
+    /// spinner.
+    pub fn new(total: Option<u64>) -> Self {
+        let bar = match total {
+            Some(total) => ProgressBar::new(total)
+                .with_style(ProgressStyle::with_template(BAR_TEMPLATE).unwrap()),
+            None => ProgressBar::new_spinner()
+                .with_style(ProgressStyle::with_template(SPINNER_TEMPLATE).unwrap()),
+        };
+        bar.enable_steady_tick(Duration::from_millis(250));
+
+        ScrapeProgress { bar, pages: 0 }
+    }
+
+    /// Counts a page of `fetched` maps, `cached` in the store so far, and where the cursor is now.
+    pub fn page_done(&mut self, fetched: usize, cached: usize, cursor: DateTime<Utc>) {
+        self.pages += 1;
+        self.bar.inc(fetched as u64);
+
+        // totals are only an estimate, don't let the bar overshoot into nonsense
+        if let Some(len) = self.bar.length()
+            && self.bar.position() > len
+        {
+            self.bar.set_length(self.bar.position());
+        }
+
+        self.bar.set_message(format!(
+            "| {} pages, {} cached, at {}",
+            self.pages,
+            cached,
+            cursor.format("%Y-%m-%d")
+        ));
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
@@ -1,73 +1,288 @@
 // PROTObuf GENerator. get it?
 
-use beatsaver_api::models::map::{Map, MapDifficulty, MapVersion};
+use beatsaver_api::models::{
+    enums::MapState,
+    map::{Map, MapDifficulty, MapVersion},
+};
+use serde_json::Value;
 
 use crate::{
-    cacher::get_map_mods,
-    mapdata::{Difficulty, Ranked, RankedValue, Votes},
+    cacher::{get_map_mods, mods::ModFlags},
+    mapdata::{
+        CharacteristicSummary, Difficulty, MapMetadata, Mapper, ParitySummary, Ranked, RankedState,
+        RankedValue, Version, Votes,
+    },
 };
 
+/// A leaderboard's ranked value from BeatSaver's stars, which are only there when it's ranked.
+fn ranked_value(stars: Option<f64>) -> RankedValue {
+    let state = match stars {
+        Some(_) => RankedState::Ranked,
+        None => RankedState::Unranked,
+    };
+
+    RankedValue {
+        is_ranked: stars.is_some(),
+        stars: stars.unwrap_or(0.0) as f32,
+        state: Some(state as i32),
+        ..Default::default()
+    }
+}
+
 /// Converts the BeatSaver ranked values to a DumbRequestManager-readable format.
 pub(crate) fn generate_protobuf_ranked_values(diff: &MapDifficulty) -> Ranked {
     // autogen moment. i kinda don't want to deal with renaming
     Ranked {
-        score_saber: RankedValue {
-            is_ranked: diff.ss_stars.is_some(),
-            stars: diff.ss_stars.unwrap_or(0.0) as f32,
-        },
-        beat_leader: RankedValue {
-            is_ranked: diff.bl_stars.is_some(),
-            stars: diff.bl_stars.unwrap_or(0.0) as f32,
-        },
+        score_saber: ranked_value(diff.ss_stars),
+        beat_leader: ranked_value(diff.bl_stars),
+    }
+}
+
+/// Converts the parity summary of a difficulty to a DumbRequestManager-readable format.
+pub(crate) fn generate_protobuf_parity(diff: &MapDifficulty) -> ParitySummary {
+    ParitySummary {
+        errors: u32::try_from(diff.parity_summary.errors).unwrap_or(0),
+        warns: u32::try_from(diff.parity_summary.warns).unwrap_or(0),
+        resets: u32::try_from(diff.parity_summary.resets).unwrap_or(0),
     }
 }
 
 /// Converts mods needed by a map to a DumbRequestManager-readable format.
 pub(crate) fn generate_protobuf_map_mods(map_version: &MapVersion) -> u32 {
     let map_mods = get_map_mods(map_version);
+    let mut mods = ModFlags::empty();
+
+    mods.set(ModFlags::CINEMA, map_mods.cinema);
+    mods.set(ModFlags::MAPPING_EXTENSIONS, map_mods.mapping_extensions);
+    mods.set(ModFlags::CHROMA, map_mods.chroma);
+    mods.set(ModFlags::NOODLE_EXTENSIONS, map_mods.noodle_extensions);
+    mods.set(ModFlags::VIVIFY, map_mods.vivify);
 
-    (map_mods.cinema as u32)
-        + ((map_mods.mapping_extensions as u32) << 1)
-        + ((map_mods.chroma as u32) << 2)
-        + ((map_mods.noodle_extensions as u32) << 3)
-        + ((map_mods.vivify as u32) << 4)
+    mods.into()
 }
 
 /// Converts mods needed by a map difficulty to a DumbRequestManager-readable format.
 pub(crate) fn generate_protobuf_diff_mods(diff: &MapDifficulty) -> u32 {
-    (diff.cinema as u32)
-        + ((diff.me as u32) << 1)
-        + ((diff.chroma as u32) << 2)
-        + ((diff.ne as u32) << 3)
-        + ((diff.vivify as u32) << 4)
+    let mut mods = ModFlags::empty();
+
+    mods.set(ModFlags::CINEMA, diff.cinema);
+    mods.set(ModFlags::MAPPING_EXTENSIONS, diff.me);
+    mods.set(ModFlags::CHROMA, diff.chroma);
+    mods.set(ModFlags::NOODLE_EXTENSIONS, diff.ne);
+    mods.set(ModFlags::VIVIFY, diff.vivify);
+
+    mods.into()
+}
+
+/// The mods a difficulty as BeatSaver sent it lists under `field`, `requirements` or
+/// `suggestions`. `None` if it doesn't have that list at all.
+fn listed_mods(raw_diff: &Value, field: &str) -> Option<u32> {
+    let listed = raw_diff[field].as_array()?;
+
+    let mods = listed
+        .iter()
+        .filter_map(Value::as_str)
+        .filter_map(ModFlags::from_requirement)
+        .fold(ModFlags::empty(), |mods, flag| mods | flag);
+
+    Some(mods.into())
+}
+
+/// The union of `mods` over every difficulty, `None` if any of them doesn't say.
+fn combined_mods(mods: impl Iterator<Item = Option<u32>>) -> Option<u32> {
+    mods.fold(Some(0), |combined, mods| Some(combined? | mods?))
+}
+
+/// The requirements a difficulty as BeatSaver sent it lists that `mods` has no bit for.
+fn other_requirements(raw_diff: &Value) -> Vec<String> {
+    let mut requirements: Vec<String> = Vec::new();
+
+    for requirement in raw_diff["requirements"].as_array().into_iter().flatten() {
+        let Some(requirement) = requirement.as_str() else {
+            continue;
+        };
+
+        if ModFlags::from_requirement(requirement).is_none()
+            && !requirements.iter().any(|known| known == requirement)
+        {
+            requirements.push(requirement.to_string());
+        }
+    }
+
+    requirements
+}
+
+/// Fills in what the models don't know about from the map as BeatSaver sent it, on each
+/// difficulty that's left and on the map itself: which mods are required and which only
+/// suggested, and the requirements that have no bit. Difficulties that don't list their
+/// requirements and suggestions leave `required_mods` and `suggested_mods` unset.
+pub(crate) fn add_requirements(map: &mut MapMetadata, raw: &Value) {
+    let Some(version) = raw["versions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|version| version["hash"].as_str() == Some(map.hash.as_str()))
+    else {
+        return;
+    };
+
+    for raw_diff in version["diffs"].as_array().into_iter().flatten() {
+        let diff = map.difficulties.iter_mut().find(|diff| {
+            raw_diff["characteristic"].as_str() == Some(diff.characteristic_name.as_str())
+                && raw_diff["difficulty"].as_str() == Some(diff.difficulty_name.as_str())
+        });
+
+        // trimmed difficulties don't count, same as their mods
+        let Some(diff) = diff else {
+            continue;
+        };
+
+        diff.required_mods = listed_mods(raw_diff, "requirements");
+        diff.suggested_mods = listed_mods(raw_diff, "suggestions");
+
+        for requirement in other_requirements(raw_diff) {
+            if !map.other_requirements.contains(&requirement) {
+                map.other_requirements.push(requirement.clone());
+            }
+
+            diff.other_requirements.push(requirement);
+        }
+    }
+
+    map.required_mods = combined_mods(map.difficulties.iter().map(|diff| diff.required_mods));
+    map.suggested_mods = combined_mods(map.difficulties.iter().map(|diff| diff.suggested_mods));
 }
 
 /// Converts each difficulty in a map on BeatSaver to a DumbRequestManager-readable format.
+/// Difficulties are grouped by characteristic, in the order BeatSaver first lists them.
 pub(crate) fn generate_protobuf_diffs(map_version: &MapVersion) -> Vec<Difficulty> {
     let mut diffs: Vec<Difficulty> = Vec::new();
 
     for diff in &map_version.diffs {
+        let mods = generate_protobuf_diff_mods(diff);
+
         diffs.push(Difficulty {
             njs: diff.njs as f32,
             notes: u32::try_from(diff.notes).unwrap_or(0),
             characteristic_name: diff.characteristic.name().to_string(),
             difficulty_name: diff.difficulty.clone(),
-            mods: generate_protobuf_diff_mods(diff),
-            environment_name: diff.environment.as_ref().unwrap().name().to_string(),
+            mods,
+            // old maps sometimes don't say
+            environment_name: diff
+                .environment
+                .as_ref()
+                .map_or("Unknown".to_string(), |environment| {
+                    environment.name().to_string()
+                }),
             ranked: generate_protobuf_ranked_values(diff),
+            seconds: Some(diff.seconds as f32),
+            nps: Some(diff.nps as f32),
+            bombs: u32::try_from(diff.bombs).ok(),
+            obstacles: u32::try_from(diff.obstacles).ok(),
+            events: u32::try_from(diff.events).ok(),
+            max_score: u32::try_from(diff.max_score).ok(),
+            parity: Some(generate_protobuf_parity(diff)),
+            label: diff.label.clone(),
+            // only the JSON says, `add_requirements` fills them in
+            required_mods: None,
+            suggested_mods: None,
+            hitbloq_pools: Vec::new(),
+            accsaber_categories: Vec::new(),
+            other_requirements: Vec::new(),
+            characteristic_index: None,
+            difficulty_index: None,
+            environment_index: None,
         });
     }
 
+    group_by_characteristic(&mut diffs);
+
     diffs
 }
 
+/// Puts difficulties of the same characteristic next to each other, keeping the order they're
+/// otherwise in. BeatSaver *usually* lists them grouped already, but not always (looking at you,
+/// Lawless).
+fn group_by_characteristic(diffs: &mut [Difficulty]) {
+    let order = characteristic_order(diffs);
+    diffs.sort_by_key(|diff| {
+        order
+            .iter()
+            .position(|name| *name == diff.characteristic_name)
+    });
+}
+
+/// Lists each characteristic once, in the order it first shows up.
+fn characteristic_order(diffs: &[Difficulty]) -> Vec<String> {
+    let mut order: Vec<String> = Vec::new();
+
+    for diff in diffs {
+        if !order.contains(&diff.characteristic_name) {
+            order.push(diff.characteristic_name.clone());
+        }
+    }
+
+    order
+}
+
+/// Counts how many difficulties each characteristic of a map has.
+pub(crate) fn generate_protobuf_characteristics(
+    diffs: &[Difficulty],
+) -> Vec<CharacteristicSummary> {
+    characteristic_order(diffs)
+        .into_iter()
+        .map(|name| CharacteristicSummary {
+            difficulty_count: diffs
+                .iter()
+                .filter(|diff| diff.characteristic_name == name)
+                .count() as u32,
+            characteristic_name: name,
+            characteristic_index: None,
+        })
+        .collect()
+}
+
 /// Converts the curator field on BeatSaver to a DumbRequestManager-readable format, if it exists.
 pub(crate) fn generate_protobuf_curator(map: &Map) -> Option<String> {
-    if map.curator.is_some() {
-        return Some(map.curator.as_ref().unwrap().name.clone());
+    map.curator.as_ref().map(|curator| curator.name.clone())
+}
+
+/// Converts the collaborators on BeatSaver to a DumbRequestManager-readable format.
+pub(crate) fn generate_protobuf_collaborators(map: &Map) -> Vec<Mapper> {
+    map.collaborators
+        .iter()
+        .flatten()
+        .map(|user| Mapper {
+            id: u32::try_from(user.id).unwrap_or(0),
+            name: user.name.clone(),
+        })
+        .collect()
+}
+
+/// A version's state, spelled the way BeatSaver's API spells it.
+fn version_state(state: &MapState) -> &'static str {
+    match state {
+        MapState::Uploaded => "Uploaded",
+        MapState::Testplay => "Testplay",
+        MapState::Published => "Published",
+        MapState::Feedback => "Feedback",
+        MapState::Scheduled => "Scheduled",
     }
+}
 
-    None
+/// Converts every version of a map on BeatSaver to a DumbRequestManager-readable format.
+///
+/// Not just the published ones: a testplay version can still be downloaded, so its hash should
+/// still find the map. Whatever only wants published versions goes by `state`.
+pub(crate) fn generate_protobuf_versions(map: &Map) -> Vec<Version> {
+    map.versions
+        .iter()
+        .map(|version| Version {
+            hash: version.hash.clone(),
+            state: version_state(&version.state).to_string(),
+            created: version.created_at.timestamp(),
+        })
+        .collect()
 }
 
 /// Converts BeatSaver map upvotes/downvotes to a DumbRequestManager-readable format.
@@ -77,3 +292,102 @@ pub(crate) fn generate_protobuf_votes(up: i32, down: i32) -> Votes {
         down: u32::try_from(down).unwrap_or(0),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(characteristic: &str, difficulty: &str) -> Difficulty {
+        Difficulty {
+            characteristic_name: characteristic.to_string(),
+            difficulty_name: difficulty.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn names(diffs: &[Difficulty]) -> Vec<(&str, &str)> {
+        diffs
+            .iter()
+            .map(|diff| {
+                (
+                    diff.characteristic_name.as_str(),
+                    diff.difficulty_name.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_interleaved_characteristics() {
+        let mut diffs = vec![
+            diff("Standard", "Easy"),
+            diff("Lawless", "Expert"),
+            diff("Standard", "Hard"),
+            diff("OneSaber", "Normal"),
+            diff("Lawless", "ExpertPlus"),
+            diff("Standard", "ExpertPlus"),
+            diff("OneSaber", "Expert"),
+        ];
+
+        group_by_characteristic(&mut diffs);
+
+        assert_eq!(
+            names(&diffs),
+            [
+                ("Standard", "Easy"),
+                ("Standard", "Hard"),
+                ("Standard", "ExpertPlus"),
+                ("Lawless", "Expert"),
+                ("Lawless", "ExpertPlus"),
+                ("OneSaber", "Normal"),
+                ("OneSaber", "Expert"),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_grouped_characteristics_alone() {
+        let mut diffs = vec![
+            diff("OneSaber", "Expert"),
+            diff("Standard", "Normal"),
+            diff("Standard", "Expert"),
+            diff("Lawless", "Hard"),
+        ];
+        let before = diffs.clone();
+
+        group_by_characteristic(&mut diffs);
+
+        assert_eq!(diffs, before);
+    }
+
+    #[test]
+    fn counts_difficulties_per_characteristic() {
+        let diffs = [
+            diff("Standard", "Easy"),
+            diff("Lawless", "Expert"),
+            diff("Standard", "Hard"),
+            diff("OneSaber", "Normal"),
+            diff("Lawless", "ExpertPlus"),
+            diff("Standard", "ExpertPlus"),
+        ];
+
+        let counts: Vec<(String, u32)> = generate_protobuf_characteristics(&diffs)
+            .into_iter()
+            .map(|summary| (summary.characteristic_name, summary.difficulty_count))
+            .collect();
+
+        assert_eq!(
+            counts,
+            [
+                ("Standard".to_string(), 3),
+                ("Lawless".to_string(), 2),
+                ("OneSaber".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_nothing_without_difficulties() {
+        assert!(generate_protobuf_characteristics(&[]).is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+// maps we couldn't turn into cache entries, kept as BeatSaver sent them so someone can look later
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::cacher::error::CacherError;
+
+#[derive(Serialize)]
+struct QuarantinedMap<'a> {
+    reason: &'a str,
+    /// When the map was put here, in unix seconds.
+    quarantined_at: i64,
+    map: &'a Value,
+}
+
+/// Map IDs that failed to convert can be anything, so only keep what's safe in a file name.
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if stem.is_empty() {
+        "unnamed".to_string()
+    } else {
+        stem
+    }
+}
+
+/// Writes `<dir>/<name>.json` with the raw map and why it couldn't be cached. A map quarantined
+/// again on a later run just replaces its old file.
+pub fn quarantine_map(dir: &str, name: &str, reason: &str, map: &Value) -> Result<(), CacherError> {
+    fs::create_dir_all(dir)?;
+
+    let record = QuarantinedMap {
+        reason,
+        quarantined_at: chrono::Utc::now().timestamp(),
+        map,
+    };
+    let path = Path::new(dir).join(format!("{}.json", file_stem(name)));
+
+    fs::write(&path, serde_json::to_string_pretty(&record).unwrap())?;
+    debug!("Quarantined {} to {}", name, path.display());
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+// picking maps out of a finished cache, for playlists, the mirror and `query`
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    cacher::expr::FilterExpr,
+    mapdata::{MapList, MapMetadata, RankedValue},
+};
+
+/// Which maps in a cache something wants. Everything left unset matches every map.
+#[derive(Debug, Default)]
+pub struct MapQuery {
+    /// Star range on ScoreSaber or BeatLeader. Only ranked difficulties have stars, so setting
+    /// either one leaves out unranked maps.
+    pub min_stars: Option<f32>,
+    pub max_stars: Option<f32>,
+    /// Only ranked maps, on either leaderboard.
+    pub ranked: bool,
+    /// Maps need every one of these tags.
+    pub tags: Vec<String>,
+    /// Matches the uploader's name, or anywhere in the level author.
+    pub mapper: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only maps that can be played without mods.
+    pub vanilla: bool,
+    /// A `--filter` expression on top of everything else.
+    pub filter: Option<FilterExpr>,
+    /// At most this many maps, newest first.
+    pub limit: Option<usize>,
+}
+
+impl MapQuery {
+    fn in_star_range(&self, ranked: &RankedValue) -> bool {
+        ranked.is_ranked
+            && self.min_stars.is_none_or(|min| ranked.stars >= min)
+            && self.max_stars.is_none_or(|max| ranked.stars <= max)
+    }
+
+    pub fn matches(&self, key: &str, map: &MapMetadata) -> bool {
+        if self.ranked
+            && !map
+                .difficulties
+                .iter()
+                .any(|diff| diff.ranked.score_saber.is_ranked || diff.ranked.beat_leader.is_ranked)
+        {
+            return false;
+        }
+
+        if (self.min_stars.is_some() || self.max_stars.is_some())
+            && !map.difficulties.iter().any(|diff| {
+                self.in_star_range(&diff.ranked.score_saber)
+                    || self.in_star_range(&diff.ranked.beat_leader)
+            })
+        {
+            return false;
+        }
+
+        if !self
+            .tags
+            .iter()
+            .all(|tag| map.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+        {
+            return false;
+        }
+
+        if let Some(mapper) = &self.mapper {
+            let mapper = mapper.to_lowercase();
+            let uploader = map
+                .uploader_name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase() == mapper);
+            let author = map
+                .level_author_name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&mapper));
+
+            if !uploader && !author {
+                return false;
+            }
+        }
+
+        if self
+            .since
+            .is_some_and(|since| map.uploaded < since.timestamp())
+            || self
+                .until
+                .is_some_and(|until| map.uploaded >= until.timestamp())
+        {
+            return false;
+        }
+
+        // older caches don't have requiredMods, and mods is the closest thing
+        if self.vanilla && map.required_mods.unwrap_or(map.mods) != 0 {
+            return false;
+        }
+
+        if let Some(filter) = &self.filter
+            && !filter.matches(key, map)
+        {
+            return false;
+        }
+
+        true
+    }
+
+    /// Every map in `map_list` that matches, newest first, up to the limit.
+    pub fn select<'a>(&self, map_list: &'a MapList) -> Vec<(&'a String, &'a MapMetadata)> {
+        let mut maps: Vec<(&String, &MapMetadata)> = map_list
+            .map_metadata
+            .iter()
+            .filter(|(key, map)| self.matches(key, map))
+            .collect();
+
+        maps.sort_by_key(|(_, map)| std::cmp::Reverse(map.uploaded));
+
+        if let Some(limit) = self.limit {
+            maps.truncate(limit);
+        }
+
+        maps
+    }
+}
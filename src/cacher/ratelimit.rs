@@ -0,0 +1,221 @@
+// keeping concurrent fetches from hammering BeatSaver
+
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use tokio::{
+    sync::Mutex,
+    time::{Instant, sleep_until},
+};
+use tracing::warn;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// Set when BeatSaver tells us to back off. Nobody gets a token before then.
+    paused_until: Option<Instant>,
+}
+
+/// A token bucket shared by every task making requests: `burst` requests can go out at once,
+/// after that it's `per_second` requests a second.
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+
+        RateLimiter {
+            per_second: per_second.max(0.01),
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last_refill: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until there's a token to spend on a request.
+    pub async fn wait(&self) {
+        loop {
+            let wait_until = {
+                let mut bucket = self.bucket.lock().await;
+                let now = Instant::now();
+
+                match bucket.paused_until {
+                    Some(paused_until) if paused_until > now => Some(paused_until),
+                    _ => {
+                        bucket.paused_until = None;
+
+                        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+                        bucket.last_refill = now;
+
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= 1.0;
+                            None
+                        } else {
+                            let missing = 1.0 - bucket.tokens;
+                            Some(now + Duration::from_secs_f64(missing / self.per_second))
+                        }
+                    }
+                }
+            };
+
+            match wait_until {
+                Some(wait_until) => sleep_until(wait_until).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Stops everyone from making requests for `duration`, and empties the bucket so requests
+    /// don't all rush out again the moment the pause ends.
+    pub async fn pause(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().await;
+        let until = Instant::now() + duration;
+
+        bucket.paused_until = Some(
+            bucket
+                .paused_until
+                .map_or(until, |paused| paused.max(until)),
+        );
+        bucket.tokens = 0.0;
+        bucket.last_refill = until;
+    }
+}
+
+/// Exponential backoff with full jitter, and a limited number of tries.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    max_retries: u32,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, max_retries: u32) -> Self {
+        Backoff {
+            base,
+            max,
+            max_retries,
+            attempt: 0,
+        }
+    }
+
+    /// How long to wait before the next try, or `None` once the retry budget is spent.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.max_retries {
+            return None;
+        }
+
+        let ceiling = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt += 1;
+
+        // full jitter, so a bunch of workers failing together don't all retry together
+        Some(ceiling.mul_f64(rand::rng().random_range(0.0..=1.0)))
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Call after a success, so the next failure starts from the bottom again.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Reads a `Retry-After` header, which is either a number of seconds or an HTTP date.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    match chrono::DateTime::parse_from_rfc2822(value) {
+        Ok(date) => Some(
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO),
+        ),
+        Err(_) => {
+            warn!("Couldn't understand Retry-After: {}", value);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn backoff_stays_under_the_ceiling_and_runs_out() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350), 4);
+
+        for ceiling in [100, 200, 350, 350] {
+            assert!(backoff.next_delay().unwrap().as_millis() <= ceiling);
+        }
+
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempts(), 4);
+
+        backoff.reset();
+        assert!(backoff.next_delay().unwrap().as_millis() <= 100);
+    }
+
+    #[test]
+    fn reads_retry_after() {
+        let retry_after = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            parse_retry_after(&headers)
+        };
+
+        assert_eq!(retry_after("30"), Some(Duration::from_secs(30)));
+        // already past
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn lets_a_burst_through_then_waits() {
+        let limiter = RateLimiter::new(10.0, 2);
+        let started = Instant::now();
+
+        limiter.wait().await;
+        limiter.wait().await;
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // the third has to wait for a token, a tenth of a second
+        limiter.wait().await;
+        assert!(started.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[tokio::test]
+    async fn nobody_gets_through_while_paused() {
+        let limiter = RateLimiter::new(100.0, 5);
+        let started = Instant::now();
+
+        limiter.pause(Duration::from_millis(100)).await;
+        limiter.wait().await;
+
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
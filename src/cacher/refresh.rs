@@ -0,0 +1,143 @@
+// patching a handful of maps into an existing cache, instead of scraping everything again
+
+use std::{collections::BTreeMap, time::Duration};
+
+use beatsaver_api::models::map::MapDetail;
+use serde_json::Value;
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    cacher::{
+        MapStore, Profile, ScrapeStats,
+        api::{ApiClient, ApiError, LookupBy, MAX_IDS_PER_REQUEST},
+        cache_map_data,
+        error::CacherError,
+        metrics::record_api_error,
+        playlist::MapSelection,
+        ratelimit::Backoff,
+    },
+    mapdata::MapList,
+};
+
+/// Fetches one batch, retrying like the scraper does.
+async fn fetch_batch(
+    client: &ApiClient,
+    by: LookupBy,
+    batch: &[String],
+    max_retries: u32,
+    stats: &mut ScrapeStats,
+) -> Result<BTreeMap<String, (MapDetail, Value)>, CacherError> {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(500),
+        Duration::from_secs(60),
+        max_retries,
+    );
+
+    loop {
+        let err = match client.lookup_maps(by, batch).await {
+            Ok(maps) => return Ok(maps),
+            Err(err) => err,
+        };
+
+        record_api_error(err.kind());
+        *stats.api_errors.entry(err.kind()).or_insert(0) += 1;
+        error!("Couldn't fetch maps starting at {}: {}", batch[0], err);
+
+        // same as in `wait_to_retry`, the limiter has already waited
+        if matches!(err, ApiError::RateLimited(_)) {
+            continue;
+        }
+
+        let Some(delay) = backoff.next_delay() else {
+            return Err(CacherError::Batch {
+                first: batch[0].clone(),
+                count: batch.len(),
+                attempts: backoff.attempts(),
+                source: err,
+            });
+        };
+
+        debug!("Retrying in {:?}", delay);
+        sleep(delay).await;
+    }
+}
+
+/// Fetches the maps with these keys or hashes from BeatSaver in batches and puts them into
+/// `map_list`, replacing whatever was there. Maps BeatSaver doesn't have anymore, or that the
+/// profile wouldn't keep anymore, are taken out.
+pub async fn refresh_maps(
+    client: &ApiClient,
+    map_list: &mut MapList,
+    by: LookupBy,
+    ids: &[String],
+    profile: &Profile,
+    max_retries: u32,
+    stats: &mut ScrapeStats,
+) -> Result<(), CacherError> {
+    // BeatSaver's keys and hashes are lowercase, and so are the cache's keys
+    let ids: Vec<String> = ids.iter().map(|id| id.to_lowercase()).collect();
+    let mut done = 0;
+
+    for batch in ids.chunks(MAX_IDS_PER_REQUEST) {
+        let maps = fetch_batch(client, by, batch, max_retries, stats).await?;
+
+        for id in batch {
+            let Some((map, raw)) = maps.get(id) else {
+                // a hash that's gone doesn't say which map it was
+                if by == LookupBy::Key && map_list.map_metadata.remove(id).is_some() {
+                    info!("{} isn't on BeatSaver anymore, removed it", id);
+                    stats.removed_maps += 1;
+                } else {
+                    warn!("{} isn't on BeatSaver", id);
+                }
+                continue;
+            };
+            let key = &map.id;
+
+            match cache_map_data(map, raw, profile) {
+                Ok(cached_map) => {
+                    if map_list.insert(key.clone(), cached_map) {
+                        stats.new_maps += 1;
+                    } else {
+                        stats.updated_maps += 1;
+                    }
+                }
+                Err(reason) => {
+                    *stats.skipped.entry(reason.as_str()).or_insert(0) += 1;
+
+                    if map_list.map_metadata.remove(key).is_some() {
+                        info!("{} got skipped ({}), removed it", key, reason.as_str());
+                        stats.removed_maps += 1;
+                    }
+                }
+            }
+        }
+
+        done += batch.len();
+        info!("[Refresh] {} of {} maps done", done, ids.len());
+    }
+
+    Ok(())
+}
+
+/// Refreshes every map in the selection, keys first.
+pub async fn refresh_selection(
+    client: &ApiClient,
+    map_list: &mut MapList,
+    selection: &MapSelection,
+    profile: &Profile,
+    max_retries: u32,
+    stats: &mut ScrapeStats,
+) -> Result<(), CacherError> {
+    let lookups = [
+        (LookupBy::Key, &selection.keys),
+        (LookupBy::Hash, &selection.hashes),
+    ];
+
+    for (by, ids) in lookups {
+        refresh_maps(client, map_list, by, ids, profile, max_retries, stats).await?;
+    }
+
+    Ok(())
+}
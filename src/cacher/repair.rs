@@ -0,0 +1,336 @@
+// getting what's still good out of a cache that's been cut short or bit-rotted, going by the
+// checksum written after every map, instead of losing all of it
+
+use std::{fs, io::Read};
+
+use flate2::read::GzDecoder;
+use prost::{
+    Message,
+    encoding::{WireType, decode_key, decode_varint, skip_field},
+};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    cacher::{envelope, error::CacherError, intern::resolve_map, migrate},
+    mapdata::{MapList, MapMetadata},
+};
+
+/// The first format version with a CRC32 after every map.
+const RECORD_CHECKSUMS_SINCE: u16 = 4;
+/// The first format version with a CRC32 after every name in the string table.
+const STRING_CHECKSUMS_SINCE: u16 = 5;
+/// A checksum's key and value. The tags are small enough for a one-byte key.
+const CHECKSUM_LEN: usize = 1 + 4;
+
+/// One `mapMetadata` entry, the way protobuf writes a map.
+#[derive(Clone, PartialEq, Message)]
+struct MapEntry {
+    #[prost(string, required, tag = "1")]
+    key: String,
+    #[prost(message, required, tag = "2")]
+    value: MapMetadata,
+}
+
+/// What `salvage_cache` found.
+#[derive(Debug, Default, Serialize)]
+pub struct RepairReport {
+    /// Maps that came out intact.
+    pub salvaged: usize,
+    /// How many the envelope says there should be.
+    pub expected: u32,
+    /// How many of those didn't make it, corrupt or cut off.
+    pub lost: usize,
+    /// Maps that were there but didn't match their checksum, didn't decode or use a name that
+    /// didn't make it, by key where the key could still be read.
+    pub corrupt: Vec<String>,
+    /// Names in the string table that didn't match their checksum.
+    pub corrupt_names: usize,
+    /// The file ended, or stopped making sense, before the last map.
+    pub truncated: bool,
+}
+
+/// Decompresses as much of the payload as still decompresses.
+fn inflate(payload: &[u8]) -> (Vec<u8>, bool) {
+    let mut decoder = GzDecoder::new(payload);
+    let mut data = Vec::new();
+    let mut chunk = [0; 64 * 1024];
+
+    loop {
+        match decoder.read(&mut chunk) {
+            Ok(0) => return (data, false),
+            Ok(read) => data.extend_from_slice(&chunk[..read]),
+            Err(e) => {
+                warn!(
+                    "[Repair] Payload stops decompressing after {} bytes: {}",
+                    data.len(),
+                    e
+                );
+                return (data, true);
+            }
+        }
+    }
+}
+
+/// The bytes of the length-delimited field `rest` starts at, past its key. `None` if the length
+/// runs past the end.
+fn length_delimited<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = decode_varint(rest).ok()? as usize;
+    if len > rest.len() {
+        return None;
+    }
+
+    let (bytes, after) = rest.split_at(len);
+    *rest = after;
+
+    Some(bytes)
+}
+
+/// The CRC32 `rest` starts at, if it does, as field `tag`. Moves past it if so.
+fn checksum(rest: &mut &[u8], tag: u32) -> Option<u32> {
+    let mut peek = *rest;
+
+    match decode_key(&mut peek) {
+        Ok((found, WireType::ThirtyTwoBit)) if found == tag && peek.len() >= 4 => {
+            let checksum = u32::from_le_bytes(peek[..4].try_into().unwrap());
+            *rest = &peek[4..];
+            Some(checksum)
+        }
+        _ => None,
+    }
+}
+
+/// Same as `checksum`, for one the format version says has to be there. Moves past where it
+/// should be either way, so a checksum whose key got damaged doesn't throw off everything after
+/// it.
+fn required_checksum(rest: &mut &[u8], tag: u32) -> Option<u32> {
+    let checksum = checksum(rest, tag);
+
+    if checksum.is_none() {
+        *rest = &rest[rest.len().min(CHECKSUM_LEN)..];
+    }
+
+    checksum
+}
+
+/// The key of an entry that didn't decode as a whole, if that much of it is readable.
+fn entry_key(mut entry: &[u8]) -> Option<String> {
+    let (tag, wire_type) = decode_key(&mut entry).ok()?;
+    if tag != 1 || wire_type != WireType::LengthDelimited {
+        return None;
+    }
+
+    String::from_utf8(length_delimited(&mut entry)?.to_vec()).ok()
+}
+
+/// Reads every map out of `data` that's still intact, skipping the ones whose checksum doesn't
+/// match and stopping where the file stops making sense. A checksum that's missing where the
+/// format version says there is one counts as a mismatch. Caches from before per-map checksums
+/// are taken as they decode.
+pub fn salvage_cache(data: &[u8]) -> Result<(MapList, RepairReport), CacherError> {
+    let header = envelope::read_header(data).map_err(|e| CacherError::Envelope(e.to_string()))?;
+    let header_len = u16::from_le_bytes([data[6], data[7]]) as usize;
+
+    let (decoded, mut truncated) = inflate(&data[header_len..]);
+    let mut map_list = MapList::default();
+    let mut report = RepairReport {
+        expected: header.map_count,
+        ..Default::default()
+    };
+    let mut strings = Vec::new();
+    let mut rest = decoded.as_slice();
+
+    while !rest.is_empty() {
+        let Ok((tag, wire_type)) = decode_key(&mut rest) else {
+            truncated = true;
+            break;
+        };
+
+        // the string table comes a name at a time, before the first map that needs each one.
+        // a name that's gone bad keeps its place, so the ones after it still line up
+        if tag == 4 && wire_type == WireType::LengthDelimited {
+            let Some(name) = length_delimited(&mut rest) else {
+                truncated = true;
+                break;
+            };
+
+            let intact = header.format_version < STRING_CHECKSUMS_SINCE
+                || required_checksum(&mut rest, 5)
+                    .is_some_and(|checksum| crc32fast::hash(name) == checksum);
+
+            match String::from_utf8(name.to_vec()) {
+                Ok(name) if intact => strings.push(Some(name)),
+                _ => {
+                    warn!(
+                        "[Repair] Name {} in the string table is corrupt",
+                        strings.len()
+                    );
+                    report.corrupt_names += 1;
+                    strings.push(None);
+                }
+            }
+            continue;
+        }
+
+        if tag != 1 || wire_type != WireType::LengthDelimited {
+            // the schema version, or a checksum without its map or name
+            if skip_field(wire_type, tag, &mut rest, Default::default()).is_err() {
+                truncated = true;
+                break;
+            }
+            continue;
+        }
+
+        let Some(entry) = length_delimited(&mut rest) else {
+            truncated = true;
+            break;
+        };
+
+        let intact = header.format_version < RECORD_CHECKSUMS_SINCE
+            || required_checksum(&mut rest, 3)
+                .is_some_and(|checksum| crc32fast::hash(entry) == checksum);
+
+        match MapEntry::decode(entry) {
+            Ok(MapEntry { key, mut value }) if intact => match resolve_map(&mut value, &strings) {
+                Ok(()) => {
+                    map_list.map_metadata.insert(key, value);
+                    report.salvaged += 1;
+                }
+                Err(e) => {
+                    warn!("[Repair] Map {} is missing a name, dropping it: {}", key, e);
+                    report.corrupt.push(key);
+                }
+            },
+            _ => {
+                let key = entry_key(entry).unwrap_or_else(|| "?".to_string());
+                warn!("[Repair] Map {} is corrupt, dropping it", key);
+                report.corrupt.push(key);
+            }
+        }
+    }
+
+    report.truncated = truncated;
+    report.lost = (report.expected as usize).saturating_sub(report.salvaged);
+    migrate::upgrade(&mut map_list, header.format_version);
+
+    Ok((map_list, report))
+}
+
+/// Same as `salvage_cache`, for the cache at `path`.
+pub fn salvage_cache_file(path: &str) -> Result<(MapList, RepairReport), CacherError> {
+    salvage_cache(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use super::*;
+    use crate::cacher::{encode_entries_streaming, fixture::generate_fixture};
+
+    /// The uncompressed payload `write_cache` would write for the first `count` maps.
+    fn payload(map_list: &MapList, count: usize) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let entries = map_list.map_metadata.iter().take(count).map(Ok);
+        encode_entries_streaming(entries, false, &mut payload).unwrap();
+
+        payload
+    }
+
+    /// Where the `index`th map's entry starts in the payload. The entries don't depend on each
+    /// other, only the schema version comes after them.
+    fn entry_offset(map_list: &MapList, index: usize) -> usize {
+        payload(map_list, index).len() - payload(map_list, 0).len()
+    }
+
+    /// Wraps a payload in an envelope, the way `write_cache` does.
+    fn wrap(payload: &[u8], map_count: usize) -> Vec<u8> {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(payload).unwrap();
+        let compressed = gz.finish().unwrap();
+
+        let mut data = envelope::encode_header(
+            map_count,
+            0,
+            crc32fast::hash(&compressed),
+            compressed.len() as u64,
+        );
+        data.extend_from_slice(&compressed);
+
+        data
+    }
+
+    /// Salvages `payload` and checks that every map but the one at `lost` came out as it went in.
+    fn salvage_without(map_list: &MapList, payload: &[u8], lost: usize) -> RepairReport {
+        let count = map_list.map_metadata.len();
+        let (salvaged, report) = salvage_cache(&wrap(payload, count)).unwrap();
+        let lost_key = map_list.map_metadata.keys().nth(lost).unwrap();
+
+        let mut expected = map_list.map_metadata.clone();
+        expected.remove(lost_key);
+
+        assert_eq!(salvaged.map_metadata, expected);
+        assert_eq!(report.corrupt, vec![lost_key.clone()]);
+        assert_eq!(report.lost, 1);
+
+        report
+    }
+
+    #[test]
+    fn salvages_an_intact_cache() {
+        let map_list = generate_fixture(20, 1);
+        let payload = payload(&map_list, usize::MAX);
+
+        let (salvaged, report) = salvage_cache(&wrap(&payload, 20)).unwrap();
+
+        assert_eq!(salvaged.map_metadata, map_list.map_metadata);
+        assert_eq!(report.salvaged, 20);
+        assert_eq!(report.lost, 0);
+        assert!(report.corrupt.is_empty());
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn drops_a_map_with_a_flipped_byte() {
+        let map_list = generate_fixture(20, 2);
+        let mut payload = payload(&map_list, usize::MAX);
+
+        let start = entry_offset(&map_list, 7);
+        let end = entry_offset(&map_list, 8);
+        payload[(start + end) / 2] ^= 0x40;
+
+        let report = salvage_without(&map_list, &payload, 7);
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn drops_a_map_whose_checksum_is_damaged() {
+        let map_list = generate_fixture(20, 3);
+        let mut payload = payload(&map_list, usize::MAX);
+
+        // the checksum's key, right before the next entry
+        let checksum_key = entry_offset(&map_list, 8) - CHECKSUM_LEN;
+        payload[checksum_key] ^= 0xff;
+
+        let report = salvage_without(&map_list, &payload, 7);
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn keeps_the_maps_before_a_truncated_tail() {
+        let map_list = generate_fixture(20, 4);
+        let payload = payload(&map_list, usize::MAX);
+
+        // cut off halfway through the 13th map
+        let cut = (entry_offset(&map_list, 12) + entry_offset(&map_list, 13)) / 2;
+        let (salvaged, report) = salvage_cache(&wrap(&payload[..cut], 20)).unwrap();
+
+        let expected: Vec<_> = map_list.map_metadata.iter().take(12).collect();
+        assert_eq!(salvaged.map_metadata.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(report.salvaged, 12);
+        assert_eq!(report.lost, 8);
+        assert!(report.truncated);
+    }
+}
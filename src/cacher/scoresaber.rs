@@ -0,0 +1,177 @@
+// same idea as the BeatLeader pass: BeatSaver's copy of ScoreSaber's stars goes stale, so ask
+// ScoreSaber directly
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::{
+    cacher::ratelimit::RateLimiter,
+    config::HttpConfig,
+    mapdata::{MapList, RankedState, RankedValue},
+};
+
+pub const SCORESABER_API_URL: &str = "https://scoresaber.com/api";
+
+#[derive(Deserialize)]
+struct LeaderboardPage {
+    leaderboards: Vec<Leaderboard>,
+    metadata: PageMetadata,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageMetadata {
+    total: u32,
+    items_per_page: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Leaderboard {
+    id: u64,
+    song_hash: String,
+    difficulty: LeaderboardDifficulty,
+    ranked: bool,
+    qualified: bool,
+    stars: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderboardDifficulty {
+    /// 1, 3, 5, 7 or 9, Easy through ExpertPlus.
+    difficulty: u32,
+    /// The characteristic with "Solo" in front, e.g. "SoloStandard".
+    game_mode: String,
+}
+
+/// The difficulty name BeatSaver uses for ScoreSaber's difficulty number.
+fn difficulty_name(difficulty: u32) -> Option<&'static str> {
+    match difficulty {
+        1 => Some("Easy"),
+        3 => Some("Normal"),
+        5 => Some("Hard"),
+        7 => Some("Expert"),
+        9 => Some("ExpertPlus"),
+        _ => None,
+    }
+}
+
+/// Every ranked and qualified leaderboard on ScoreSaber, by (lowercase hash, characteristic,
+/// difficulty).
+pub struct ScoreSaberRanking {
+    leaderboards: HashMap<(String, String, String), Leaderboard>,
+}
+
+/// Pages through every ranked and then every qualified leaderboard on ScoreSaber.
+pub async fn fetch_ranking(http: &HttpConfig) -> anyhow::Result<ScoreSaberRanking> {
+    let client = http.client()?;
+    let limiter = RateLimiter::new(5.0, 1);
+    let mut leaderboards = HashMap::new();
+
+    for list in ["ranked", "qualified"] {
+        let mut page = 1;
+
+        loop {
+            limiter.wait().await;
+
+            let res: LeaderboardPage = client
+                .get(format!("{}/leaderboards", SCORESABER_API_URL))
+                .query(&[(list, "true".to_string()), ("page", page.to_string())])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            debug!(
+                "[ScoreSaber] Got {} page {} ({} leaderboards)",
+                list,
+                page,
+                res.leaderboards.len()
+            );
+
+            let done = res.leaderboards.is_empty()
+                || page * res.metadata.items_per_page >= res.metadata.total;
+
+            for leaderboard in res.leaderboards {
+                let Some(difficulty) = difficulty_name(leaderboard.difficulty.difficulty) else {
+                    continue;
+                };
+                let characteristic = leaderboard.difficulty.game_mode.trim_start_matches("Solo");
+
+                let key = (
+                    leaderboard.song_hash.to_lowercase(),
+                    characteristic.to_string(),
+                    difficulty.to_string(),
+                );
+                leaderboards.insert(key, leaderboard);
+            }
+
+            if done {
+                break;
+            }
+
+            page += 1;
+        }
+    }
+
+    info!("[ScoreSaber] Fetched {} leaderboards", leaderboards.len());
+
+    Ok(ScoreSaberRanking { leaderboards })
+}
+
+/// Replaces BeatSaver's idea of every difficulty's ScoreSaber ranking with ScoreSaber's own.
+/// Difficulties ScoreSaber isn't ranking come out unranked, whatever BeatSaver thought. Returns
+/// how many difficulties changed.
+pub fn apply_ranking(map_list: &mut MapList, ranking: &ScoreSaberRanking) -> usize {
+    let mut changed = 0;
+
+    for map in map_list.map_metadata.values_mut() {
+        let hash = map.hash.to_lowercase();
+
+        for diff in &mut map.difficulties {
+            let key = (
+                hash.clone(),
+                diff.characteristic_name.clone(),
+                diff.difficulty_name.clone(),
+            );
+
+            // ScoreSaber doesn't do nominations, so those just show up as unranked
+            let ranked = match ranking.leaderboards.get(&key) {
+                Some(leaderboard) => {
+                    let state = if leaderboard.ranked {
+                        RankedState::Ranked
+                    } else if leaderboard.qualified {
+                        RankedState::Qualified
+                    } else {
+                        RankedState::Unranked
+                    };
+
+                    RankedValue {
+                        is_ranked: leaderboard.ranked,
+                        stars: leaderboard.stars,
+                        state: Some(state as i32),
+                        leaderboard_id: Some(leaderboard.id.to_string()),
+                        ..Default::default()
+                    }
+                }
+                None => RankedValue {
+                    is_ranked: false,
+                    stars: 0.0,
+                    state: Some(RankedState::Unranked as i32),
+                    ..Default::default()
+                },
+            };
+
+            if diff.ranked.score_saber != ranked {
+                diff.ranked.score_saber = ranked;
+                changed += 1;
+            }
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,165 @@
+// full-text search over the names in a cache, so "what was that map called" works offline
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use tantivy::{
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument,
+    collector::TopDocs,
+    doc,
+    query::QueryParser,
+    schema::{Field, STORED, STRING, Schema, TEXT, Value},
+};
+use tracing::info;
+
+use crate::{cacher::error::CacherError, mapdata::MapList};
+
+/// How much memory the writer gets while building, tantivy's own suggestion.
+const WRITER_MEMORY: usize = 50_000_000;
+
+struct Fields {
+    key: Field,
+    song_name: Field,
+    song_sub_name: Field,
+    song_author: Field,
+    mapper: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            key: builder.add_text_field("key", STRING | STORED),
+            song_name: builder.add_text_field("song_name", TEXT),
+            song_sub_name: builder.add_text_field("song_sub_name", TEXT),
+            song_author: builder.add_text_field("song_author", TEXT),
+            mapper: builder.add_text_field("mapper", TEXT),
+        };
+
+        (builder.build(), fields)
+    }
+
+    fn from_schema(schema: &Schema) -> Result<Fields, CacherError> {
+        Ok(Fields {
+            key: schema.get_field("key")?,
+            song_name: schema.get_field("song_name")?,
+            song_sub_name: schema.get_field("song_sub_name")?,
+            song_author: schema.get_field("song_author")?,
+            mapper: schema.get_field("mapper")?,
+        })
+    }
+
+    fn searched(&self) -> Vec<Field> {
+        vec![
+            self.song_name,
+            self.song_sub_name,
+            self.song_author,
+            self.mapper,
+        ]
+    }
+}
+
+/// One search result, best first.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub key: String,
+    pub score: f32,
+}
+
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    fields: Fields,
+}
+
+impl SearchIndex {
+    /// Indexes every map in `map_list`, in memory, or into `dir` so `search --index` can reuse
+    /// it. Anything already in `dir` is replaced.
+    pub fn build(map_list: &MapList, dir: Option<&str>) -> Result<Self, CacherError> {
+        let (schema, fields) = Fields::schema();
+
+        let index = match dir {
+            Some(dir) => {
+                if Path::new(dir).exists() {
+                    fs::remove_dir_all(dir)?;
+                }
+                fs::create_dir_all(dir)?;
+
+                Index::create_in_dir(dir, schema)?
+            }
+            None => Index::create_in_ram(schema),
+        };
+
+        let mut writer: IndexWriter = index.writer(WRITER_MEMORY)?;
+
+        for (key, map) in &map_list.map_metadata {
+            let mut mappers = vec![map.level_author_name().to_string()];
+            mappers.extend(map.uploader_name.clone());
+            mappers.extend(map.collaborators.iter().map(|mapper| mapper.name.clone()));
+
+            writer.add_document(doc!(
+                fields.key => key.as_str(),
+                fields.song_name => map.song_name(),
+                fields.song_sub_name => map.song_sub_name(),
+                fields.song_author => map.song_author_name(),
+                fields.mapper => mappers.join(" "),
+            ))?;
+        }
+
+        writer.commit()?;
+
+        info!("Indexed {} maps for search", map_list.map_metadata.len());
+
+        Self::from_index(index, fields)
+    }
+
+    /// Opens an index an earlier `build` left in `dir`.
+    pub fn open(dir: &str) -> Result<Self, CacherError> {
+        let index = Index::open_in_dir(dir)?;
+        let fields = Fields::from_schema(&index.schema())?;
+
+        Self::from_index(index, fields)
+    }
+
+    fn from_index(index: Index, fields: Fields) -> Result<Self, CacherError> {
+        // nothing writes to it after it's built
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+
+        Ok(SearchIndex {
+            index,
+            reader,
+            fields,
+        })
+    }
+
+    /// The `limit` best matches for `query`. Words match as prefixes with a typo allowed, so
+    /// half-remembered titles still turn up, and whatever the parser doesn't understand is
+    /// searched for as plain words.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, CacherError> {
+        let mut parser = QueryParser::for_index(&self.index, self.fields.searched());
+
+        for field in self.fields.searched() {
+            parser.set_field_fuzzy(field, true, 1, true);
+        }
+
+        let (query, _) = parser.parse_query_lenient(query);
+        let searcher = self.reader.searcher();
+        let mut hits = Vec::new();
+
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit.max(1)))? {
+            let doc: TantivyDocument = searcher.doc(address)?;
+
+            if let Some(key) = doc.get_first(self.fields.key).and_then(|key| key.as_str()) {
+                hits.push(SearchHit {
+                    key: key.to_string(),
+                    score,
+                });
+            }
+        }
+
+        Ok(hits)
+    }
+}
@@ -0,0 +1,190 @@
+// splitting one huge MapList into a bunch of smaller ones
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use chrono::{DateTime, Datelike};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    cacher::{checksum::sha256_file, error::CacherError, write_cache},
+    mapdata::{MapList, MapMetadata},
+};
+
+/// How many keys end up in a single shard when sharding by key range.
+const KEY_RANGE_SIZE: u32 = 0x4000;
+
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShardBy {
+    /// Group maps into fixed-size ranges of BeatSaver keys.
+    KeyRange,
+    /// Group maps by the year they were (last) published.
+    Year,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ShardEntry {
+    pub name: String,
+    pub file: String,
+    pub maps: usize,
+    pub sha256: String,
+}
+
+/// Lists every shard that was written, so clients know what to download.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ShardManifest {
+    pub shard_by: ShardBy,
+    pub total_maps: usize,
+    pub shards: Vec<ShardEntry>,
+}
+
+/// Figures out which shard a map belongs to.
+fn shard_name(map: &MapMetadata, shard_by: ShardBy) -> String {
+    match shard_by {
+        ShardBy::KeyRange => {
+            let start = map.key - (map.key % KEY_RANGE_SIZE);
+            format!("keys-{:x}-{:x}", start, start + KEY_RANGE_SIZE - 1)
+        }
+        ShardBy::Year => match DateTime::from_timestamp(map.uploaded, 0) {
+            Some(uploaded) => uploaded.year().to_string(),
+            None => "unknown".to_string(),
+        },
+    }
+}
+
+/// Splits the map list into one `MapList` per shard. Ordered by shard name.
+fn split_map_list(map_list: &MapList, shard_by: ShardBy) -> BTreeMap<String, MapList> {
+    let mut shards: BTreeMap<String, MapList> = BTreeMap::new();
+
+    for (key, map) in &map_list.map_metadata {
+        shards
+            .entry(shard_name(map, shard_by))
+            .or_insert_with(|| MapList {
+                map_metadata: BTreeMap::new(),
+                schema_version: map_list.schema_version,
+                record_checksums: Vec::new(),
+                strings: Vec::new(),
+                string_checksums: Vec::new(),
+            })
+            .map_metadata
+            .insert(key.clone(), map.clone());
+    }
+
+    shards
+}
+
+/// Writes the map list as several smaller caches into `dir`, plus a `manifest.json` listing them.
+/// `intern_names` is the same as for `write_cache`.
+pub async fn write_sharded_cache(
+    map_list: &MapList,
+    dir: &str,
+    shard_by: ShardBy,
+    intern_names: bool,
+) -> Result<(), CacherError> {
+    fs::create_dir_all(dir)?;
+
+    let mut manifest = ShardManifest {
+        shard_by,
+        total_maps: map_list.map_metadata.len(),
+        shards: Vec::new(),
+    };
+
+    for (name, shard) in split_map_list(map_list, shard_by) {
+        let file = format!("mapData.{}.proto.gz", name);
+        let path = Path::new(dir).join(&file).to_string_lossy().to_string();
+
+        write_cache(&shard, &path, intern_names).await?;
+        let sha256 = sha256_file(&path)?;
+
+        manifest.shards.push(ShardEntry {
+            name,
+            file,
+            maps: shard.map_metadata.len(),
+            sha256,
+        });
+    }
+
+    let manifest_path = Path::new(dir).join("manifest.json");
+    let manifest_json = serde_json::to_string_pretty(&manifest).unwrap();
+
+    fs::write(&manifest_path, manifest_json)?;
+    info!(
+        "Wrote {} shards, manifest saved to {}",
+        manifest.shards.len(),
+        manifest_path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cacher::fixture::generate_fixture;
+
+    #[test]
+    fn names_key_ranges() {
+        let name = |key| {
+            shard_name(
+                &MapMetadata {
+                    key,
+                    ..Default::default()
+                },
+                ShardBy::KeyRange,
+            )
+        };
+
+        assert_eq!(name(0x1), "keys-0-3fff");
+        assert_eq!(name(0x3fff), "keys-0-3fff");
+        assert_eq!(name(0x4000), "keys-4000-7fff");
+    }
+
+    #[test]
+    fn names_years() {
+        let name = |uploaded| {
+            shard_name(
+                &MapMetadata {
+                    uploaded,
+                    ..Default::default()
+                },
+                ShardBy::Year,
+            )
+        };
+
+        // 2020-09-13
+        assert_eq!(name(1600000000), "2020");
+        assert_eq!(name(i64::MAX), "unknown");
+    }
+
+    #[test]
+    fn puts_every_map_in_exactly_one_shard() {
+        // spread the fixture's keys out over a few key ranges
+        let map_list = MapList {
+            map_metadata: generate_fixture(300, 1)
+                .map_metadata
+                .into_values()
+                .map(|mut map| {
+                    map.key *= 0x100;
+                    (format!("{:x}", map.key), map)
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let shards = split_map_list(&map_list, ShardBy::KeyRange);
+        assert_eq!(shards.len(), 5);
+
+        let mut combined = BTreeMap::new();
+
+        for (name, shard) in shards {
+            for (key, map) in shard.map_metadata {
+                assert_eq!(shard_name(&map, ShardBy::KeyRange), name);
+                assert!(combined.insert(key, map).is_none());
+            }
+        }
+
+        assert_eq!(combined, map_list.map_metadata);
+    }
+}
@@ -0,0 +1,434 @@
+// where scraped maps come from, a page at a time: BeatSaver itself, or maps saved from it earlier,
+// so converting and filtering can be rerun without going online
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{BufRead, BufReader, Lines},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use tracing::{Instrument, debug, debug_span, field, info, warn};
+
+use crate::cacher::{
+    Listing, MAX_STALLS, PAGE_OVERLAP, PAGE_SIZE, ScrapeWindow,
+    api::{ApiClient, LatestPage, RejectedMap},
+    error::CacherError,
+    metrics::record_api_error,
+    ratelimit::Backoff,
+    wait_to_retry,
+};
+
+/// One page out of a `MapSource`.
+pub struct SourcePage {
+    pub page: LatestPage,
+    /// Names the maps on the page without an ID, when they're quarantined.
+    pub label: String,
+    /// How far back in upload time the source has got, for the progress bar.
+    pub cursor: DateTime<Utc>,
+    /// Failed requests since the previous page, by `ApiError::kind`.
+    pub api_errors: BTreeMap<&'static str, u64>,
+}
+
+/// Somewhere maps come from, a page at a time, before they're converted and filtered.
+pub trait MapSource: Send {
+    /// The next page, `None` once there's nothing left.
+    fn next_page(&mut self) -> BoxFuture<'_, Result<Option<SourcePage>, CacherError>>;
+}
+
+fn backoff(max_retries: u32) -> Backoff {
+    Backoff::new(
+        Duration::from_millis(500),
+        Duration::from_secs(60),
+        max_retries,
+    )
+}
+
+/// `/maps/latest` through one window of upload time, newest first.
+pub struct WindowSource<'a> {
+    client: &'a ApiClient,
+    window: ScrapeWindow,
+    current_time: DateTime<Utc>,
+    /// Every map on the previous page, to drop what the overlap brings back.
+    seen: HashSet<String>,
+    /// Pages in a row that didn't get us any further back.
+    stalls: u32,
+    backoff: Backoff,
+    /// Handed out after the page that got the cursor stuck for good.
+    stalled: Option<CacherError>,
+}
+
+impl<'a> WindowSource<'a> {
+    pub(super) fn new(client: &'a ApiClient, window: ScrapeWindow, max_retries: u32) -> Self {
+        WindowSource {
+            client,
+            current_time: window.before,
+            window,
+            seen: HashSet::new(),
+            stalls: 0,
+            backoff: backoff(max_retries),
+            stalled: None,
+        }
+    }
+}
+
+impl MapSource for WindowSource<'_> {
+    fn next_page(&mut self) -> BoxFuture<'_, Result<Option<SourcePage>, CacherError>> {
+        Box::pin(async move {
+            if let Some(err) = self.stalled.take() {
+                return Err(err);
+            }
+
+            let mut api_errors = BTreeMap::new();
+
+            loop {
+                let before = (self.current_time + PAGE_OVERLAP).min(self.window.before);
+                let span = debug_span!(
+                    "fetch_page",
+                    before = %before,
+                    maps = field::Empty,
+                    outcome = field::Empty
+                );
+                let res = self
+                    .client
+                    .latest(before, self.window.after, PAGE_SIZE)
+                    .instrument(span.clone())
+                    .await;
+
+                let mut data = match res {
+                    Ok(data) => {
+                        span.record("maps", data.len());
+                        span.record("outcome", "ok");
+                        self.backoff.reset();
+                        data
+                    }
+                    Err(err) => {
+                        span.record("outcome", err.kind());
+                        record_api_error(err.kind());
+                        *api_errors.entry(err.kind()).or_insert(0) += 1;
+
+                        if let Err(err) = wait_to_retry(err, &mut self.backoff).await {
+                            return Err(CacherError::Api {
+                                before: self.current_time,
+                                attempts: self.backoff.attempts(),
+                                source: err,
+                            });
+                        }
+                        continue;
+                    }
+                };
+
+                debug!("Obtained {} maps", data.docs.len());
+
+                let full = data.len() >= PAGE_SIZE as usize;
+                // the oldest map on the page is where the next one starts, wanted or not
+                let oldest = data.oldest_upload();
+                let page_ids: HashSet<String> = data
+                    .docs
+                    .iter()
+                    .map(|map| map.id.clone())
+                    .chain(
+                        data.rejected
+                            .iter()
+                            .filter_map(|map| map.id().map(String::from)),
+                    )
+                    .collect();
+
+                data.drop_seen(&self.seen);
+
+                // a full page of nothing new isn't the end, it's BeatSaver sending the same page
+                if data.is_empty() && !full {
+                    info!("[Scraper] No maps left before {}!", self.window.before);
+                    return Ok(None);
+                }
+
+                let label = self.current_time.timestamp().to_string();
+                self.seen = page_ids;
+
+                let progressed = oldest.is_some_and(|oldest| oldest < self.current_time);
+                if let Some(oldest) = oldest
+                    && progressed
+                {
+                    self.stalls = 0;
+                    self.current_time = oldest;
+                    debug!("current_time set to {}", self.current_time);
+                }
+
+                let cursor = self.current_time;
+
+                if !progressed {
+                    self.stalls += 1;
+
+                    if self.stalls > MAX_STALLS {
+                        self.stalled = Some(CacherError::Stalled {
+                            before: self.current_time,
+                            pages: self.stalls,
+                        });
+                    } else {
+                        // further back every time, the maps in between are lost but the rest isn't
+                        let nudge = PAGE_OVERLAP * 2_i32.pow(self.stalls);
+                        warn!(
+                            "[Scraper] Cursor stuck at {}, moving back {}s, might miss maps",
+                            self.current_time,
+                            nudge.num_seconds()
+                        );
+                        self.current_time -= nudge;
+                    }
+                }
+
+                return Ok(Some(SourcePage {
+                    page: data,
+                    label,
+                    cursor,
+                    api_errors,
+                }));
+            }
+        })
+    }
+}
+
+/// A listing, paged by number instead of by upload time, so there's no cursor to watch.
+pub struct ListingSource<'a> {
+    client: &'a ApiClient,
+    listing: &'a Listing,
+    window: ScrapeWindow,
+    page_number: u32,
+    backoff: Backoff,
+}
+
+impl<'a> ListingSource<'a> {
+    pub(super) fn new(
+        client: &'a ApiClient,
+        listing: &'a Listing,
+        window: ScrapeWindow,
+        max_retries: u32,
+    ) -> Self {
+        ListingSource {
+            client,
+            listing,
+            window,
+            page_number: 0,
+            backoff: backoff(max_retries),
+        }
+    }
+}
+
+impl MapSource for ListingSource<'_> {
+    fn next_page(&mut self) -> BoxFuture<'_, Result<Option<SourcePage>, CacherError>> {
+        Box::pin(async move {
+            let mut api_errors = BTreeMap::new();
+
+            loop {
+                let span = debug_span!(
+                    "fetch_page",
+                    listing = ?self.listing,
+                    page = self.page_number,
+                    maps = field::Empty,
+                    outcome = field::Empty
+                );
+                let fetch = async {
+                    match self.listing {
+                        Listing::Uploader(uploader) => {
+                            self.client.uploader_maps(*uploader, self.page_number).await
+                        }
+                        Listing::Search(search) => {
+                            let (after, before) = (self.window.after, self.window.before);
+                            self.client
+                                .search(search, self.page_number, after, before)
+                                .await
+                        }
+                    }
+                };
+                let res = fetch.instrument(span.clone()).await;
+
+                let data = match res {
+                    Ok(data) => {
+                        span.record("maps", data.len());
+                        span.record("outcome", "ok");
+                        self.backoff.reset();
+                        data
+                    }
+                    Err(err) => {
+                        span.record("outcome", err.kind());
+                        record_api_error(err.kind());
+                        *api_errors.entry(err.kind()).or_insert(0) += 1;
+
+                        if let Err(err) = wait_to_retry(err, &mut self.backoff).await {
+                            return Err(CacherError::Api {
+                                before: Utc::now(),
+                                attempts: self.backoff.attempts(),
+                                source: err,
+                            });
+                        }
+                        continue;
+                    }
+                };
+
+                if data.is_empty() {
+                    info!("[Scraper] No maps left in {:?}!", self.listing);
+                    return Ok(None);
+                }
+
+                let label = format!("page-{}", self.page_number);
+                self.page_number += 1;
+
+                return Ok(Some(SourcePage {
+                    cursor: data.oldest_upload().unwrap_or_else(Utc::now),
+                    page: data,
+                    label,
+                    api_errors,
+                }));
+            }
+        })
+    }
+}
+
+/// Turns saved JSON into a page: a whole page the way BeatSaver sends it, a list of maps, or
+/// just the one map.
+fn saved_page(path: &Path, json: &str) -> Result<LatestPage, CacherError> {
+    let bad = |source| CacherError::SavedMaps {
+        path: path.display().to_string(),
+        source,
+    };
+
+    let docs = match serde_json::from_str(json).map_err(bad)? {
+        Value::Object(mut page) if page.contains_key("docs") => match page.remove("docs") {
+            Some(Value::Array(docs)) => docs,
+            _ => return Err(bad(serde::de::Error::custom("docs isn't a list of maps"))),
+        },
+        Value::Array(docs) => docs,
+        map @ Value::Object(_) => vec![map],
+        _ => {
+            return Err(bad(serde::de::Error::custom(
+                "not a map, list of maps or page",
+            )));
+        }
+    };
+
+    Ok(LatestPage::from_docs(docs))
+}
+
+/// A dump with one map per line, the way BeatSaver sends them.
+pub struct DumpSource {
+    path: PathBuf,
+    lines: Lines<BufReader<File>>,
+    line_number: usize,
+}
+
+impl DumpSource {
+    pub fn open(path: &Path) -> Result<Self, CacherError> {
+        Ok(DumpSource {
+            path: path.to_path_buf(),
+            lines: BufReader::new(File::open(path)?).lines(),
+            line_number: 0,
+        })
+    }
+}
+
+impl MapSource for DumpSource {
+    fn next_page(&mut self) -> BoxFuture<'_, Result<Option<SourcePage>, CacherError>> {
+        Box::pin(async move {
+            let label = format!("line-{}", self.line_number + 1);
+            let mut docs = Vec::new();
+            let mut rejected = Vec::new();
+
+            while docs.len() + rejected.len() < PAGE_SIZE as usize {
+                let Some(line) = self.lines.next() else {
+                    break;
+                };
+                let line = line?;
+                self.line_number += 1;
+
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                // a line that isn't even JSON is quarantined like any other broken map
+                match serde_json::from_str(&line) {
+                    Ok(doc) => docs.push(doc),
+                    Err(error) => rejected.push(RejectedMap {
+                        raw: Value::String(line),
+                        error,
+                    }),
+                }
+            }
+
+            if docs.is_empty() && rejected.is_empty() {
+                info!("[Scraper] No maps left in {}!", self.path.display());
+                return Ok(None);
+            }
+
+            let mut page = LatestPage::from_docs(docs);
+            page.rejected.extend(rejected);
+
+            Ok(Some(SourcePage {
+                cursor: page.oldest_upload().unwrap_or_else(Utc::now),
+                page,
+                label,
+                api_errors: BTreeMap::new(),
+            }))
+        })
+    }
+}
+
+/// A directory of saved `.json` files, a page each, read in name order.
+pub struct DirSource {
+    files: std::vec::IntoIter<PathBuf>,
+}
+
+impl DirSource {
+    pub fn open(dir: &Path) -> Result<Self, CacherError> {
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+
+        files.sort();
+
+        Ok(DirSource {
+            files: files.into_iter(),
+        })
+    }
+}
+
+impl MapSource for DirSource {
+    fn next_page(&mut self) -> BoxFuture<'_, Result<Option<SourcePage>, CacherError>> {
+        Box::pin(async move {
+            let Some(path) = self.files.next() else {
+                return Ok(None);
+            };
+
+            let page = saved_page(&path, &fs::read_to_string(&path)?)?;
+            let label = path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+
+            Ok(Some(SourcePage {
+                cursor: page.oldest_upload().unwrap_or_else(Utc::now),
+                page,
+                label,
+                api_errors: BTreeMap::new(),
+            }))
+        })
+    }
+}
+
+/// Maps saved earlier at `path`: a directory of pages, or a dump with a map per line.
+pub fn open_saved(path: &str) -> Result<Box<dyn MapSource>, CacherError> {
+    let path = Path::new(path);
+
+    Ok(if path.is_dir() {
+        Box::new(DirSource::open(path)?)
+    } else {
+        Box::new(DumpSource::open(path)?)
+    })
+}
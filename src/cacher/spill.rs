@@ -0,0 +1,123 @@
+// parking scraped maps on disk instead of keeping them all in RAM
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+};
+
+use prost::Message;
+use tracing::{error, info, warn};
+
+use crate::{
+    cacher::{MapStore, error::CacherError, write_cache_file},
+    mapdata::MapMetadata,
+};
+
+/// Where a record lives in the spill file.
+struct RecordLocation {
+    offset: u64,
+    len: u64,
+}
+
+/// Maps written to a temp file as length-delimited `MapMetadata` records. Only the key → offset
+/// index stays in memory. Re-inserting a key appends a new record and points the index at it.
+pub struct SpillStore {
+    path: String,
+    writer: BufWriter<File>,
+    offset: u64,
+    index: BTreeMap<String, RecordLocation>,
+    newest_update: i64,
+}
+
+impl SpillStore {
+    /// Starts a new spill file at `path`, overwriting whatever was there.
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(SpillStore {
+            path: path.to_string(),
+            writer: BufWriter::new(File::create(path)?),
+            offset: 0,
+            index: BTreeMap::new(),
+            newest_update: 0,
+        })
+    }
+
+    /// Reads every stored map back in key order.
+    fn entries(&mut self) -> io::Result<impl Iterator<Item = io::Result<(&String, MapMetadata)>>> {
+        self.writer.flush()?;
+
+        let mut reader = File::open(&self.path)?;
+        let mut buf = Vec::new();
+
+        Ok(self.index.iter().map(move |(key, location)| {
+            buf.resize(location.len as usize, 0);
+
+            reader.seek(SeekFrom::Start(location.offset))?;
+            reader.read_exact(&mut buf)?;
+
+            let map = MapMetadata::decode_length_delimited(buf.as_slice())?;
+
+            Ok((key, map))
+        }))
+    }
+}
+
+impl MapStore for SpillStore {
+    fn insert(&mut self, key: String, map: MapMetadata) -> bool {
+        let record = map.encode_length_delimited_to_vec();
+
+        if let Err(e) = self.writer.write_all(&record) {
+            // not much we can do mid-scrape, the final write will come up short and say so
+            error!("Couldn't spill {} to {}: {:?}", key, self.path, e);
+            return false;
+        }
+
+        self.newest_update = self.newest_update.max(map.last_updated);
+        let previous = self.index.insert(
+            key,
+            RecordLocation {
+                offset: self.offset,
+                len: record.len() as u64,
+            },
+        );
+        self.offset += record.len() as u64;
+
+        previous.is_none()
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+/// Assembles the final cache from the spill file, without loading it all into memory. The spill
+/// file is deleted afterwards. Hands back how big the payload was before it was compressed.
+pub async fn write_spilled_cache(
+    mut store: SpillStore,
+    path: &str,
+    intern_names: bool,
+) -> Result<u64, CacherError> {
+    let map_count = store.len();
+    let created = store.newest_update;
+
+    let result = store
+        .entries()
+        .and_then(|entries| write_cache_file(entries, map_count, created, intern_names, path));
+
+    // the writer has to be closed first, or Windows won't let go of the file
+    let SpillStore {
+        path: spill_path,
+        writer,
+        ..
+    } = store;
+    drop(writer);
+
+    if let Err(e) = fs::remove_file(&spill_path) {
+        warn!("Couldn't clean up spill file {}: {:?}", spill_path, e);
+    }
+
+    let uncompressed_len = result?;
+    info!("Saved to {}", path);
+
+    Ok(uncompressed_len)
+}
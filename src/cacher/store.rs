@@ -0,0 +1,390 @@
+// other places a finished cache gets saved, on top of --output, and that the daemon can serve
+// from instead of the file
+
+mod tables;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use futures::future::BoxFuture;
+use prost::Message;
+use redis::AsyncCommands;
+use s3::Bucket;
+use tokio_postgres::NoTls;
+use tracing::{error, info};
+
+use crate::{
+    cacher::{decode_cache, store::tables::PostgresTablesStore, upload::s3_bucket},
+    config::{S3Config, StoreConfig},
+    mapdata::MapList,
+};
+
+/// Somewhere a finished cache can be saved and read back from.
+pub trait CacheStore: Send + Sync {
+    /// For logs.
+    fn describe(&self) -> String;
+
+    /// Saves the cache that was just written to `file`, which holds the same maps as `map_list`.
+    fn save<'a>(
+        &'a self,
+        map_list: &'a MapList,
+        file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// The cache as it was last saved here, `None` if nothing has been yet.
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>>;
+}
+
+/// A copy of the file somewhere else on disk, like a directory a web server serves.
+struct FileStore {
+    path: String,
+}
+
+impl CacheStore for FileStore {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn save<'a>(
+        &'a self,
+        _map_list: &'a MapList,
+        file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            // copied next to it first, so nobody reading it sees half a cache
+            let tmp = format!("{}.tmp", self.path);
+            fs::copy(file, &tmp)?;
+            fs::rename(&tmp, &self.path)?;
+            Ok(())
+        })
+    }
+
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>> {
+        Box::pin(async move {
+            match fs::read(&self.path) {
+                Ok(data) => Ok(Some(decode_cache(&data)?)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+/// The file as one object in a bucket.
+struct S3Store {
+    bucket: Box<Bucket>,
+    key: String,
+}
+
+impl CacheStore for S3Store {
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket.name(), self.key)
+    }
+
+    fn save<'a>(
+        &'a self,
+        _map_list: &'a MapList,
+        file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let res = self
+                .bucket
+                .put_object_with_content_type(&self.key, &fs::read(file)?, "application/gzip")
+                .await?;
+
+            if !(200..300).contains(&res.status_code()) {
+                anyhow::bail!("S3 said {} to {}", res.status_code(), self.key);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>> {
+        Box::pin(async move {
+            let res = self.bucket.get_object(&self.key).await?;
+
+            match res.status_code() {
+                404 => Ok(None),
+                200..300 => Ok(Some(decode_cache(res.as_slice())?)),
+                status => anyhow::bail!("S3 said {} to {}", status, self.key),
+            }
+        })
+    }
+}
+
+/// The file as one Redis string, and maybe every map on its own too.
+struct RedisStore {
+    client: redis::Client,
+    key: String,
+    /// Where single maps go, when they're pushed at all.
+    map_prefix: Option<String>,
+}
+
+/// How many maps go to Redis in one round trip.
+const REDIS_BATCH: usize = 1000;
+
+impl RedisStore {
+    /// Pushes every map as protobuf under its key and its hash, then drops the ones from last
+    /// time that aren't there anymore. What was pushed is kept in the `<prefix>index` set.
+    async fn push_maps(
+        &self,
+        redis: &mut redis::aio::MultiplexedConnection,
+        prefix: &str,
+        map_list: &MapList,
+    ) -> anyhow::Result<()> {
+        let index = format!("{}index", prefix);
+        let mut pushed = HashSet::new();
+
+        let maps: Vec<_> = map_list.map_metadata.iter().collect();
+        for batch in maps.chunks(REDIS_BATCH) {
+            let mut pipe = redis::pipe();
+
+            for (key, map) in batch {
+                let data = map.encode_to_vec();
+                let by_key = format!("{}key:{}", prefix, key);
+                let by_hash = format!("{}hash:{}", prefix, map.hash.to_lowercase());
+
+                pipe.set(&by_key, &data).ignore();
+                pipe.set(&by_hash, &data).ignore();
+                pushed.insert(by_key);
+                pushed.insert(by_hash);
+            }
+
+            let _: () = pipe.query_async(redis).await?;
+        }
+
+        let previous: HashSet<String> = redis.smembers(&index).await?;
+        let stale: Vec<&String> = previous.difference(&pushed).collect();
+
+        for batch in stale.chunks(REDIS_BATCH) {
+            let _: () = redis.del(batch).await?;
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&index).ignore();
+        for batch in pushed.iter().collect::<Vec<_>>().chunks(REDIS_BATCH) {
+            pipe.sadd(&index, batch).ignore();
+        }
+        let _: () = pipe.query_async(redis).await?;
+
+        info!(
+            "[Stores] Pushed {} maps to redis, dropped {} stale keys",
+            map_list.map_metadata.len(),
+            stale.len()
+        );
+
+        Ok(())
+    }
+}
+
+impl CacheStore for RedisStore {
+    fn describe(&self) -> String {
+        format!("redis key {}", self.key)
+    }
+
+    fn save<'a>(
+        &'a self,
+        map_list: &'a MapList,
+        file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut redis = self.client.get_multiplexed_async_connection().await?;
+            let _: () = redis.set(&self.key, fs::read(file)?).await?;
+
+            if let Some(prefix) = &self.map_prefix {
+                self.push_maps(&mut redis, prefix, map_list).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>> {
+        Box::pin(async move {
+            let mut redis = self.client.get_multiplexed_async_connection().await?;
+            let data: Option<Vec<u8>> = redis.get(&self.key).await?;
+
+            Ok(data.map(|data| decode_cache(&data)).transpose()?)
+        })
+    }
+}
+
+/// The file as a row in a table, one row per store name.
+struct PostgresStore {
+    url: String,
+    table: String,
+    name: String,
+}
+
+async fn connect_postgres(url: &str) -> anyhow::Result<tokio_postgres::Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("PostgreSQL connection failed: {:?}", e);
+        }
+    });
+
+    Ok(client)
+}
+
+/// Table names go straight into the SQL, so they can't be anything that isn't a plain name.
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl CacheStore for PostgresStore {
+    fn describe(&self) -> String {
+        format!("PostgreSQL table {} ({})", self.table, self.name)
+    }
+
+    fn save<'a>(
+        &'a self,
+        map_list: &'a MapList,
+        file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let client = connect_postgres(&self.url).await?;
+
+            client
+                .batch_execute(&format!(
+                    "CREATE TABLE IF NOT EXISTS {} (
+                        name TEXT PRIMARY KEY,
+                        data BYTEA NOT NULL,
+                        map_count BIGINT NOT NULL,
+                        saved_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                    )",
+                    self.table
+                ))
+                .await?;
+
+            client
+                .execute(
+                    &format!(
+                        "INSERT INTO {} (name, data, map_count) VALUES ($1, $2, $3)
+                         ON CONFLICT (name) DO UPDATE
+                         SET data = EXCLUDED.data, map_count = EXCLUDED.map_count,
+                             saved_at = now()",
+                        self.table
+                    ),
+                    &[
+                        &self.name,
+                        &fs::read(file)?,
+                        &(map_list.map_metadata.len() as i64),
+                    ],
+                )
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>> {
+        Box::pin(async move {
+            let client = connect_postgres(&self.url).await?;
+            let row = client
+                .query_opt(
+                    &format!("SELECT data FROM {} WHERE name = $1", self.table),
+                    &[&self.name],
+                )
+                .await;
+
+            // no table yet is the same as nothing saved yet
+            let row = match row {
+                Ok(row) => row,
+                Err(e) if e.code() == Some(&tokio_postgres::error::SqlState::UNDEFINED_TABLE) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            match row {
+                Some(row) => Ok(Some(decode_cache(row.get::<_, &[u8]>(0))?)),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+/// Sets up the store `[stores.<name>]` describes. Nothing connects until it's used.
+pub fn open_store(
+    name: &str,
+    config: &StoreConfig,
+    s3: &S3Config,
+) -> anyhow::Result<Box<dyn CacheStore>> {
+    Ok(match config {
+        StoreConfig::File { path } => Box::new(FileStore { path: path.clone() }),
+        StoreConfig::S3 { bucket, key } => Box::new(S3Store {
+            bucket: s3_bucket(bucket, s3)?,
+            key: key.clone(),
+        }),
+        StoreConfig::Redis {
+            url,
+            key,
+            per_map,
+            map_prefix,
+        } => Box::new(RedisStore {
+            client: redis::Client::open(url.as_str())?,
+            key: key.clone(),
+            map_prefix: per_map.then(|| map_prefix.clone()),
+        }),
+        StoreConfig::Postgres { url, table } => {
+            if !is_identifier(table) {
+                anyhow::bail!("{} isn't a valid table name", table);
+            }
+
+            Box::new(PostgresStore {
+                url: url.clone(),
+                table: table.clone(),
+                name: name.to_string(),
+            })
+        }
+        StoreConfig::PostgresTables { url, prefix } => {
+            if !prefix.is_empty() && !is_identifier(prefix) {
+                anyhow::bail!("{} isn't a valid table prefix", prefix);
+            }
+
+            Box::new(PostgresTablesStore {
+                url: url.clone(),
+                prefix: prefix.clone(),
+            })
+        }
+    })
+}
+
+/// Saves a finished cache to every configured store. One failing doesn't stop the rest, but
+/// still fails the whole thing once they've all had a go.
+pub async fn save_to_stores(
+    stores: &BTreeMap<String, StoreConfig>,
+    s3: &S3Config,
+    map_list: &MapList,
+    file: &Path,
+) -> anyhow::Result<()> {
+    let mut failed = Vec::new();
+
+    for (name, config) in stores {
+        let saved = match open_store(name, config, s3) {
+            Ok(store) => store.save(map_list, file).await.map(|_| store.describe()),
+            Err(e) => Err(e),
+        };
+
+        match saved {
+            Ok(describe) => info!("[Stores] Saved to {} ({})", name, describe),
+            Err(e) => {
+                error!("[Stores] Couldn't save to {}: {:?}", name, e);
+                failed.push(name.as_str());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!("couldn't save to {}", failed.join(", "));
+    }
+
+    Ok(())
+}
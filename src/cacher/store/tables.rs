@@ -0,0 +1,256 @@
+// the cache as real PostgreSQL tables, one row per map and per difficulty, so it can be queried
+// with SQL instead of decoded
+
+use std::{collections::BTreeMap, path::Path};
+
+use futures::future::BoxFuture;
+use prost::Message;
+use tracing::info;
+
+use crate::{
+    cacher::{
+        envelope::FORMAT_VERSION,
+        store::{CacheStore, connect_postgres},
+    },
+    mapdata::{MapList, MapMetadata},
+};
+
+pub(super) struct PostgresTablesStore {
+    pub(super) url: String,
+    /// Goes in front of the table names, already checked to be a plain name.
+    pub(super) prefix: String,
+}
+
+impl PostgresTablesStore {
+    fn schema(&self) -> String {
+        let p = &self.prefix;
+
+        // timestamps are unix seconds, same as in the cache. `data` is the whole map as protobuf,
+        // which is what a run compares against to tell if anything changed
+        format!(
+            "CREATE TABLE IF NOT EXISTS {p}maps (
+                key TEXT PRIMARY KEY,
+                hash TEXT NOT NULL,
+                song_name TEXT,
+                song_sub_name TEXT,
+                song_author_name TEXT,
+                level_author_name TEXT,
+                uploader_id BIGINT,
+                uploader_name TEXT,
+                curator_name TEXT,
+                duration INTEGER NOT NULL,
+                bpm REAL,
+                uploaded BIGINT NOT NULL,
+                last_updated BIGINT NOT NULL,
+                mods INTEGER NOT NULL,
+                upvotes INTEGER NOT NULL,
+                downvotes INTEGER NOT NULL,
+                score REAL,
+                plays BIGINT,
+                downloads BIGINT,
+                tags TEXT[] NOT NULL,
+                data BYTEA NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {p}maps_hash ON {p}maps (hash);
+            CREATE TABLE IF NOT EXISTS {p}difficulties (
+                key TEXT NOT NULL REFERENCES {p}maps (key) ON DELETE CASCADE,
+                characteristic TEXT NOT NULL,
+                difficulty TEXT NOT NULL,
+                label TEXT,
+                njs REAL NOT NULL,
+                notes INTEGER NOT NULL,
+                nps REAL,
+                seconds REAL,
+                mods INTEGER NOT NULL,
+                ss_ranked BOOLEAN NOT NULL,
+                ss_stars REAL,
+                bl_ranked BOOLEAN NOT NULL,
+                bl_stars REAL,
+                PRIMARY KEY (key, characteristic, difficulty)
+            );"
+        )
+    }
+
+    /// Inserts a map, or updates it if it's different from what's there. Returns whether
+    /// anything was written.
+    async fn upsert_map(
+        &self,
+        tx: &tokio_postgres::Transaction<'_>,
+        statement: &tokio_postgres::Statement,
+        key: &str,
+        map: &MapMetadata,
+    ) -> anyhow::Result<bool> {
+        let written = tx
+            .execute(
+                statement,
+                &[
+                    &key,
+                    &map.hash.to_lowercase(),
+                    &map.song_name,
+                    &map.song_sub_name,
+                    &map.song_author_name,
+                    &map.level_author_name,
+                    &map.uploader_id.map(i64::from),
+                    &map.uploader_name,
+                    &map.curator_name,
+                    &(map.duration as i32),
+                    &map.bpm,
+                    &map.uploaded,
+                    &map.last_updated,
+                    &(map.mods as i32),
+                    &(map.votes.up as i32),
+                    &(map.votes.down as i32),
+                    &map.score,
+                    &map.plays.map(i64::from),
+                    &map.downloads.map(i64::from),
+                    &map.tags,
+                    &map.encode_to_vec(),
+                ],
+            )
+            .await?;
+
+        Ok(written > 0)
+    }
+}
+
+impl CacheStore for PostgresTablesStore {
+    fn describe(&self) -> String {
+        format!(
+            "PostgreSQL tables {}maps and {}difficulties",
+            self.prefix, self.prefix
+        )
+    }
+
+    /// Upserts every map, rewriting the difficulties of the ones that changed, and deletes the
+    /// maps that aren't in the cache anymore. All in one transaction, so readers never see half
+    /// a run.
+    fn save<'a>(
+        &'a self,
+        map_list: &'a MapList,
+        _file: &'a Path,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let p = &self.prefix;
+            let mut client = connect_postgres(&self.url).await?;
+            client.batch_execute(&self.schema()).await?;
+
+            let tx = client.transaction().await?;
+
+            let upsert = tx
+                .prepare(&format!(
+                    "INSERT INTO {p}maps VALUES
+                     ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                      $18, $19, $20, $21)
+                     ON CONFLICT (key) DO UPDATE SET
+                        hash = EXCLUDED.hash, song_name = EXCLUDED.song_name,
+                        song_sub_name = EXCLUDED.song_sub_name,
+                        song_author_name = EXCLUDED.song_author_name,
+                        level_author_name = EXCLUDED.level_author_name,
+                        uploader_id = EXCLUDED.uploader_id, uploader_name = EXCLUDED.uploader_name,
+                        curator_name = EXCLUDED.curator_name, duration = EXCLUDED.duration,
+                        bpm = EXCLUDED.bpm, uploaded = EXCLUDED.uploaded,
+                        last_updated = EXCLUDED.last_updated, mods = EXCLUDED.mods,
+                        upvotes = EXCLUDED.upvotes, downvotes = EXCLUDED.downvotes,
+                        score = EXCLUDED.score, plays = EXCLUDED.plays,
+                        downloads = EXCLUDED.downloads, tags = EXCLUDED.tags, data = EXCLUDED.data
+                     WHERE {p}maps.data IS DISTINCT FROM EXCLUDED.data"
+                ))
+                .await?;
+            let clear_difficulties = tx
+                .prepare(&format!("DELETE FROM {p}difficulties WHERE key = $1"))
+                .await?;
+            let insert_difficulty = tx
+                .prepare(&format!(
+                    "INSERT INTO {p}difficulties VALUES
+                     ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                     ON CONFLICT DO NOTHING"
+                ))
+                .await?;
+
+            let mut changed = 0;
+
+            for (key, map) in &map_list.map_metadata {
+                if !self.upsert_map(&tx, &upsert, key, map).await? {
+                    continue;
+                }
+
+                changed += 1;
+                tx.execute(&clear_difficulties, &[key]).await?;
+
+                for diff in &map.difficulties {
+                    let (ss, bl) = (&diff.ranked.score_saber, &diff.ranked.beat_leader);
+
+                    tx.execute(
+                        &insert_difficulty,
+                        &[
+                            key,
+                            &diff.characteristic_name,
+                            &diff.difficulty_name,
+                            &diff.label,
+                            &diff.njs,
+                            &(diff.notes as i32),
+                            &diff.nps,
+                            &diff.seconds,
+                            &(diff.mods as i32),
+                            &ss.is_ranked,
+                            &ss.is_ranked.then_some(ss.stars),
+                            &bl.is_ranked,
+                            &bl.is_ranked.then_some(bl.stars),
+                        ],
+                    )
+                    .await?;
+                }
+            }
+
+            let keys: Vec<&str> = map_list.map_metadata.keys().map(String::as_str).collect();
+            let removed = tx
+                .execute(
+                    &format!("DELETE FROM {p}maps WHERE NOT (key = ANY($1))"),
+                    &[&keys],
+                )
+                .await?;
+
+            tx.commit().await?;
+
+            info!(
+                "[Stores] {} maps written to {}maps, {} removed",
+                changed, p, removed
+            );
+
+            Ok(())
+        })
+    }
+
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<Option<MapList>>> {
+        Box::pin(async move {
+            let client = connect_postgres(&self.url).await?;
+            let rows = client
+                .query(&format!("SELECT key, data FROM {}maps", self.prefix), &[])
+                .await;
+
+            // no table yet is the same as nothing saved yet
+            let rows = match rows {
+                Ok(rows) => rows,
+                Err(e) if e.code() == Some(&tokio_postgres::error::SqlState::UNDEFINED_TABLE) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut map_metadata = BTreeMap::new();
+
+            for row in rows {
+                let data: &[u8] = row.get(1);
+                map_metadata.insert(row.get(0), MapMetadata::decode(data)?);
+            }
+
+            Ok(Some(MapList {
+                map_metadata,
+                schema_version: Some(FORMAT_VERSION.into()),
+                record_checksums: Vec::new(),
+                strings: Vec::new(),
+                string_checksums: Vec::new(),
+            }))
+        })
+    }
+}
@@ -0,0 +1,45 @@
+// what a run did, for CI jobs and scripts that don't want to read logs
+
+use std::fs;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::cacher::{ScrapeStats, error::CacherError, usage::ResourceUsage};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunResult {
+    Finished,
+    Interrupted,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub result: RunResult,
+    /// Nothing was written, `output_bytes` is an estimate.
+    pub dry_run: bool,
+    /// What went wrong, for failed runs.
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub stats: ScrapeStats,
+    /// Maps in the cache that was written, if one was.
+    pub total_maps: Option<usize>,
+    /// Wall time, CPU time, peak RSS and bytes downloaded and written.
+    #[serde(flatten)]
+    pub usage: ResourceUsage,
+    /// The cache (or shard manifest) that was written, if any.
+    pub output: Option<String>,
+    pub output_bytes: Option<u64>,
+}
+
+/// Writes the summary as pretty JSON to `path`.
+pub fn write_summary(summary: &RunSummary, path: &str) -> Result<(), CacherError> {
+    let json = serde_json::to_string_pretty(summary).unwrap();
+
+    fs::write(path, json)?;
+    info!("Saved run summary to {}", path);
+
+    Ok(())
+}
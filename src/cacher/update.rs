@@ -0,0 +1,150 @@
+// keeping an existing cache alive between --update runs
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use tracing::{error, info, warn};
+
+use crate::{
+    cacher::{
+        envelope,
+        error::CacherError,
+        journal::{read_cache_with_journal, remove_journal},
+        read_cache,
+    },
+    mapdata::MapList,
+};
+
+/// How many old copies of the cache to keep around.
+const MAX_BACKUPS: usize = 3;
+
+/// Backups of `path`, newest first. They're named `<file>.<unix seconds>.bak`.
+fn backup_paths(path: &str) -> Vec<(i64, PathBuf)> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    let mut backups: Vec<(i64, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let timestamp = name.strip_prefix(&prefix)?.strip_suffix(".bak")?;
+
+                Some((timestamp.parse().ok()?, entry.path()))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    backups.sort_by(|a, b| b.0.cmp(&a.0));
+    backups
+}
+
+/// Copies the current cache to a timestamped backup, dropping the oldest ones past `MAX_BACKUPS`.
+pub fn backup_cache(path: &str) -> Result<(), CacherError> {
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+
+    let backup = format!("{}.{}.bak", path, chrono::Utc::now().timestamp());
+
+    fs::copy(path, &backup)?;
+
+    info!("Backed up {} to {}", path, backup);
+
+    for (_, old) in backup_paths(path).into_iter().skip(MAX_BACKUPS) {
+        if let Err(e) = fs::remove_file(&old) {
+            warn!("Couldn't remove old backup {}: {:?}", old.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends a line to `<path>.corruption.log` so someone can look into it later.
+fn record_corruption(path: &str, reason: &str) {
+    let log_path = format!("{}.corruption.log", path);
+    let line = format!("{} {}: {}\n", chrono::Utc::now().to_rfc3339(), path, reason);
+
+    let file = OpenOptions::new().create(true).append(true).open(&log_path);
+
+    if let Err(e) = file.and_then(|mut file| file.write_all(line.as_bytes())) {
+        error!("Couldn't write to {}: {:?}", log_path, e);
+    }
+}
+
+/// Loads the cache an `--update` run should start from.
+///
+/// If the cache is corrupt, the newest backup that still decodes is used instead. If there's
+/// nothing usable, `Ok(None)` means "do a full rescrape", which only happens when
+/// `allow_full_rescrape` is set; otherwise this errors out.
+pub fn load_existing_cache(
+    path: &str,
+    allow_full_rescrape: bool,
+) -> anyhow::Result<Option<MapList>> {
+    if !Path::new(path).exists() {
+        info!("No existing cache at {}, starting from scratch", path);
+        return Ok(None);
+    }
+
+    // a cache from before the envelope isn't corrupt, just old. it can't have a journal, and this
+    // run writes it in the current format
+    let read = match envelope::is_legacy_file(path) {
+        Ok(true) => {
+            info!("{} is from before the envelope, upgrading it", path);
+            read_cache(path)
+        }
+        _ => read_cache_with_journal(path),
+    };
+
+    let err = match read {
+        Ok(map_list) => return Ok(Some(map_list)),
+        Err(e) => e,
+    };
+
+    error!("Existing cache {} is corrupt: {:?}", path, err);
+    record_corruption(path, &err.to_string());
+
+    for (_, backup) in backup_paths(path) {
+        let backup = backup.to_string_lossy();
+
+        match read_cache(&backup) {
+            Ok(map_list) => {
+                warn!("Recovered from backup {}", backup);
+                record_corruption(path, &format!("recovered from {}", backup));
+
+                // it was on top of the broken cache, the next round writes the cache in full
+                if let Err(e) = remove_journal(path) {
+                    warn!("Couldn't remove the journal of {}: {:?}", path, e);
+                }
+                return Ok(Some(map_list));
+            }
+            Err(e) => {
+                warn!("Backup {} is no good either: {:?}", backup, e);
+            }
+        }
+    }
+
+    if allow_full_rescrape {
+        warn!("No usable backups, doing a full rescrape");
+        record_corruption(path, "no usable backups, doing a full rescrape");
+        return Ok(None);
+    }
+
+    record_corruption(path, "no usable backups, giving up");
+    bail!(
+        "No usable cache or backups for {} (pass --allow-full-rescrape to rebuild it)",
+        path
+    )
+}
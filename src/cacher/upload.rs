@@ -0,0 +1,325 @@
+// pushing a finished cache somewhere public, so mirrors don't need their own sync script
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use s3::{Bucket, Region, creds::Credentials};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::config::{GitHubConfig, HttpConfig, PutConfig, S3Config};
+
+/// Where `--upload` (or `target` under `[upload]`) puts things.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum UploadTarget {
+    /// `s3://bucket/prefix`, the prefix without a trailing slash and empty for the root.
+    S3 { bucket: String, prefix: String },
+    /// `github://owner/repo`, a new release every run.
+    GitHub { owner: String, repo: String },
+    /// An `http://` or `https://` URL every file gets PUT under, which is also how WebDAV
+    /// takes uploads.
+    Put { url: String },
+}
+
+impl TryFrom<String> for UploadTarget {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        parse_upload_target(&value)
+    }
+}
+
+/// Parses `s3://bucket/prefix`, `github://owner/repo` or an `http(s)://` URL.
+pub fn parse_upload_target(value: &str) -> Result<UploadTarget, String> {
+    if let Some(rest) = value.strip_prefix("s3://") {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+
+        if bucket.is_empty() {
+            return Err(format!("{} has no bucket", value));
+        }
+
+        return Ok(UploadTarget::S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        });
+    }
+
+    if let Some(rest) = value.strip_prefix("github://") {
+        return match rest.trim_matches('/').split_once('/') {
+            Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+                Ok(UploadTarget::GitHub {
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                })
+            }
+            _ => Err(format!("{} isn't github://owner/repo", value)),
+        };
+    }
+
+    if value.starts_with("http://") || value.starts_with("https://") {
+        return Ok(UploadTarget::Put {
+            url: value.trim_end_matches('/').to_string(),
+        });
+    }
+
+    Err(format!(
+        "{} isn't an s3://, github:// or http(s):// URL",
+        value
+    ))
+}
+
+/// How to log in to each kind of target.
+pub struct UploadConfigs<'a> {
+    pub http: &'a HttpConfig,
+    pub s3: &'a S3Config,
+    pub github: &'a GitHubConfig,
+    pub put: &'a PutConfig,
+}
+
+fn file_name(file: &Path) -> String {
+    file.file_name().unwrap().to_string_lossy().to_string()
+}
+
+/// Every file a run published, in the order they should go up: the data first, then the files
+/// that describe it, so nobody downloads a checksum or manifest for something that isn't there
+/// yet. `path` is the cache, or the manifest when the cache is sharded into `shard_dir`.
+pub fn artifact_files(path: &str, shard_dir: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    if let Some(dir) = shard_dir {
+        let mut shards = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let file = entry?.path();
+            let is_manifest = file
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("manifest.json"));
+
+            if file.is_file() && !is_manifest {
+                shards.push(file);
+            }
+        }
+
+        shards.sort();
+        files.extend(shards);
+    }
+
+    files.push(PathBuf::from(path));
+
+    for sidecar in [format!("{}.sha256", path), format!("{}.sig", path)] {
+        if Path::new(&sidecar).exists() {
+            files.push(PathBuf::from(sidecar));
+        }
+    }
+
+    Ok(files)
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => "application/gzip",
+        Some("json") => "application/json",
+        Some("sha256" | "sig") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+pub(crate) fn s3_bucket(name: &str, config: &S3Config) -> anyhow::Result<Box<Bucket>> {
+    let region_name = config
+        .region
+        .clone()
+        .unwrap_or_else(|| "us-east-1".to_string());
+    let region = match &config.endpoint {
+        Some(endpoint) => Region::Custom {
+            region: region_name,
+            endpoint: endpoint.clone(),
+        },
+        None => region_name.parse()?,
+    };
+
+    // the config wins, otherwise AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY
+    let credentials = match (&config.access_key_id, &config.secret_access_key) {
+        (Some(key), Some(secret)) => Credentials::new(Some(key), Some(secret), None, None, None)?,
+        _ => Credentials::from_env()?,
+    };
+
+    let bucket = Bucket::new(name, region, credentials)?;
+
+    Ok(if config.path_style {
+        bucket.with_path_style()
+    } else {
+        bucket
+    })
+}
+
+async fn upload_to_s3(
+    bucket_name: &str,
+    prefix: &str,
+    config: &S3Config,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    let bucket = s3_bucket(bucket_name, config)?;
+
+    for file in files {
+        let key = if prefix.is_empty() {
+            file_name(file)
+        } else {
+            format!("{}/{}", prefix, file_name(file))
+        };
+        let data = fs::read(file)?;
+
+        let res = bucket
+            .put_object_with_content_type(&key, &data, content_type(file))
+            .await?;
+
+        if !(200..300).contains(&res.status_code()) {
+            anyhow::bail!(
+                "S3 said {} to {}: {}",
+                res.status_code(),
+                key,
+                String::from_utf8_lossy(res.as_slice())
+            );
+        }
+
+        info!(
+            "Uploaded {} to s3://{}/{}",
+            file.display(),
+            bucket_name,
+            key
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NewRelease<'a> {
+    tag_name: &'a str,
+    name: &'a str,
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    html_url: String,
+    /// Comes with a `{?name,label}` template on the end.
+    upload_url: String,
+}
+
+/// Makes a new release tagged for this run, and attaches every file to it.
+async fn upload_to_github(
+    owner: &str,
+    repo: &str,
+    config: &GitHubConfig,
+    http: &HttpConfig,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    let token = config
+        .token
+        .clone()
+        .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!("No GitHub token, set token under [github] or GITHUB_TOKEN")
+        })?;
+    let client = http.client()?;
+
+    let tag = format!(
+        "{}{}",
+        config.tag_prefix,
+        Utc::now().format("%Y%m%d-%H%M%S")
+    );
+    let release: Release = client
+        .post(format!(
+            "https://api.github.com/repos/{}/{}/releases",
+            owner, repo
+        ))
+        .bearer_auth(&token)
+        .header(ACCEPT, "application/vnd.github+json")
+        .json(&NewRelease {
+            tag_name: &tag,
+            name: &tag,
+            body: "Made by beatsaver-cacher.",
+        })
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let upload_url = release
+        .upload_url
+        .split('{')
+        .next()
+        .unwrap_or(&release.upload_url)
+        .to_string();
+
+    for file in files {
+        client
+            .post(&upload_url)
+            .query(&[("name", file_name(file))])
+            .bearer_auth(&token)
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(CONTENT_TYPE, content_type(file))
+            .body(fs::read(file)?)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        info!("Attached {} to release {}", file.display(), tag);
+    }
+
+    info!("Published release {}", release.html_url);
+
+    Ok(())
+}
+
+/// PUTs every file to `<url>/<file name>`.
+async fn upload_with_put(
+    url: &str,
+    config: &PutConfig,
+    http: &HttpConfig,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    let client = http.client()?;
+
+    for file in files {
+        let target = format!("{}/{}", url, file_name(file));
+        let mut request = client
+            .put(&target)
+            .header(CONTENT_TYPE, content_type(file))
+            .body(fs::read(file)?);
+
+        if let Some(token) = &config.bearer_token {
+            request = request.bearer_auth(token);
+        } else if let Some(username) = &config.username {
+            request = request.basic_auth(username, config.password.as_ref());
+        }
+
+        request.send().await?.error_for_status()?;
+        info!("Uploaded {} to {}", file.display(), target);
+    }
+
+    Ok(())
+}
+
+/// Uploads `files` to the target, one after the other.
+pub async fn upload_files(
+    target: &UploadTarget,
+    configs: &UploadConfigs<'_>,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    match target {
+        UploadTarget::S3 { bucket, prefix } => {
+            upload_to_s3(bucket, prefix, configs.s3, files).await
+        }
+        UploadTarget::GitHub { owner, repo } => {
+            upload_to_github(owner, repo, configs.github, configs.http, files).await
+        }
+        UploadTarget::Put { url } => upload_with_put(url, configs.put, configs.http, files).await,
+    }
+}
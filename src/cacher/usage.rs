@@ -0,0 +1,105 @@
+// how much of the machine a run actually used
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+use tracing::info;
+
+static BYTES_DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+/// CPU time the process had used when the run started, the daemon does one run after the other.
+static CPU_TIME_AT_START: Mutex<Option<f64>> = Mutex::new(None);
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ResourceUsage {
+    pub wall_time_secs: f64,
+    /// The most the process has used so far, which for the daemon can be from an earlier run.
+    /// `None` on platforms we can't ask.
+    pub peak_rss_bytes: Option<u64>,
+    /// User + system time. `None` on platforms we can't ask.
+    pub cpu_time_secs: Option<f64>,
+    pub bytes_downloaded: u64,
+    pub bytes_written: u64,
+}
+
+/// Counts bytes of API responses.
+pub fn record_downloaded(bytes: u64) {
+    BYTES_DOWNLOADED.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Counts bytes written to output files.
+pub fn record_written(bytes: u64) {
+    BYTES_WRITTEN.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Peak RSS in bytes and CPU time in seconds, straight from `getrusage`.
+#[cfg(unix)]
+fn process_usage() -> (Option<u64>, Option<f64>) {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return (None, None);
+    }
+
+    // macOS reports bytes, everything else reports kilobytes
+    let peak_rss = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64
+    } else {
+        usage.ru_maxrss as u64 * 1024
+    };
+
+    let cpu_time = (usage.ru_utime.tv_sec + usage.ru_stime.tv_sec) as f64
+        + (usage.ru_utime.tv_usec + usage.ru_stime.tv_usec) as f64 / 1_000_000.0;
+
+    (Some(peak_rss), Some(cpu_time))
+}
+
+#[cfg(not(unix))]
+fn process_usage() -> (Option<u64>, Option<f64>) {
+    (None, None)
+}
+
+/// Starts counting from zero for a new run.
+pub fn reset_usage() {
+    BYTES_DOWNLOADED.store(0, Ordering::Relaxed);
+    BYTES_WRITTEN.store(0, Ordering::Relaxed);
+    *CPU_TIME_AT_START.lock().unwrap() = process_usage().1;
+}
+
+/// Collects everything measured since `started`.
+pub fn measure(started: Instant) -> ResourceUsage {
+    let (peak_rss_bytes, cpu_time_secs) = process_usage();
+    let cpu_time_at_start = CPU_TIME_AT_START.lock().unwrap().unwrap_or(0.0);
+
+    ResourceUsage {
+        wall_time_secs: started.elapsed().as_secs_f64(),
+        peak_rss_bytes,
+        cpu_time_secs: cpu_time_secs.map(|secs| secs - cpu_time_at_start),
+        bytes_downloaded: BYTES_DOWNLOADED.load(Ordering::Relaxed),
+        bytes_written: BYTES_WRITTEN.load(Ordering::Relaxed),
+    }
+}
+
+/// Logs the usage in a human-readable way.
+pub fn log_usage(usage: &ResourceUsage) {
+    info!(
+        "[Usage] wall time {:.1}s, CPU time {}, peak RSS {}, downloaded {} bytes, written {} bytes",
+        usage.wall_time_secs,
+        usage
+            .cpu_time_secs
+            .map(|secs| format!("{:.1}s", secs))
+            .unwrap_or_else(|| "unknown".to_string()),
+        usage
+            .peak_rss_bytes
+            .map(|bytes| format!("{} MiB", bytes / 1024 / 1024))
+            .unwrap_or_else(|| "unknown".to_string()),
+        usage.bytes_downloaded,
+        usage.bytes_written
+    );
+}
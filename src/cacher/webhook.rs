@@ -0,0 +1,79 @@
+// letting mirror maintainers know how a run went without them watching logs
+
+use serde_json::{Value, json};
+use tracing::{error, info};
+
+use crate::{
+    cacher::summary::{RunResult, RunSummary},
+    config::{HttpConfig, WebhookConfig, WebhookFormat},
+};
+
+fn field(name: &str, value: impl ToString) -> Value {
+    json!({ "name": name, "value": value.to_string(), "inline": true })
+}
+
+/// Turns the summary into a Discord embed.
+fn discord_embed(summary: &RunSummary) -> Value {
+    let (title, color) = match summary.result {
+        RunResult::Finished => ("Scrape finished", 0x2ecc71),
+        RunResult::Interrupted => ("Scrape interrupted", 0xf1c40f),
+        RunResult::Failed => ("Scrape failed", 0xe74c3c),
+    };
+
+    let mut fields = vec![
+        field("New maps", summary.stats.new_maps),
+        field("Updated maps", summary.stats.updated_maps),
+        field("Took", format!("{:.0}s", summary.usage.wall_time_secs)),
+    ];
+
+    if let Some(total_maps) = summary.total_maps {
+        fields.push(field("Total maps", total_maps));
+    }
+
+    let skipped: u64 = summary.stats.skipped.values().sum();
+    let api_errors: u64 = summary.stats.api_errors.values().sum();
+    fields.push(field("Skipped", skipped));
+    fields.push(field("API errors", api_errors));
+
+    if summary.stats.quarantined > 0 {
+        fields.push(field("Quarantined", summary.stats.quarantined));
+    }
+
+    json!({
+        "embeds": [{
+            "title": title,
+            "description": summary.error.as_deref().unwrap_or(""),
+            "color": color,
+            "fields": fields,
+        }]
+    })
+}
+
+/// POSTs the run summary to the configured webhook. Failing to do so is logged and otherwise
+/// ignored, the run itself already happened.
+pub async fn notify_webhook(webhook: &WebhookConfig, http: &HttpConfig, summary: &RunSummary) {
+    let body = match webhook.format {
+        WebhookFormat::Json => serde_json::to_value(summary).unwrap(),
+        WebhookFormat::Discord => discord_embed(summary),
+    };
+
+    let client = match http.client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Couldn't set up the HTTP client for the webhook: {:?}", e);
+            return;
+        }
+    };
+
+    let res = client
+        .post(&webhook.url)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+
+    match res {
+        Ok(_) => info!("Sent run summary to the webhook"),
+        Err(e) => error!("Couldn't send run summary to the webhook: {:?}", e),
+    }
+}
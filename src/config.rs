@@ -0,0 +1,405 @@
+// config.toml, for the things that are too fiddly to pass as flags every time
+
+use std::{collections::BTreeMap, fs, time::Duration};
+
+use serde::Deserialize;
+
+use crate::cacher::{
+    export::ExportFormat,
+    filter::{AiDeclaration, Mod},
+    upload::UploadTarget,
+};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub filter: FilterConfig,
+    pub fields: FieldConfig,
+    /// Extra caches built from the same scrape. When there are any, they're written instead of
+    /// `--output`.
+    #[serde(rename = "profile")]
+    pub profiles: Vec<ProfileConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub covers: CoverConfig,
+    pub previews: PreviewConfig,
+    pub upload: UploadConfig,
+    pub s3: S3Config,
+    pub github: GitHubConfig,
+    pub put: PutConfig,
+    /// Other places every finished cache is saved, by name.
+    pub stores: BTreeMap<String, StoreConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// How long to wait for a connection to BeatSaver.
+    pub connect_timeout_secs: u64,
+    /// How long a response can go without sending anything before we give up on it.
+    pub read_timeout_secs: u64,
+    /// Replaces the whole User-Agent. Leave it alone unless you have a good reason to.
+    pub user_agent: Option<String>,
+    /// An email or Discord handle, added to the User-Agent so BeatSaver knows who to poke.
+    pub contact: Option<String>,
+    /// `http://`, `https://` or `socks5://` proxy for every request. Without it, the usual
+    /// `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` environment variables are used.
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            user_agent: None,
+            contact: None,
+            proxy: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn user_agent(&self) -> String {
+        if let Some(user_agent) = &self.user_agent {
+            return user_agent.clone();
+        }
+
+        let mut user_agent = format!(
+            "{}/{} (+https://github.com/mercurialworld/beatsaver-cacher",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+
+        if let Some(contact) = &self.contact {
+            user_agent.push_str("; ");
+            user_agent.push_str(contact);
+        }
+
+        user_agent.push(')');
+        user_agent
+    }
+
+    /// An HTTP client with these timeouts, User-Agent and proxy.
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(self.read_timeout_secs))
+            .user_agent(self.user_agent());
+
+        // reqwest already picks up HTTPS_PROXY and friends on its own
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Which maps make it into the cache. Every filter here is one `MapFilter` in the pipeline.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Leave out maps that were never published, or whose latest version isn't.
+    pub published_only: bool,
+    /// Leave out maps where the map or the song was declared AI-generated.
+    pub exclude_ai: bool,
+    /// AI declarations that `exclude_ai` lets through anyway.
+    pub allow_ai: Vec<AiDeclaration>,
+    /// Leave out maps flagged as automapped.
+    pub exclude_automapped: bool,
+    /// Only keep maps with at least one difficulty ranked on ScoreSaber or BeatLeader.
+    pub ranked_only: bool,
+    /// Only keep maps a curator has picked.
+    pub curated_only: bool,
+    /// Only keep maps uploaded by verified mappers.
+    pub verified_mappers_only: bool,
+    /// Leave out maps with a BeatSaver rating below this, from 0 to 1.
+    pub min_score: Option<f64>,
+    /// Leave out maps with fewer upvotes than this.
+    pub min_upvotes: Option<u32>,
+    /// Leave out songs shorter than this many seconds.
+    pub min_duration: Option<u32>,
+    /// Leave out songs longer than this many seconds.
+    pub max_duration: Option<u32>,
+    /// Leave out maps where every difficulty has fewer notes per second than this.
+    pub min_nps: Option<f64>,
+    /// Leave out maps where every difficulty has more notes per second than this.
+    pub max_nps: Option<f64>,
+    /// Only keep maps with at least one of these tags. Empty keeps everything.
+    pub include_tags: Vec<String>,
+    /// Leave out maps with any of these tags.
+    pub exclude_tags: Vec<String>,
+    /// Characteristics to drop from every map, e.g. `Lightshow`. Maps with nothing else are left
+    /// out entirely.
+    pub exclude_characteristics: Vec<String>,
+    /// Difficulties to drop from every map, either by name (`Easy`) or only for one
+    /// characteristic (`360Degree/Expert`). Maps with nothing else are left out entirely.
+    pub exclude_difficulties: Vec<String>,
+    /// Leave out maps where every difficulty needs one of these mods.
+    pub exclude_mods: Vec<Mod>,
+    /// File of uploader IDs or names (one per line) whose maps are always left out.
+    pub mapper_blocklist: Option<String>,
+    /// File of uploader IDs or names (one per line). If set, only their maps are kept.
+    pub mapper_allowlist: Option<String>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            published_only: true,
+            exclude_ai: true,
+            allow_ai: Vec::new(),
+            exclude_automapped: true,
+            ranked_only: false,
+            curated_only: false,
+            verified_mappers_only: false,
+            min_score: None,
+            min_upvotes: None,
+            min_duration: None,
+            max_duration: None,
+            min_nps: None,
+            max_nps: None,
+            include_tags: Vec::new(),
+            exclude_tags: Vec::new(),
+            exclude_characteristics: Vec::new(),
+            exclude_difficulties: Vec::new(),
+            exclude_mods: Vec::new(),
+            mapper_blocklist: None,
+            mapper_allowlist: None,
+        }
+    }
+}
+
+/// Which optional fields end up in the cache. Anything turned off is written as its protobuf
+/// default instead, which makes for a much smaller file.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FieldConfig {
+    pub song_sub_name: bool,
+    pub song_author_name: bool,
+    pub level_author_name: bool,
+    /// BeatSaver's curator, and BeastSaber's if the cache was made with `--bsaber`.
+    pub curator: bool,
+    /// The uploader's account and the collaborators.
+    pub uploader: bool,
+    /// Votes, plays, downloads and score.
+    pub votes: bool,
+    pub tags: bool,
+    /// Cover, preview and download URLs.
+    pub urls: bool,
+    /// Every version of the map, not just the current one.
+    pub versions: bool,
+    /// The per-characteristic difficulty counts.
+    pub characteristics: bool,
+    /// Every difficulty, with everything in it.
+    pub difficulties: bool,
+    /// ScoreSaber/BeatLeader stars on each difficulty, and its Hitbloq/AccSaber pools.
+    pub ranked: bool,
+    /// The environment of each difficulty.
+    pub environments: bool,
+}
+
+impl Default for FieldConfig {
+    fn default() -> Self {
+        FieldConfig {
+            song_sub_name: true,
+            song_author_name: true,
+            level_author_name: true,
+            curator: true,
+            uploader: true,
+            votes: true,
+            tags: true,
+            urls: true,
+            versions: true,
+            characteristics: true,
+            difficulties: true,
+            ranked: true,
+            environments: true,
+        }
+    }
+}
+
+/// Thumbnails for `--cover-pack`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CoverConfig {
+    /// Width and height of the thumbnails, in pixels. Covers are cropped to a square first.
+    pub size: u32,
+    /// JPEG quality, 1 to 100.
+    pub quality: u8,
+    /// Where thumbnails are kept between runs, so only new maps' covers get downloaded.
+    pub dir: String,
+    /// How many covers to download at once.
+    pub concurrency: usize,
+}
+
+impl Default for CoverConfig {
+    fn default() -> Self {
+        CoverConfig {
+            size: 128,
+            quality: 80,
+            dir: "covers".to_string(),
+            concurrency: 8,
+        }
+    }
+}
+
+/// Limits for `--preview-dir`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    /// Stop downloading once the directory holds this many bytes. Unset means no cap.
+    pub max_bytes: Option<u64>,
+    /// How many previews to download at once.
+    pub concurrency: usize,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        PreviewConfig {
+            max_bytes: None,
+            concurrency: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// Same as `--upload`, which wins when both are given.
+    pub target: Option<UploadTarget>,
+}
+
+/// One `[stores.<name>]`, picked by `kind`.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StoreConfig {
+    /// A copy of the cache file somewhere else.
+    File { path: String },
+    /// The cache file as an object, logged in with `[s3]`.
+    S3 { bucket: String, key: String },
+    /// The cache file as a string value.
+    Redis {
+        url: String,
+        #[serde(default = "default_redis_key")]
+        key: String,
+        /// Also every map on its own, as `<map_prefix>key:<key>` and `<map_prefix>hash:<hash>`.
+        #[serde(default)]
+        per_map: bool,
+        #[serde(default = "default_redis_map_prefix")]
+        map_prefix: String,
+    },
+    /// The cache file as a row in `table`, under the store's name.
+    Postgres {
+        url: String,
+        #[serde(default = "default_postgres_table")]
+        table: String,
+    },
+    /// Every map and difficulty as its own row, in `<prefix>maps` and `<prefix>difficulties`.
+    /// Only maps that changed get rewritten.
+    #[serde(rename = "postgres-tables")]
+    PostgresTables {
+        url: String,
+        #[serde(default = "default_postgres_prefix")]
+        prefix: String,
+    },
+}
+
+fn default_redis_key() -> String {
+    "beatsaver:cache".to_string()
+}
+
+fn default_redis_map_prefix() -> String {
+    "beatsaver:map:".to_string()
+}
+
+fn default_postgres_table() -> String {
+    "beatsaver_caches".to_string()
+}
+
+fn default_postgres_prefix() -> String {
+    "beatsaver_".to_string()
+}
+
+/// Where and how `--upload s3://...` connects.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct S3Config {
+    /// For S3-compatible storage (R2, B2, MinIO...). Unset means AWS itself.
+    pub endpoint: Option<String>,
+    /// Defaults to `us-east-1`, which most S3-compatible services accept too.
+    pub region: Option<String>,
+    /// Without these, `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` are used.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Put the bucket in the path instead of the host name, which MinIO wants.
+    pub path_style: bool,
+}
+
+/// How `--upload github://...` makes releases.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GitHubConfig {
+    /// Needs to be able to make releases. Without it, `GITHUB_TOKEN` is used.
+    pub token: Option<String>,
+    /// Every run gets its own tag, this followed by the time.
+    pub tag_prefix: String,
+}
+
+impl Default for GitHubConfig {
+    fn default() -> Self {
+        GitHubConfig {
+            token: None,
+            tag_prefix: "cache-".to_string(),
+        }
+    }
+}
+
+/// How `--upload https://...` logs in. A bearer token wins over a username and password.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PutConfig {
+    pub bearer_token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A cache with its own filters and fields, e.g. a ranked-only or a lite one.
+#[derive(Deserialize)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub output: String,
+    /// Leave it out for the usual gzipped protobuf.
+    pub format: Option<ExportFormat>,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default)]
+    pub fields: FieldConfig,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The run summary as-is.
+    #[default]
+    Json,
+    /// A Discord embed, for pointing straight at a channel webhook.
+    Discord,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookConfig {
+    /// Where the run summary gets POSTed when a scrape finishes or fails.
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+/// Loads the config file, or the defaults if there isn't one.
+pub fn load_config(path: Option<&str>) -> anyhow::Result<Config> {
+    match path {
+        Some(path) => Ok(toml::from_str(&fs::read_to_string(path)?)?),
+        None => Ok(Config::default()),
+    }
+}
@@ -0,0 +1,282 @@
+// keeping the cache fresh on a timer, for running as a service
+
+mod events;
+mod grpc;
+mod live;
+mod systemd;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use chrono::Utc;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::{
+    INTERRUPTED_EXIT_CODE, ScrapeArgs, ScrapeError,
+    cacher::{
+        ScrapeStats,
+        journal::{check_journal, read_cache_with_journal},
+        metrics::{last_progress, last_success, record_progress, record_run, render},
+        store::{CacheStore, open_store},
+    },
+    config::Config,
+    daemon::{events::events, grpc::spawn_grpc, live::LiveCache},
+    mapdata::MapList,
+    run_scrape, summarize,
+};
+
+/// What `/healthz` and the watchdog go by.
+struct Health {
+    started: Instant,
+    /// How old the last successful scrape can get before we call ourselves unhealthy.
+    healthy_within: Duration,
+    /// Set while waiting for the next round, when there's no progress to expect.
+    idle: AtomicBool,
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(),
+    )
+}
+
+async fn healthz(State(health): State<Arc<Health>>) -> impl IntoResponse {
+    let last_success = last_success();
+
+    // before the first success, count from startup so a long first scrape isn't a failure
+    let age = match last_success {
+        Some(last_success) => (Utc::now().timestamp() - last_success).max(0) as u64,
+        None => health.started.elapsed().as_secs(),
+    };
+    let healthy = age <= health.healthy_within.as_secs();
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(json!({
+            "healthy": healthy,
+            "last_success": last_success,
+            "last_success_age_secs": age,
+        })),
+    )
+}
+
+/// Pings systemd's watchdog while scrapes are getting somewhere (or there's nothing to scrape
+/// right now). A wedged scrape stops the pings and systemd restarts us.
+fn spawn_watchdog(health: Arc<Health>) {
+    let Some(timeout) = systemd::watchdog_timeout() else {
+        return;
+    };
+
+    info!("systemd watchdog is on, pinging every {:?}", timeout / 2);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(timeout / 2);
+
+        loop {
+            ticker.tick().await;
+
+            let progressing = last_progress().is_some_and(|at| at.elapsed() < timeout);
+
+            if health.idle.load(Ordering::Relaxed) || progressing {
+                systemd::notify_watchdog();
+            } else {
+                warn!("No progress in {:?}, leaving it to the watchdog", timeout);
+            }
+        }
+    });
+}
+
+/// Where the servers get the cache from: the output file, or a store.
+enum Source {
+    File(String),
+    Store(Box<dyn CacheStore>),
+}
+
+impl Source {
+    fn describe(&self) -> String {
+        match self {
+            Source::File(path) => path.clone(),
+            Source::Store(store) => store.describe(),
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<Option<MapList>> {
+        match self {
+            Source::File(path) if !std::path::Path::new(path).exists() => Ok(None),
+            Source::File(path) => {
+                // a journal that's not for the snapshot means the snapshot was rewritten behind
+                // our back, better to keep serving what we have than go back in time
+                check_journal(path)?;
+                Ok(Some(read_cache_with_journal(path)?))
+            }
+            Source::Store(store) => store.load().await,
+        }
+    }
+}
+
+/// Reads the cache back after a scrape, so the servers answer from what was just written.
+async fn reload(live: &LiveCache, source: &Source) {
+    match source.load().await {
+        Ok(Some(maps)) => {
+            let changes = live.replace(maps);
+            info!("Reloaded {}, {} maps changed", source.describe(), changes);
+        }
+        Ok(None) => warn!(
+            "{} has no cache in it, still serving the old one",
+            source.describe()
+        ),
+        Err(e) => warn!(
+            "Couldn't reload {}, still serving the old one: {:?}",
+            source.describe(),
+            e
+        ),
+    }
+}
+
+/// Scrapes every `interval` until Ctrl-C, serving `/metrics` and `/healthz` on `listen` the
+/// whole time, plus `/events` if `push_events`, and the gRPC service on `grpc_listen` if given.
+/// Those answer from the output file, or from the `serve_from` store. Unhealthy means no
+/// successful scrape within `healthy_within`.
+pub async fn run_daemon(
+    mut args: ScrapeArgs,
+    config: Config,
+    interval: Duration,
+    healthy_within: Duration,
+    listen: SocketAddr,
+    grpc_listen: Option<SocketAddr>,
+    serve_from: Option<String>,
+    push_events: bool,
+) {
+    // the first round does a full scrape if there's no cache yet, the rest only fetch what's new
+    args.update = true;
+
+    let health = Arc::new(Health {
+        started: Instant::now(),
+        healthy_within,
+        idle: AtomicBool::new(false),
+    });
+
+    let listener = match TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Couldn't listen on {}: {:?}", listen, e);
+            std::process::exit(1);
+        }
+    };
+
+    let source = match &serve_from {
+        Some(name) => {
+            let store = config
+                .stores
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("there's no [stores.{}]", name))
+                .and_then(|store| open_store(name, store, &config.s3));
+
+            match store {
+                Ok(store) => Source::Store(store),
+                Err(e) => {
+                    error!("Can't serve from {}: {:?}", name, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => Source::File(args.output.clone()),
+    };
+
+    // no cache yet just means starting out empty, the first scrape fills it in
+    let live = if grpc_listen.is_some() || push_events {
+        let maps = source.load().await.ok().flatten().unwrap_or_default();
+        Some(Arc::new(LiveCache::new(maps)))
+    } else {
+        None
+    };
+
+    let mut app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .with_state(health.clone());
+
+    if let Some(live) = &live
+        && push_events
+    {
+        app = app.merge(
+            Router::new()
+                .route("/events", get(events))
+                .with_state(live.clone()),
+        );
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Metrics server stopped: {:?}", e);
+        }
+    });
+    info!("Serving metrics and health on http://{}", listen);
+
+    if let (Some(live), Some(grpc_listen)) = (&live, grpc_listen) {
+        spawn_grpc(live.clone(), grpc_listen);
+    }
+
+    systemd::notify_ready();
+    spawn_watchdog(health.clone());
+
+    loop {
+        let started = Instant::now();
+        let mut stats = ScrapeStats::default();
+
+        health.idle.store(false, Ordering::Relaxed);
+        record_progress();
+
+        let result = run_scrape(&args, &config, &mut stats).await;
+        summarize(&args, &config, &result, stats, started).await;
+
+        match result {
+            Ok(report) => {
+                record_run(started.elapsed(), Some(report.cache_bytes));
+                info!("Scrape done, {} maps in {}", report.maps, report.path);
+
+                if let Some(live) = &live {
+                    reload(live, &source).await;
+                }
+            }
+            Err(ScrapeError::Interrupted) => std::process::exit(INTERRUPTED_EXIT_CODE),
+            Err(ScrapeError::Failed(e)) => {
+                record_run(started.elapsed(), None);
+                error!("Scrape failed, trying again next round: {:?}", e);
+            }
+        }
+
+        health.idle.store(true, Ordering::Relaxed);
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => {
+                warn!("Stopping");
+                systemd::notify_stopping();
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// pushing every change to the cache to WebSocket clients, so request managers stay in sync
+// without polling
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    response::Response,
+};
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::debug;
+
+use crate::daemon::live::{LiveCache, MapEvent};
+
+/// `/events`: one JSON text message per map added, updated or removed, from when the client
+/// connects. Clients only ever need to close it.
+pub async fn events(ws: WebSocketUpgrade, State(live): State<Arc<LiveCache>>) -> Response {
+    ws.on_upgrade(move |socket| push_events(socket, live.subscribe()))
+}
+
+async fn push_events(mut socket: WebSocket, mut events: Receiver<MapEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let text = serde_json::to_string(&event).unwrap();
+
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        return;
+                    }
+                }
+                // a client that fell behind can't trust what it has anymore, it should fetch
+                // the cache again and reconnect
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Closing an events client that missed {} events", missed);
+
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AGAIN,
+                            reason: format!("missed {} events", missed).into(),
+                        })))
+                        .await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => {
+                if !matches!(message, Some(Ok(_))) {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// typed lookups over gRPC, from the same .proto the cache is written with
+
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures::{Stream, stream};
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Request, Response, Status, transport::Server};
+use tracing::{error, info};
+
+use crate::{
+    daemon::live::{LiveCache, MapEvent},
+    mapdata::{
+        GetMapByHashRequest, GetMapByKeyRequest, MapMetadata, MapUpdate, StreamUpdatesRequest,
+        map_service_server::{MapService, MapServiceServer},
+    },
+};
+
+impl From<MapEvent> for MapUpdate {
+    fn from(event: MapEvent) -> Self {
+        match event {
+            MapEvent::Added { key, map } | MapEvent::Updated { key, map } => MapUpdate {
+                key,
+                map: Some(map),
+            },
+            MapEvent::Removed { key } => MapUpdate { key, map: None },
+        }
+    }
+}
+
+struct LiveMapService {
+    live: Arc<LiveCache>,
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<MapUpdate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl MapService for LiveMapService {
+    async fn get_map_by_key(
+        &self,
+        request: Request<GetMapByKeyRequest>,
+    ) -> Result<Response<MapMetadata>, Status> {
+        let key = request.into_inner().key.to_lowercase();
+
+        match self.live.snapshot().maps.map_metadata.get(&key) {
+            Some(map) => Ok(Response::new(map.clone())),
+            None => Err(Status::not_found(format!("no map with key {}", key))),
+        }
+    }
+
+    async fn get_map_by_hash(
+        &self,
+        request: Request<GetMapByHashRequest>,
+    ) -> Result<Response<MapMetadata>, Status> {
+        let hash = request.into_inner().hash;
+        let snapshot = self.live.snapshot();
+
+        match snapshot
+            .index
+            .key(&hash)
+            .and_then(|key| snapshot.maps.map_metadata.get(key))
+        {
+            Some(map) => Ok(Response::new(map.clone())),
+            None => Err(Status::not_found(format!("no map with hash {}", hash))),
+        }
+    }
+
+    type StreamUpdatesStream = UpdateStream;
+
+    async fn stream_updates(
+        &self,
+        _request: Request<StreamUpdatesRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let updates = stream::unfold(Some(self.live.subscribe()), |events| async move {
+            let mut events = events?;
+
+            match events.recv().await {
+                Ok(event) => Some((Ok(event.into()), Some(events))),
+                // a client that fell behind can't trust what it has anymore, so end its stream
+                // and let it look things up again
+                Err(RecvError::Lagged(missed)) => Some((
+                    Err(Status::data_loss(format!("missed {} updates", missed))),
+                    None,
+                )),
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+/// Serves the gRPC service on `listen` in the background.
+pub fn spawn_grpc(live: Arc<LiveCache>, listen: SocketAddr) {
+    let service = MapServiceServer::new(LiveMapService { live });
+
+    tokio::spawn(async move {
+        if let Err(e) = Server::builder().add_service(service).serve(listen).await {
+            error!("gRPC server stopped: {:?}", e);
+        }
+    });
+
+    info!("Serving gRPC on {}", listen);
+}
@@ -0,0 +1,86 @@
+// the cache as the daemon last wrote it, kept in memory so the servers can answer from it and
+// tell clients what changed
+
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    cacher::{delta::diff_caches, hash_index::HashIndex},
+    mapdata::{MapList, MapMetadata},
+};
+
+/// How many events a slow subscriber can fall behind by before it misses some.
+const EVENT_BUFFER: usize = 4096;
+
+pub struct Snapshot {
+    pub maps: MapList,
+    pub index: HashIndex,
+}
+
+impl Snapshot {
+    fn new(maps: MapList) -> Self {
+        let index = HashIndex::build(&maps);
+        Snapshot { maps, index }
+    }
+}
+
+/// One change to the cache between two scrapes. In JSON, `event` says which.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum MapEvent {
+    Added { key: String, map: MapMetadata },
+    Updated { key: String, map: MapMetadata },
+    Removed { key: String },
+}
+
+pub struct LiveCache {
+    snapshot: RwLock<Arc<Snapshot>>,
+    events: broadcast::Sender<MapEvent>,
+}
+
+impl LiveCache {
+    pub fn new(maps: MapList) -> Self {
+        LiveCache {
+            snapshot: RwLock::new(Arc::new(Snapshot::new(maps))),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    /// The cache as it is right now. Holding on to it doesn't block the next `replace`.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MapEvent> {
+        self.events.subscribe()
+    }
+
+    /// Swaps in a freshly written cache and sends an event for every map that's different from
+    /// the last one. Returns how many there were.
+    pub fn replace(&self, maps: MapList) -> usize {
+        let old = self.snapshot();
+        let delta = diff_caches(&old.maps, &maps);
+        let changes = delta.upserted.len() + delta.removed.len();
+
+        *self.snapshot.write().unwrap() = Arc::new(Snapshot::new(maps));
+
+        // nobody listening isn't an error
+        for (key, map) in delta.upserted {
+            let event = if old.maps.map_metadata.contains_key(&key) {
+                MapEvent::Updated { key, map }
+            } else {
+                MapEvent::Added { key, map }
+            };
+
+            let _ = self.events.send(event);
+        }
+
+        for key in delta.removed {
+            let _ = self.events.send(MapEvent::Removed { key });
+        }
+
+        changes
+    }
+}
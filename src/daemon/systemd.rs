@@ -0,0 +1,43 @@
+// telling systemd how we're doing, when it's the one running us. does nothing anywhere else
+
+use std::time::Duration;
+
+/// Tells systemd we're up.
+#[cfg(unix)]
+pub fn notify_ready() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
+}
+
+/// Tells systemd we're still alive.
+#[cfg(unix)]
+pub fn notify_watchdog() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]);
+}
+
+/// Tells systemd we're shutting down on purpose.
+#[cfg(unix)]
+pub fn notify_stopping() {
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Stopping]);
+}
+
+/// How long systemd waits for a watchdog ping before restarting us, if it's watching at all.
+#[cfg(unix)]
+pub fn watchdog_timeout() -> Option<Duration> {
+    let mut usec = 0;
+
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(not(unix))]
+pub fn notify_ready() {}
+
+#[cfg(not(unix))]
+pub fn notify_watchdog() {}
+
+#[cfg(not(unix))]
+pub fn notify_stopping() {}
+
+#[cfg(not(unix))]
+pub fn watchdog_timeout() -> Option<Duration> {
+    None
+}
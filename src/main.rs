@@ -1,20 +1,2054 @@
-use beatsaver_api::client::BeatSaverClient;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use crate::cacher::{init_cache, write_cache};
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use tracing::{error, info, warn};
+
+use crate::cacher::{
+    Listing, MapStore, Profile, ScrapeOptions, ScrapeOutcome, ScrapeStats, accsaber,
+    api::{ApiClient, SearchQuery},
+    beatleader, bsaber,
+    checksum::{write_checksum, write_signature},
+    chunks::write_chunks,
+    covers::write_cover_pack,
+    delta::{apply_delta, read_delta, write_delta},
+    descriptor::{descriptor_path, write_descriptor_set},
+    dump::{DumpFormat, dump_maps},
+    error::CacherError,
+    estimate_cache_size,
+    exclusion::{flag_recently_played, load_exclusion_feed},
+    export::{ExportFormat, export_cache},
+    expr::{FilterExpr, parse_filter},
+    filter::FilterPipeline,
+    fixture::generate_fixture,
+    hash_index::{HashIndex, write_hash_index},
+    history::append_hash_history,
+    hitbloq, init_cache,
+    integrity::{Artifacts, verify_artifacts},
+    journal::{
+        append_journal, read_cache_with_journal, remove_journal, replay_journal, restart_journal,
+        start_journal,
+    },
+    local::{LocalMatcher, LocalState, scan_custom_levels, write_song_hash_data},
+    manifest::{manifest_path, write_manifest},
+    metrics::record_usage,
+    migrate::migrate_cache,
+    mirror::mirror_maps,
+    mods::{ModFlags, name_mods_in_json},
+    newest_upload,
+    playlist::{MapSelection, PlaylistInfo, read_selection, write_playlist},
+    previews::mirror_previews,
+    query::MapQuery,
+    ratelimit::RateLimiter,
+    refresh::refresh_selection,
+    repair::salvage_cache_file,
+    scoresaber, scrape_saved,
+    search::SearchIndex,
+    shard::{ShardBy, write_sharded_cache},
+    source::open_saved,
+    spill::{SpillStore, write_spilled_cache},
+    store::save_to_stores,
+    summary::{RunResult, RunSummary, write_summary},
+    update::{backup_cache, load_existing_cache},
+    upload::{UploadConfigs, UploadTarget, artifact_files, parse_upload_target, upload_files},
+    usage::{log_usage, measure, reset_usage},
+    webhook::notify_webhook,
+    write_cache,
+};
+use crate::config::{Config, FieldConfig, FilterConfig, HttpConfig, load_config};
+use crate::daemon::run_daemon;
+use crate::logging::{LogFormat, init_logging};
+use crate::mapdata::{MapList, MapMetadata};
+use crate::serve::run_serve;
 
 mod cacher;
+mod config;
+mod daemon;
+mod logging;
+mod serve;
 
 pub(crate) mod mapdata {
     include!(concat!(env!("OUT_DIR"), "\\cached_beat_saver_data.rs"));
 }
 
+#[derive(Parser)]
+#[command(version, about = "Caches BeatSaver map data for DumbRequestManager")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    scrape: ScrapeArgs,
+
+    /// Path to a config.toml
+    #[arg(short, long, global = true)]
+    config: Option<String>,
+
+    /// How log lines are written, levels still come from RUST_LOG
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert an existing cache into another format
+    Export {
+        /// Format to export to
+        #[arg(value_enum)]
+        format: ExportFormat,
+
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Where to write the exported file
+        #[arg(short, long)]
+        output: String,
+
+        /// File or URL listing recently played keys/hashes, which get flagged in the export
+        #[arg(long)]
+        exclusion_feed: Option<String>,
+
+        /// Also build a search index of the maps into this directory, for `search --index`
+        #[arg(long)]
+        search_index: Option<String>,
+    },
+    /// Make a .bplist playlist out of the maps in a cache that match some filters
+    Playlist {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Where to write the playlist
+        #[arg(short, long)]
+        output: String,
+
+        /// Title shown in game
+        #[arg(long, default_value = "BeatSaver cache")]
+        title: String,
+
+        #[arg(long, default_value = "beatsaver-cacher")]
+        author: String,
+
+        #[arg(long)]
+        description: Option<String>,
+
+        /// PNG or JPEG to use as the playlist's cover
+        #[arg(long)]
+        image: Option<String>,
+
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Download the zips of the maps in a cache that match some filters, stored by hash, to keep
+    /// a mirror of them
+    Mirror {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Where the zips and their index.json go
+        #[arg(short, long, default_value = "mirror")]
+        dir: String,
+
+        /// How many zips to download at once
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Bring a cache up to date with deltas made by --delta-dir, applied in order
+    ApplyDelta {
+        /// Deltas to apply
+        #[arg(required = true)]
+        deltas: Vec<String>,
+
+        /// Cache to patch
+        #[arg(long, default_value = "mapData.proto.gz")]
+        cache: String,
+    },
+    /// Rewrite a cache made by an older version in the current format
+    Migrate {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Where to write the migrated cache, the input is replaced if not given
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Salvage every intact map from a cache that's cut short or corrupt, and print what was lost
+    Repair {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Where to write what's left, `<input>.repaired` if not given
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Print maps from a cache as JSON, by key or hash
+    Lookup {
+        /// Keys or hashes to look up, older versions' hashes work too
+        #[arg(required = true)]
+        ids: Vec<String>,
+
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+    },
+    /// Print what's in a cache in readable form, for debugging
+    Dump {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// How many maps to print
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// How many maps to skip first, in key order
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        #[arg(long, value_enum, default_value_t = DumpFormat::Text)]
+        format: DumpFormat,
+    },
+    /// Hash the maps in a Beat Saber CustomLevels folder and report which are outdated, gone
+    /// from BeatSaver, or not in the cache at all
+    Scan {
+        /// The CustomLevels folder
+        custom_levels: String,
+
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Hash history file (from --hash-history), to recognise deleted maps by hash too
+        #[arg(long)]
+        hash_history: Option<String>,
+
+        /// List maps that are up to date too
+        #[arg(long)]
+        all: bool,
+
+        /// Also write the hashes as SongCore's SongHashData.dat here, so the game can skip
+        /// hashing every map on its next start. Run it on the same machine as the game
+        #[arg(long)]
+        song_hash_data: Option<String>,
+    },
+    /// Print the maps in a cache that match some filters, newest first
+    Query {
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        #[command(flatten)]
+        query: QueryArgs,
+    },
+    /// Find maps in a cache by song name, song author or mapper, typos and all
+    Search {
+        query: String,
+
+        /// Cache to read from
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Search index made by `export --search-index` or `serve --search-index`, instead of
+        /// indexing the cache again
+        #[arg(long)]
+        index: Option<String>,
+
+        /// How many maps to print, best match first
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Serve a cache over HTTP: /search?q= finds maps by name, /query?filter= by expression
+    Serve {
+        /// Cache to serve
+        #[arg(short, long, default_value = "mapData.proto.gz")]
+        input: String,
+
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+
+        /// Keep the search index in this directory instead of in memory
+        #[arg(long)]
+        search_index: Option<String>,
+
+        /// Also answer BeatSaver's /maps/id/{id}, /maps/hash/{hash} and /search/text/{page}, so
+        /// mods can be pointed here while BeatSaver is down
+        #[arg(long)]
+        api_compat: bool,
+
+        /// Also answer GraphQL queries on /graphql
+        #[arg(long)]
+        graphql: bool,
+    },
+    /// Write the protobuf descriptor set caches can be decoded with, for tools without the
+    /// .proto files
+    Descriptor {
+        /// Where to write it
+        #[arg(short, long, default_value = "mapData.desc")]
+        output: String,
+    },
+    /// Generate a synthetic cache for load testing, without touching BeatSaver
+    GenFixture {
+        /// How many maps to generate
+        #[arg(long, default_value_t = 10000)]
+        maps: u32,
+
+        /// Seed for the random generator, the same seed gives the same cache
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Where to write the fixture
+        #[arg(short, long, default_value = "fixture.proto.gz")]
+        output: String,
+    },
+    /// Re-fetch specific maps and patch them into an existing cache, instead of scraping everything
+    /// again
+    Refresh {
+        /// Keys or hashes of the maps to refresh
+        keys: Vec<String>,
+
+        /// File with more keys or hashes to refresh, one per line, or a .bplist playlist
+        #[arg(long)]
+        keys_file: Option<String>,
+
+        /// Cache to patch
+        #[arg(long, default_value = "mapData.proto.gz")]
+        cache: String,
+
+        /// How many times a single batch can fail before giving up
+        #[arg(long, default_value_t = 8)]
+        max_retries: u32,
+    },
+    /// Rebuild the cache from pages saved with --archive-dir, with today's filters and fields
+    /// instead of whatever the scrape used. Same options as a scrape
+    Reprocess {
+        /// Directory the pages were archived into
+        archive: String,
+
+        #[command(flatten)]
+        scrape: ScrapeArgs,
+    },
+    /// Keep the cache up to date, scraping for new maps every so often and serving metrics
+    Daemon {
+        /// Seconds to wait between scrapes
+        #[arg(long, default_value_t = 3600)]
+        interval: u64,
+
+        /// Seconds since the last successful scrape before /healthz reports unhealthy. Defaults
+        /// to three intervals
+        #[arg(long)]
+        healthy_within: Option<u64>,
+
+        /// Address to serve /metrics and /healthz on
+        #[arg(long, default_value = "127.0.0.1:9187")]
+        listen: SocketAddr,
+
+        /// Also serve map lookups and a stream of changes over gRPC on this address
+        #[arg(long, conflicts_with = "shard_by")]
+        grpc_listen: Option<SocketAddr>,
+
+        /// Serve lookups and events from this store under [stores] instead of the output file
+        #[arg(long)]
+        serve_from: Option<String>,
+
+        /// Push every map the scrapes add, update or remove to WebSocket clients on /events, as
+        /// JSON
+        #[arg(long, conflicts_with = "shard_by")]
+        events: bool,
+
+        #[command(flatten)]
+        scrape: ScrapeArgs,
+    },
+}
+
+/// Which maps in a cache a subcommand works on.
+#[derive(Args)]
+struct QueryArgs {
+    /// Only maps with a difficulty ranked at this many stars or more
+    #[arg(long)]
+    min_stars: Option<f32>,
+
+    /// Only maps with a difficulty ranked at this many stars or fewer
+    #[arg(long)]
+    max_stars: Option<f32>,
+
+    /// Only maps ranked on ScoreSaber or BeatLeader
+    #[arg(long)]
+    ranked: bool,
+
+    /// Only maps with this tag, can be given more than once
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+
+    /// Only maps by this mapper
+    #[arg(long)]
+    mapper: Option<String>,
+
+    /// Only maps uploaded on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only maps uploaded before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only maps that don't need any mods
+    #[arg(long)]
+    vanilla: bool,
+
+    /// Only maps this expression is true for, like "ss_stars > 7 && mods.chroma && duration < 240"
+    #[arg(long, value_parser = parse_filter)]
+    filter: Option<FilterExpr>,
+
+    /// At most this many maps, newest first
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+/// Scraping is what happens when no subcommand is given.
+#[derive(Args)]
+struct ScrapeArgs {
+    /// Where to write the cache
+    #[arg(short, long, default_value = "mapData.proto.gz")]
+    output: String,
+
+    /// Split the cache into several files instead of one big one
+    #[arg(long, value_enum)]
+    shard_by: Option<ShardBy>,
+
+    /// Directory to write shards (and their manifest) into
+    #[arg(long, default_value = "shards")]
+    shard_dir: String,
+
+    /// Sign the output with this ed25519 key (a file containing the hex-encoded 32-byte seed)
+    #[arg(long)]
+    signing_key: Option<String>,
+
+    /// Upload the cache, its checksum and signature after a successful run: s3://bucket/prefix,
+    /// github://owner/repo for a release, or an http(s):// URL to PUT to. Overrides `target`
+    /// under `[upload]` in the config
+    #[arg(long, value_parser = parse_upload_target)]
+    upload: Option<UploadTarget>,
+
+    /// With --update, also write what changed since the last cache into this directory, as
+    /// `<old version>.delta.gz`, so clients can catch up without downloading everything again
+    #[arg(long, requires = "update", conflicts_with_all = ["shard_by", "spill"])]
+    delta_dir: Option<String>,
+
+    /// Also cut the cache into pieces in this directory, plus `<cache file name>.json` listing
+    /// them, so clients can download it a bit at a time
+    #[arg(long, conflicts_with = "shard_by")]
+    chunk_dir: Option<String>,
+
+    /// How big each piece is, in bytes
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    chunk_size: u64,
+
+    /// Append every version hash and when it was current to this hash history file (JSON lines)
+    #[arg(long)]
+    hash_history: Option<String>,
+
+    /// Also write a compact hash → key index to this file, for clients that look maps up by hash
+    #[arg(long, conflicts_with = "spill")]
+    hash_index: Option<String>,
+
+    /// Also write the protobuf descriptor set next to the cache as mapData.desc, so tools
+    /// without the .proto files can decode it
+    #[arg(long)]
+    descriptor_set: bool,
+
+    /// Only fetch maps newer than the ones already in the output cache, and add them to it
+    #[arg(long)]
+    update: bool,
+
+    /// When updating, append what changed to `<output>.journal` instead of rewriting the whole
+    /// cache. Checksums, uploads, stores and the rest only catch up when the journal gets folded
+    /// back into the cache
+    #[arg(long, conflicts_with_all = ["shard_by", "spill"])]
+    journal: bool,
+
+    /// How many records the journal can get to before it's folded back into the cache
+    #[arg(long, default_value_t = 10000)]
+    compact_after: usize,
+
+    /// When updating and neither the cache nor any backup is readable, rebuild from scratch
+    #[arg(long, requires = "update")]
+    allow_full_rescrape: bool,
+
+    /// Write characteristic, difficulty and environment names once, in a string table, instead of
+    /// in every difficulty. Makes the cache smaller, but only clients that resolve the table
+    /// (format version 5) see the names
+    #[arg(long)]
+    intern_names: bool,
+
+    /// Keep scraped maps in this temporary file instead of in memory. Uses far less RAM, but only
+    /// works for a plain single-file scrape
+    #[arg(long, conflicts_with_all = ["update", "shard_by", "hash_history"])]
+    spill: Option<String>,
+
+    /// Instead of scraping, fetch exactly the maps listed in this file (keys or hashes, one per
+    /// line) or .bplist playlist. The filters don't apply
+    #[arg(
+        long,
+        conflicts_with_all = ["update", "since", "until", "uploader", "query", "tags", "spill"]
+    )]
+    from_list: Option<String>,
+
+    /// Instead of asking BeatSaver, convert maps saved from it earlier: a file with one map per
+    /// line, or a directory of saved pages (`.json`). The time range doesn't apply
+    #[arg(
+        long,
+        conflicts_with_all = ["from_list", "since", "until", "uploader", "query", "tags"]
+    )]
+    from_saved: Option<String>,
+
+    /// Save every page BeatSaver sends into this directory as-is, so `reprocess` can rebuild the
+    /// cache from it later without downloading anything
+    #[arg(long, conflicts_with_all = ["from_saved", "from_list"])]
+    archive_dir: Option<String>,
+
+    /// Only scrape the maps this mapper uploaded, by BeatSaver user ID
+    #[arg(long, conflicts_with_all = ["update", "since", "until"])]
+    uploader: Option<u32>,
+
+    /// Only scrape maps a BeatSaver search for this turns up
+    #[arg(long, conflicts_with = "uploader")]
+    query: Option<String>,
+
+    /// Only scrape maps with this tag, can be given more than once (e.g. `--tag tech --tag dance`)
+    #[arg(long = "tag", conflicts_with = "uploader")]
+    tags: Vec<String>,
+
+    /// Only scrape maps uploaded on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only scrape maps uploaded before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<DateTime<Utc>>,
+
+    /// After scraping, replace BeatSaver's copy of BeatLeader's ranked data with what BeatLeader
+    /// itself says, which BeatSaver lags behind on
+    #[arg(long, conflicts_with = "spill")]
+    beatleader: bool,
+
+    /// After scraping, replace BeatSaver's copy of ScoreSaber's ranked data with what ScoreSaber
+    /// itself says, which BeatSaver lags behind on
+    #[arg(long, conflicts_with = "spill")]
+    scoresaber: bool,
+
+    /// After scraping, mark which maps BeastSaber's curators picked, and their review scores
+    #[arg(long, conflicts_with = "spill")]
+    bsaber: bool,
+
+    /// After scraping, record which Hitbloq pools each difficulty is in
+    #[arg(long, conflicts_with = "spill")]
+    hitbloq: bool,
+
+    /// After scraping, record which AccSaber categories each difficulty is ranked in
+    #[arg(long, conflicts_with = "spill")]
+    accsaber: bool,
+
+    /// After writing the cache, download every map's cover and pack them as thumbnails into this
+    /// tar (see `[covers]` in the config)
+    #[arg(long, conflicts_with = "spill")]
+    cover_pack: Option<String>,
+
+    /// After writing the cache, mirror every map's preview MP3 into this directory (see
+    /// `[previews]` in the config). Unfinished downloads are resumed on the next run
+    #[arg(long, conflicts_with = "spill")]
+    preview_dir: Option<String>,
+
+    /// How many pages to fetch at once
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+
+    /// Maximum requests per second to BeatSaver
+    #[arg(long, default_value_t = 10.0)]
+    rate_limit: f64,
+
+    /// How many requests can go out back-to-back before the rate limit kicks in
+    #[arg(long, default_value_t = 1)]
+    burst: u32,
+
+    /// How many times a single page can fail before giving up on the whole scrape
+    #[arg(long, default_value_t = 8)]
+    max_retries: u32,
+
+    /// Only cache maps ranked on ScoreSaber or BeatLeader (same as `ranked_only` in the config)
+    #[arg(long)]
+    ranked_only: bool,
+
+    /// Only cache maps a curator has picked (same as `curated_only` in the config)
+    #[arg(long)]
+    curated_only: bool,
+
+    /// Only cache maps by verified mappers (same as `verified_mappers_only` in the config)
+    #[arg(long)]
+    verified_mappers_only: bool,
+
+    /// Keep automapped maps too, for research dumps (same as `exclude_automapped = false`)
+    #[arg(long)]
+    include_automapped: bool,
+
+    /// Keep maps that were never published, or whose latest version isn't (same as
+    /// `published_only = false`)
+    #[arg(long)]
+    include_unpublished: bool,
+
+    /// Scrape and convert as usual, but don't write the cache (or anything else that goes with
+    /// it), just say what would have been written
+    #[arg(long, conflicts_with = "spill")]
+    dry_run: bool,
+
+    /// Where to write a JSON summary of the run
+    #[arg(long, default_value = "summary.json")]
+    summary: String,
+
+    /// Where to dump the raw JSON of maps that couldn't be converted (bad key, missing data), so
+    /// they can be looked at after the run
+    #[arg(long, default_value = "quarantine")]
+    quarantine_dir: String,
+
+    /// Also print the run summary to stdout as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Reads a `--since`/`--until` date. A bare date means midnight UTC.
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        .map_err(|_| format!("{} isn't a YYYY-MM-DD or RFC 3339 date", value))
+}
+
+/// Exit code for a scrape stopped with Ctrl-C, same as shells use for SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Why a scrape didn't produce a cache.
+enum ScrapeError {
+    /// Someone hit Ctrl-C. Whatever was scraped is in the partial file.
+    Interrupted,
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ScrapeError {
+    fn from(e: anyhow::Error) -> Self {
+        ScrapeError::Failed(e)
+    }
+}
+
+/// What a successful scrape wrote.
+struct ScrapeReport {
+    /// The cache, or the shard manifest when sharding. With profiles, all of their outputs.
+    path: String,
+    /// Added up over every profile, when there are several.
+    maps: usize,
+    /// Size of everything a mirror would serve, so all shards when sharding.
+    cache_bytes: u64,
+}
+
+/// The part of BeatSaver to scrape, when it isn't all of it.
+fn map_query(args: QueryArgs) -> MapQuery {
+    MapQuery {
+        min_stars: args.min_stars,
+        max_stars: args.max_stars,
+        ranked: args.ranked,
+        tags: args.tags,
+        mapper: args.mapper,
+        since: args.since,
+        until: args.until,
+        vanilla: args.vanilla,
+        filter: args.filter,
+        limit: args.limit,
+    }
+}
+
+/// One line per map for `query` and `search`.
+fn print_map(key: &str, map: &MapMetadata) {
+    let mods: Vec<String> = ModFlags::from(map.mods)
+        .names()
+        .map(String::from)
+        .chain(map.other_requirements.iter().cloned())
+        .collect();
+
+    if mods.is_empty() {
+        println!(
+            "{:>6}  {} - {} ({})",
+            key,
+            map.song_author_name(),
+            map.song_name(),
+            map.level_author_name()
+        );
+    } else {
+        println!(
+            "{:>6}  {} - {} ({}) [{}]",
+            key,
+            map.song_author_name(),
+            map.song_name(),
+            map.level_author_name(),
+            mods.join(", ")
+        );
+    }
+}
+
+fn listing(args: &ScrapeArgs) -> Option<Listing> {
+    if let Some(uploader) = args.uploader {
+        return Some(Listing::Uploader(uploader));
+    }
+
+    if args.query.is_none() && args.tags.is_empty() {
+        return None;
+    }
+
+    Some(Listing::Search(SearchQuery {
+        text: args.query.clone().unwrap_or_default(),
+        tags: args.tags.clone(),
+    }))
+}
+
+/// Where broken maps go. Dry runs don't write anything, so they only count them as skipped.
+fn quarantine_dir(args: &ScrapeArgs) -> Option<String> {
+    (!args.dry_run).then(|| args.quarantine_dir.clone())
+}
+
+/// Where maps go when a scrape doesn't make it to the end.
+fn partial_path(output: &str) -> String {
+    format!("{}.partial", output)
+}
+
+/// Says whether what was scraped before things stopped made it into the partial file.
+fn report_partial(partial: &str, saved: Result<u64, CacherError>) {
+    match saved {
+        Ok(_) => warn!("Saved what was scraped so far to {}", partial),
+        Err(e) => error!(
+            "Couldn't save what was scraped so far to {}: {}",
+            partial, e
+        ),
+    }
+}
+
+/// Turns a scrape that didn't finish into the right error.
+fn unfinished(result: Result<ScrapeOutcome, CacherError>) -> ScrapeError {
+    match result {
+        Ok(_) => ScrapeError::Interrupted,
+        Err(e) => ScrapeError::Failed(e.into()),
+    }
+}
+
+/// What to exit with after a failed run: whatever the `CacherError` underneath says, or 1 for
+/// anything else.
+fn exit_code(e: &anyhow::Error) -> i32 {
+    e.downcast_ref::<CacherError>()
+        .map_or(1, CacherError::exit_code)
+}
+
+/// Adds up the size of a file, or of every file in a directory.
+fn size_on_disk(path: &str) -> u64 {
+    let Ok(metadata) = fs::metadata(path) else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Writes the summary of a run, prints it too with `--json`, and sends it to the webhook if
+/// there is one.
+async fn summarize(
+    args: &ScrapeArgs,
+    config: &Config,
+    result: &Result<ScrapeReport, ScrapeError>,
+    stats: ScrapeStats,
+    started: Instant,
+) {
+    let report = result.as_ref().ok();
+    let usage = measure(started);
+    log_usage(&usage);
+    record_usage(&usage);
+
+    let (run_result, error) = match result {
+        Ok(_) => (RunResult::Finished, None),
+        Err(ScrapeError::Interrupted) => (RunResult::Interrupted, None),
+        Err(ScrapeError::Failed(e)) => (RunResult::Failed, Some(format!("{:#}", e))),
+    };
+
+    let summary = RunSummary {
+        result: run_result,
+        dry_run: args.dry_run,
+        error,
+        stats,
+        total_maps: report.map(|report| report.maps),
+        usage,
+        output: report.map(|report| report.path.clone()),
+        output_bytes: report.map(|report| report.cache_bytes),
+    };
+
+    if let Err(e) = write_summary(&summary, &args.summary) {
+        error!("Couldn't write run summary {}: {}", args.summary, e);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+    }
+
+    if let Some(webhook) = &config.webhook {
+        notify_webhook(webhook, &config.http, &summary).await;
+    }
+}
+
+/// The given filters, with whatever the flags add on top.
+fn filter_config(args: &ScrapeArgs, filter: &FilterConfig) -> FilterConfig {
+    let mut filter_config = filter.clone();
+
+    if args.ranked_only {
+        filter_config.ranked_only = true;
+    }
+
+    if args.curated_only {
+        filter_config.curated_only = true;
+    }
+
+    if args.verified_mappers_only {
+        filter_config.verified_mappers_only = true;
+    }
+
+    if args.include_automapped {
+        filter_config.exclude_automapped = false;
+    }
+
+    if args.include_unpublished {
+        filter_config.published_only = false;
+    }
+
+    filter_config
+}
+
+/// How many of the newest maps a dry run shows.
+const DRY_RUN_SAMPLES: usize = 3;
+
+/// Says what a real run would have written to `target`, without writing it.
+fn report_dry_run(args: &ScrapeArgs, target: &str, maps: &MapList) -> anyhow::Result<ScrapeReport> {
+    let estimated_bytes =
+        estimate_cache_size(maps, args.intern_names).context("Couldn't estimate the cache size")?;
+
+    let mut newest: Vec<_> = maps.map_metadata.values().collect();
+    newest.sort_by_key(|map| std::cmp::Reverse(map.uploaded));
+    newest.truncate(DRY_RUN_SAMPLES);
+
+    // with --json, stdout is for the summary only
+    if !args.json {
+        println!(
+            "Dry run, would have written {} maps (about {} bytes) to {}",
+            maps.map_metadata.len(),
+            estimated_bytes,
+            target
+        );
+
+        for map in newest {
+            println!("{}", serde_json::to_string_pretty(map)?);
+        }
+    }
+
+    Ok(ScrapeReport {
+        path: target.to_string(),
+        maps: maps.map_metadata.len(),
+        cache_bytes: estimated_bytes,
+    })
+}
+
+async fn run_scrape(
+    args: &ScrapeArgs,
+    config: &Config,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeReport, ScrapeError> {
+    if !config.stores.is_empty()
+        && (!config.profiles.is_empty() || args.spill.is_some() || args.shard_by.is_some())
+    {
+        warn!("[Stores] Only a plain single-file cache goes to [stores], not saving to any");
+    }
+
+    // the daemon does one run after the other, each one counts from zero
+    reset_usage();
+
+    if !config.profiles.is_empty() {
+        return run_profiles(args, config, stats).await;
+    }
+
+    let started = Instant::now();
+    let filter_config = filter_config(args, &config.filter);
+    // maps listed by hand are wanted whatever the filters would say
+    let filters = match args.from_list {
+        Some(_) => FilterPipeline::none(),
+        None => FilterPipeline::from_config(&filter_config)?,
+    };
+    let beatsaver_api = ApiClient::new(RateLimiter::new(args.rate_limit, args.burst), &config.http)
+        .context("Couldn't set up the HTTP client")?
+        .include_automapped(!filter_config.exclude_automapped)
+        .archive_pages(args.archive_dir.as_deref())
+        .context("Couldn't create the archive directory")?;
+
+    if let Some(spill_path) = &args.spill {
+        let mut store = SpillStore::create(spill_path)
+            .with_context(|| format!("Couldn't create spill file {}", spill_path))?;
+
+        let options = ScrapeOptions {
+            stop_at: args.since.map(|since| since.timestamp()),
+            start_at: args.until.map(|until| until.timestamp()),
+            concurrency: args.concurrency,
+            max_retries: args.max_retries,
+            profiles: vec![Profile {
+                filters,
+                fields: config.fields.clone(),
+            }],
+            quarantine_dir: quarantine_dir(args),
+            listing: listing(args),
+        };
+
+        let result = fill_stores(
+            args,
+            &beatsaver_api,
+            std::slice::from_mut(&mut store),
+            &options,
+            stats,
+        )
+        .await;
+
+        if !matches!(result, Ok(ScrapeOutcome::Finished)) {
+            let partial = partial_path(&args.output);
+            report_partial(
+                &partial,
+                write_spilled_cache(store, &partial, args.intern_names).await,
+            );
+            return Err(unfinished(result));
+        }
+
+        let maps = store.len();
+
+        write_spilled_cache(store, &args.output, args.intern_names)
+            .await
+            .with_context(|| format!("Couldn't write {}", args.output))?;
+
+        write_checksum(&args.output).context("Couldn't write the checksum")?;
+
+        if let Some(key_path) = &args.signing_key {
+            write_signature(&args.output, key_path).context("Couldn't sign the cache")?;
+        }
+
+        chunk(args, &args.output)?;
+        upload(args, config, &args.output, None, Vec::new(), Vec::new()).await?;
+
+        return Ok(ScrapeReport {
+            path: args.output.clone(),
+            maps,
+            cache_bytes: size_on_disk(&args.output),
+        });
+    }
+
+    let existing = if args.update {
+        load_existing_cache(&args.output, args.allow_full_rescrape)?
+    } else {
+        None
+    };
+
+    // when updating from a cache that already goes past --since, there's no point going back further
+    let stop_at = existing
+        .as_ref()
+        .and_then(newest_upload)
+        .max(args.since.map(|since| since.timestamp()));
+    // what the delta and the journal are from, there's nothing to diff against without an
+    // existing cache
+    let base = (args.delta_dir.is_some() || args.journal)
+        .then(|| existing.clone())
+        .flatten();
+    let mut maps = existing.unwrap_or_default();
+
+    let options = ScrapeOptions {
+        stop_at,
+        start_at: args.until.map(|until| until.timestamp()),
+        concurrency: args.concurrency,
+        max_retries: args.max_retries,
+        profiles: vec![Profile {
+            filters,
+            fields: config.fields.clone(),
+        }],
+        quarantine_dir: quarantine_dir(args),
+        listing: listing(args),
+    };
+
+    let result = match &args.from_list {
+        Some(list) => fetch_listed(&beatsaver_api, &mut maps, list, &options, stats).await,
+        None => {
+            fill_stores(
+                args,
+                &beatsaver_api,
+                std::slice::from_mut(&mut maps),
+                &options,
+                stats,
+            )
+            .await
+        }
+    };
+
+    if !matches!(result, Ok(ScrapeOutcome::Finished)) {
+        if !args.dry_run {
+            let partial = partial_path(&args.output);
+            report_partial(
+                &partial,
+                write_cache(&maps, &partial, args.intern_names).await,
+            );
+        }
+
+        return Err(unfinished(result));
+    }
+
+    enrich(args, &config.http, &mut [(&mut maps, &config.fields)]).await;
+
+    if args.dry_run {
+        let target = match args.shard_by {
+            Some(_) => &args.shard_dir,
+            None => &args.output,
+        };
+
+        return report_dry_run(args, target, &maps).map_err(ScrapeError::Failed);
+    }
+
+    if let Some(history_path) = &args.hash_history {
+        append_hash_history(&maps, history_path)
+            .with_context(|| format!("Couldn't update hash history {}", history_path))?;
+    }
+
+    // between compactions only the changes get written, everything that goes by the cache file
+    // stays as it was
+    if args.journal
+        && let Some(base) = &base
+        && append_journal(&args.output, base, &maps, args.compact_after)
+            .with_context(|| format!("Couldn't append to the journal of {}", args.output))?
+            .is_some()
+    {
+        return Ok(ScrapeReport {
+            path: args.output.clone(),
+            maps: maps.map_metadata.len(),
+            cache_bytes: size_on_disk(&args.output),
+        });
+    }
+
+    // only set when there's a single cache file, shards already have a manifest of their own
+    let mut uncompressed_bytes = None;
+
+    // in sharded mode the manifest carries each shard's hash, so that's what gets the sidecars
+    let path = match args.shard_by {
+        Some(shard_by) => {
+            write_sharded_cache(&maps, &args.shard_dir, shard_by, args.intern_names)
+                .await
+                .with_context(|| format!("Couldn't write shards to {}", args.shard_dir))?;
+
+            Path::new(&args.shard_dir)
+                .join("manifest.json")
+                .to_string_lossy()
+                .to_string()
+        }
+        None => {
+            if args.update {
+                backup_cache(&args.output)
+                    .with_context(|| format!("Couldn't back up {}", args.output))?;
+            }
+
+            uncompressed_bytes = Some(
+                write_cache(&maps, &args.output, args.intern_names)
+                    .await
+                    .with_context(|| format!("Couldn't write {}", args.output))?,
+            );
+
+            if args.journal {
+                start_journal(&args.output)
+                    .with_context(|| format!("Couldn't start a journal for {}", args.output))?;
+            } else {
+                // whatever was in it got read in with the cache, or rescraped
+                remove_journal(&args.output)
+                    .with_context(|| format!("Couldn't remove the journal of {}", args.output))?;
+            }
+
+            args.output.clone()
+        }
+    };
+
+    let mut extra = Vec::new();
+    let mut delta = None;
+
+    if let (Some(dir), Some(base)) = (&args.delta_dir, &base) {
+        let delta_path = write_delta(base, &maps, dir).context("Couldn't write the delta")?;
+        delta = Some((delta_path.to_string_lossy().to_string(), base));
+        extra.push(delta_path);
+    }
+
+    if let Some(index_path) = &args.hash_index {
+        write_hash_index(&maps, index_path)
+            .with_context(|| format!("Couldn't write hash index {}", index_path))?;
+        extra.push(PathBuf::from(index_path));
+    }
+
+    if args.descriptor_set {
+        let desc_path = descriptor_path(&args.output);
+        write_descriptor_set(&desc_path).context("Couldn't write the descriptor set")?;
+        extra.push(desc_path);
+    }
+
+    let artifacts = Artifacts {
+        cache: args.shard_by.is_none().then(|| args.output.clone()),
+        shard_dir: args.shard_by.is_some().then(|| args.shard_dir.clone()),
+        hash_history: args.hash_history.clone(),
+        hash_index: args.hash_index.clone(),
+        delta,
+    };
+
+    // nothing gets checksummed, signed or uploaded unless everything we wrote agrees
+    verify_artifacts(&maps, &artifacts).context("Not publishing, artifacts don't agree")?;
+
+    write_checksum(&path).context("Couldn't write the checksum")?;
+
+    if let Some(key_path) = &args.signing_key {
+        write_signature(&path, key_path).context("Couldn't sign the cache")?;
+    }
+
+    let mut manifests = Vec::new();
+
+    if let Some(uncompressed_bytes) = uncompressed_bytes {
+        let manifest = manifest_path(&args.output, None);
+        write_manifest(
+            &maps,
+            &args.output,
+            &manifest,
+            uncompressed_bytes,
+            &measure(started),
+        )
+        .context("Couldn't write the manifest")?;
+        manifests.push(manifest);
+    }
+
+    chunk(args, &path)?;
+
+    if args.shard_by.is_none() {
+        save_to_stores(&config.stores, &config.s3, &maps, Path::new(&args.output)).await?;
+    }
+
+    let shard_dir = args.shard_by.is_some().then_some(args.shard_dir.as_str());
+    upload(args, config, &path, shard_dir, extra, manifests).await?;
+
+    write_assets(args, config, &[&maps]).await;
+
+    let cache_bytes = match args.shard_by {
+        Some(_) => size_on_disk(&args.shard_dir),
+        None => size_on_disk(&args.output),
+    };
+
+    Ok(ScrapeReport {
+        path,
+        maps: maps.map_metadata.len(),
+        cache_bytes,
+    })
+}
+
+/// Runs whichever enrichment passes were asked for over finished scrapes. They only ever correct
+/// what BeatSaver said, so one failing is logged and the caches go out as they are. Caches that
+/// leave a pass's fields out are skipped by it.
+async fn enrich(args: &ScrapeArgs, http: &HttpConfig, stores: &mut [(&mut MapList, &FieldConfig)]) {
+    let ranked = stores.iter().any(|(_, fields)| fields.ranked);
+    let curator = stores.iter().any(|(_, fields)| fields.curator);
+
+    if args.beatleader && ranked {
+        match beatleader::fetch_ranking(http).await {
+            Ok(ranking) => {
+                for (maps, _) in stores.iter_mut().filter(|(_, fields)| fields.ranked) {
+                    let changed = beatleader::apply_ranking(maps, &ranking);
+                    info!("[BeatLeader] Corrected {} difficulties", changed);
+                }
+            }
+            Err(e) => warn!(
+                "[BeatLeader] Couldn't fetch ranked data, keeping BeatSaver's: {:?}",
+                e
+            ),
+        }
+    }
+
+    if args.scoresaber && ranked {
+        match scoresaber::fetch_ranking(http).await {
+            Ok(ranking) => {
+                for (maps, _) in stores.iter_mut().filter(|(_, fields)| fields.ranked) {
+                    let changed = scoresaber::apply_ranking(maps, &ranking);
+                    info!("[ScoreSaber] Corrected {} difficulties", changed);
+                }
+            }
+            Err(e) => warn!(
+                "[ScoreSaber] Couldn't fetch ranked data, keeping BeatSaver's: {:?}",
+                e
+            ),
+        }
+    }
+
+    if args.hitbloq && ranked {
+        match hitbloq::fetch_pools(http).await {
+            Ok(pools) => {
+                for (maps, _) in stores.iter_mut().filter(|(_, fields)| fields.ranked) {
+                    let pooled = hitbloq::apply_pools(maps, &pools);
+                    info!("[Hitbloq] {} difficulties are in a pool", pooled);
+                }
+            }
+            Err(e) => warn!("[Hitbloq] Couldn't fetch map pools: {:?}", e),
+        }
+    }
+
+    if args.accsaber && ranked {
+        match accsaber::fetch_categories(http).await {
+            Ok(categories) => {
+                for (maps, _) in stores.iter_mut().filter(|(_, fields)| fields.ranked) {
+                    let categorized = accsaber::apply_categories(maps, &categories);
+                    info!("[AccSaber] {} difficulties are ranked", categorized);
+                }
+            }
+            Err(e) => warn!("[AccSaber] Couldn't fetch ranked maps: {:?}", e),
+        }
+    }
+
+    if args.bsaber && curator {
+        let keys: HashSet<String> = stores
+            .iter()
+            .filter(|(_, fields)| fields.curator)
+            .flat_map(|(maps, _)| maps.map_metadata.keys().cloned())
+            .collect();
+
+        match bsaber::fetch_curation(http, &keys).await {
+            Ok(curation) => {
+                for (maps, _) in stores.iter_mut().filter(|(_, fields)| fields.curator) {
+                    let curated = bsaber::apply_curation(maps, &curation);
+                    info!("[BeastSaber] {} maps are curated", curated);
+                }
+            }
+            Err(e) => warn!("[BeastSaber] Couldn't fetch curated maps: {:?}", e),
+        }
+    }
+}
+
+/// Applies `deltas` to the cache at `path` one after the other, writing it back only if they all
+/// applied.
+async fn run_apply_delta(deltas: &[String], path: &str) -> anyhow::Result<()> {
+    let mut maps =
+        read_cache_with_journal(path).with_context(|| format!("Couldn't read {}", path))?;
+
+    for delta in deltas {
+        let read = read_delta(delta).with_context(|| format!("Couldn't read {}", delta))?;
+        apply_delta(&mut maps, read).with_context(|| format!("Couldn't apply {}", delta))?;
+        info!("Applied {}", delta);
+    }
+
+    backup_cache(path).with_context(|| format!("Couldn't back up {}", path))?;
+    write_cache(&maps, path, false)
+        .await
+        .with_context(|| format!("Couldn't write {}", path))?;
+    restart_journal(path).with_context(|| format!("Couldn't restart the journal of {}", path))?;
+    write_checksum(path).context("Couldn't write the checksum")?;
+
+    Ok(())
+}
+
+/// Writes every map still intact in the cache at `input` to `output`, and prints what didn't make
+/// it.
+async fn run_repair(input: &str, output: &str) -> anyhow::Result<()> {
+    let (mut maps, report) =
+        salvage_cache_file(input).with_context(|| format!("Couldn't read {}", input))?;
+
+    // the journal goes by the envelope, which is still fine if anything was salvaged
+    if let Err(e) = replay_journal(&mut maps, input) {
+        warn!("Couldn't replay the journal of {}: {:?}", input, e);
+    }
+
+    info!(
+        "Salvaged {} of {} maps from {}",
+        report.salvaged, report.expected, input
+    );
+    if report.lost > 0 || report.truncated {
+        warn!(
+            "Lost {} maps, {} of them corrupt{}",
+            report.lost,
+            report.corrupt.len(),
+            if report.truncated {
+                ", the rest cut off"
+            } else {
+                ""
+            }
+        );
+    }
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    write_cache(&maps, output, false)
+        .await
+        .with_context(|| format!("Couldn't write {}", output))?;
+    restart_journal(output)
+        .with_context(|| format!("Couldn't restart the journal of {}", output))?;
+    write_checksum(output).context("Couldn't write the checksum")?;
+
+    Ok(())
+}
+
+/// Cuts the cache at `path` into chunks, if `--chunk-dir` was given.
+fn chunk(args: &ScrapeArgs, path: &str) -> anyhow::Result<()> {
+    if let Some(dir) = &args.chunk_dir {
+        write_chunks(path, dir, args.chunk_size.max(1))
+            .with_context(|| format!("Couldn't cut {} into chunks", path))?;
+    }
+
+    Ok(())
+}
+
+/// Uploads everything a run published, if there's somewhere to upload to. `first` goes up before
+/// the cache and `last` after it, e.g. deltas and manifests.
+async fn upload(
+    args: &ScrapeArgs,
+    config: &Config,
+    path: &str,
+    shard_dir: Option<&str>,
+    first: Vec<PathBuf>,
+    last: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let Some(target) = args.upload.as_ref().or(config.upload.target.as_ref()) else {
+        return Ok(());
+    };
+
+    let configs = UploadConfigs {
+        http: &config.http,
+        s3: &config.s3,
+        github: &config.github,
+        put: &config.put,
+    };
+    let mut files = first;
+    files.extend(artifact_files(path, shard_dir)?);
+    files.extend(last);
+
+    upload_files(target, &configs, &files)
+        .await
+        .with_context(|| format!("Couldn't upload {}", path))
+}
+
+/// Writes whatever was asked for next to the caches once they're out. The caches are fine
+/// without them, so one failing is only logged.
+async fn write_assets(args: &ScrapeArgs, config: &Config, map_lists: &[&MapList]) {
+    if let Some(path) = &args.cover_pack
+        && let Err(e) = write_cover_pack(map_lists, &config.covers, &config.http, path).await
+    {
+        warn!("[Covers] Couldn't write the cover pack {}: {:?}", path, e);
+    }
+
+    if let Some(dir) = &args.preview_dir
+        && let Err(e) = mirror_previews(map_lists, &config.previews, &config.http, dir).await
+    {
+        warn!("[Previews] Couldn't mirror previews into {}: {:?}", dir, e);
+    }
+}
+
+/// Scrapes BeatSaver into `stores`, or converts what `--from-saved` points at instead.
+async fn fill_stores<S: MapStore>(
+    args: &ScrapeArgs,
+    client: &ApiClient,
+    stores: &mut [S],
+    options: &ScrapeOptions,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeOutcome, CacherError> {
+    match &args.from_saved {
+        Some(path) => {
+            info!("[Scraper] Reading saved maps from {}", path);
+            scrape_saved(open_saved(path)?.as_mut(), stores, options, stats).await
+        }
+        None => init_cache(client, stores, options, stats).await,
+    }
+}
+
+/// Fills `maps` with the maps listed in `path`, instead of scraping for them.
+async fn fetch_listed(
+    client: &ApiClient,
+    maps: &mut MapList,
+    path: &str,
+    options: &ScrapeOptions,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeOutcome, CacherError> {
+    let mut selection = MapSelection::default();
+    read_selection(path, &mut selection)?;
+    info!(
+        "[Scraper] Fetching the {} maps in {}",
+        selection.len(),
+        path
+    );
+
+    let profile = &options.profiles[0];
+    refresh_selection(
+        client,
+        maps,
+        &selection,
+        profile,
+        options.max_retries,
+        stats,
+    )
+    .await?;
+
+    Ok(ScrapeOutcome::Finished)
+}
+
+/// Patches `keys` into the cache at `path`, using the main filters and fields from the config.
+async fn run_refresh(
+    keys: Vec<String>,
+    keys_file: Option<&str>,
+    path: &str,
+    max_retries: u32,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let mut selection = MapSelection::default();
+
+    for key in &keys {
+        selection.push(key);
+    }
+
+    if let Some(keys_file) = keys_file {
+        read_selection(keys_file, &mut selection)
+            .with_context(|| format!("Couldn't read {}", keys_file))?;
+    }
+
+    if selection.is_empty() {
+        return Err(anyhow!("No keys to refresh"));
+    }
+
+    let mut maps =
+        read_cache_with_journal(path).with_context(|| format!("Couldn't read {}", path))?;
+    let profile = Profile {
+        filters: FilterPipeline::from_config(&config.filter)?,
+        fields: config.fields.clone(),
+    };
+    let beatsaver_api = ApiClient::new(RateLimiter::new(10.0, 1), &config.http)
+        .context("Couldn't set up the HTTP client")?;
+    let mut stats = ScrapeStats::default();
+
+    refresh_selection(
+        &beatsaver_api,
+        &mut maps,
+        &selection,
+        &profile,
+        max_retries,
+        &mut stats,
+    )
+    .await
+    .context("Couldn't refresh the maps")?;
+
+    info!(
+        "[Refresh] {} new, {} updated, {} removed",
+        stats.new_maps, stats.updated_maps, stats.removed_maps
+    );
+
+    backup_cache(path).with_context(|| format!("Couldn't back up {}", path))?;
+    write_cache(&maps, path, false)
+        .await
+        .with_context(|| format!("Couldn't write {}", path))?;
+    restart_journal(path).with_context(|| format!("Couldn't restart the journal of {}", path))?;
+    write_checksum(path).context("Couldn't write the checksum")?;
+
+    Ok(())
+}
+
+/// Builds every profile in the config from one scrape, instead of the usual single cache.
+async fn run_profiles(
+    args: &ScrapeArgs,
+    config: &Config,
+    stats: &mut ScrapeStats,
+) -> Result<ScrapeReport, ScrapeError> {
+    if args.spill.is_some()
+        || args.shard_by.is_some()
+        || args.hash_history.is_some()
+        || args.from_list.is_some()
+    {
+        return Err(anyhow!(
+            "Profiles don't work with --spill, --shard-by, --hash-history or --from-list"
+        )
+        .into());
+    }
+
+    let started = Instant::now();
+
+    let mut profiles = Vec::new();
+    let mut include_automapped = false;
+
+    for profile in &config.profiles {
+        let filter_config = filter_config(args, &profile.filter);
+        include_automapped |= !filter_config.exclude_automapped;
+
+        profiles.push(Profile {
+            filters: FilterPipeline::from_config(&filter_config).with_context(|| {
+                format!("Couldn't set up the filters of profile {}", profile.name)
+            })?,
+            fields: profile.fields.clone(),
+        });
+    }
+
+    let beatsaver_api = ApiClient::new(RateLimiter::new(args.rate_limit, args.burst), &config.http)
+        .context("Couldn't set up the HTTP client")?
+        .include_automapped(include_automapped)
+        .archive_pages(args.archive_dir.as_deref())
+        .context("Couldn't create the archive directory")?;
+
+    let mut stores = Vec::new();
+
+    for profile in &config.profiles {
+        let existing = if args.update {
+            if profile.format.is_some() {
+                return Err(anyhow!(
+                    "Profile {} isn't protobuf, so it can't be updated",
+                    profile.name
+                )
+                .into());
+            }
+
+            load_existing_cache(&profile.output, args.allow_full_rescrape)?
+        } else {
+            None
+        };
+
+        stores.push(existing.unwrap_or_default());
+    }
+
+    // only skip what every profile already has, so the most out of date one decides
+    let newest = stores.iter().map(newest_upload).min().flatten();
+    let stop_at = newest.max(args.since.map(|since| since.timestamp()));
+
+    let options = ScrapeOptions {
+        stop_at,
+        start_at: args.until.map(|until| until.timestamp()),
+        concurrency: args.concurrency,
+        max_retries: args.max_retries,
+        profiles,
+        quarantine_dir: quarantine_dir(args),
+        listing: listing(args),
+    };
+
+    let result = fill_stores(args, &beatsaver_api, &mut stores, &options, stats).await;
+
+    if !matches!(result, Ok(ScrapeOutcome::Finished)) {
+        if !args.dry_run {
+            for (profile, maps) in config.profiles.iter().zip(&stores) {
+                let partial = partial_path(&profile.output);
+                report_partial(
+                    &partial,
+                    write_cache(maps, &partial, args.intern_names).await,
+                );
+            }
+        }
+
+        return Err(unfinished(result));
+    }
+
+    let mut enriched: Vec<(&mut MapList, &FieldConfig)> = stores
+        .iter_mut()
+        .zip(config.profiles.iter().map(|profile| &profile.fields))
+        .collect();
+    enrich(args, &config.http, &mut enriched).await;
+
+    let mut report = ScrapeReport {
+        path: String::new(),
+        maps: 0,
+        cache_bytes: 0,
+    };
+    let mut paths = Vec::new();
+
+    for (profile, maps) in config.profiles.iter().zip(&stores) {
+        if args.dry_run {
+            let dry_run = report_dry_run(args, &profile.output, maps)?;
+            report.maps += dry_run.maps;
+            report.cache_bytes += dry_run.cache_bytes;
+            paths.push(dry_run.path);
+            continue;
+        }
+
+        let written = match profile.format {
+            Some(format) => export_cache(maps, format, &profile.output).map(|()| None),
+            None => {
+                if args.update {
+                    backup_cache(&profile.output)
+                        .with_context(|| format!("Couldn't back up {}", profile.output))?;
+                }
+
+                write_cache(maps, &profile.output, args.intern_names)
+                    .await
+                    .map(Some)
+            }
+        };
+
+        // only protobuf caches have an uncompressed size to put in a manifest
+        let uncompressed_bytes = written.with_context(|| {
+            format!(
+                "Couldn't write profile {} to {}",
+                profile.name, profile.output
+            )
+        })?;
+
+        // the other formats can't be read back, so only protobuf gets checked
+        if profile.format.is_none() {
+            let artifacts = Artifacts {
+                cache: Some(profile.output.clone()),
+                shard_dir: None,
+                hash_history: None,
+                hash_index: None,
+                delta: None,
+            };
+
+            verify_artifacts(maps, &artifacts).with_context(|| {
+                format!(
+                    "Not publishing profile {}, it doesn't read back right",
+                    profile.name
+                )
+            })?;
+        }
+
+        write_checksum(&profile.output).context("Couldn't write the checksum")?;
+
+        if let Some(key_path) = &args.signing_key {
+            write_signature(&profile.output, key_path).context("Couldn't sign the cache")?;
+        }
+
+        // the other formats aren't what the manifest describes
+        let mut manifests = Vec::new();
+
+        if let Some(uncompressed_bytes) = uncompressed_bytes {
+            let manifest = manifest_path(&profile.output, Some(&profile.name));
+            write_manifest(
+                maps,
+                &profile.output,
+                &manifest,
+                uncompressed_bytes,
+                &measure(started),
+            )
+            .context("Couldn't write the manifest")?;
+            manifests.push(manifest);
+        }
+
+        chunk(args, &profile.output)?;
+        upload(args, config, &profile.output, None, Vec::new(), manifests).await?;
+
+        info!(
+            "Profile {}: {} maps in {}",
+            profile.name,
+            maps.map_metadata.len(),
+            profile.output
+        );
+
+        report.maps += maps.map_metadata.len();
+        report.cache_bytes += size_on_disk(&profile.output);
+        paths.push(profile.output.clone());
+    }
+
+    if !args.dry_run {
+        let map_lists: Vec<&MapList> = stores.iter().collect();
+        write_assets(args, config, &map_lists).await;
+    }
+
+    report.path = paths.join(", ");
+    Ok(report)
+}
+
+async fn scrape(args: ScrapeArgs, config: Config) {
+    let started = Instant::now();
+    let mut stats = ScrapeStats::default();
+
+    let result = run_scrape(&args, &config, &mut stats).await;
+    summarize(&args, &config, &result, stats, started).await;
+
+    match result {
+        Ok(_) => {}
+        Err(ScrapeError::Interrupted) => std::process::exit(INTERRUPTED_EXIT_CODE),
+        Err(ScrapeError::Failed(e)) => {
+            error!("{:?}", e);
+            std::process::exit(exit_code(&e));
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    let cli = Cli::parse();
+
+    init_logging(cli.log_format);
+
+    let config = match load_config(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Couldn't load config: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command {
+        None => scrape(cli.scrape, config).await,
+        Some(Command::Export {
+            format,
+            input,
+            output,
+            exclusion_feed,
+            search_index,
+        }) => match read_cache_with_journal(&input) {
+            Ok(mut maps) => {
+                if let Some(source) = &exclusion_feed {
+                    match load_exclusion_feed(source).await {
+                        Ok(excluded) => {
+                            let flagged = flag_recently_played(&mut maps, &excluded);
+                            info!("Flagged {} maps as recently played", flagged);
+                        }
+                        Err(e) => {
+                            error!("Couldn't load exclusion feed {}: {:?}", source, e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+
+                if let Err(e) = export_cache(&maps, format, &output) {
+                    error!("Couldn't export to {}: {:?}", output, e);
+                    std::process::exit(e.exit_code());
+                }
+
+                if let Some(dir) = &search_index
+                    && let Err(e) = SearchIndex::build(&maps, Some(dir))
+                {
+                    error!("Couldn't build the search index in {}: {:?}", dir, e);
+                    std::process::exit(e.exit_code());
+                }
+            }
+            Err(e) => {
+                error!("Couldn't read {}: {:?}", input, e);
+                std::process::exit(e.exit_code());
+            }
+        },
+        Some(Command::Playlist {
+            input,
+            output,
+            title,
+            author,
+            description,
+            image,
+            query,
+        }) => {
+            let query = map_query(query);
+            let info = PlaylistInfo {
+                title: &title,
+                author: &author,
+                description: description.as_deref(),
+                image: image.as_deref(),
+            };
+
+            let written = read_cache_with_journal(&input)
+                .and_then(|maps| write_playlist(&maps, &query, &info, &output));
+
+            if let Err(e) = written {
+                error!("Couldn't make playlist {} from {}: {:?}", output, input, e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Some(Command::Mirror {
+            input,
+            dir,
+            concurrency,
+            query,
+        }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            let mirrored =
+                mirror_maps(&maps, &map_query(query), &config.http, &dir, concurrency).await;
+
+            if let Err(e) = mirrored {
+                error!("Couldn't mirror maps into {}: {:?}", dir, e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::ApplyDelta { deltas, cache }) => {
+            if let Err(e) = run_apply_delta(&deltas, &cache).await {
+                error!("{:?}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+        Some(Command::Migrate { input, output }) => {
+            let output = output.unwrap_or_else(|| input.clone());
+
+            if let Err(e) = migrate_cache(&input, &output).await {
+                error!("Couldn't migrate {}: {:?}", input, e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Some(Command::Repair { input, output }) => {
+            let output = output.unwrap_or_else(|| format!("{}.repaired", input));
+
+            if let Err(e) = run_repair(&input, &output).await {
+                error!("{:?}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+        Some(Command::Lookup { ids, input }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+            let index = HashIndex::build(&maps);
+
+            // ids that aren't in the cache come out as null, so the output lines up with the input
+            let found: BTreeMap<&str, Option<serde_json::Value>> = ids
+                .iter()
+                .map(|id| {
+                    let map = index.find(&maps, id).map(|map| {
+                        let mut json = serde_json::to_value(map).unwrap();
+                        name_mods_in_json(&mut json);
+                        json
+                    });
+
+                    (id.as_str(), map)
+                })
+                .collect();
+
+            println!("{}", serde_json::to_string_pretty(&found).unwrap());
+        }
+        Some(Command::Dump {
+            input,
+            limit,
+            offset,
+            format,
+        }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            let mut out = std::io::stdout().lock();
+            if let Err(e) = dump_maps(&maps, offset, limit, format, &mut out) {
+                error!("Couldn't print the maps: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Scan {
+            custom_levels,
+            input,
+            hash_history,
+            all,
+            song_hash_data,
+        }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            let levels = match scan_custom_levels(Path::new(&custom_levels)) {
+                Ok(levels) => levels,
+                Err(e) => {
+                    error!("Couldn't scan {}: {:?}", custom_levels, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            let matcher = LocalMatcher::new(&maps, hash_history.as_deref());
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+
+            for level in &levels {
+                let (state, key) = matcher.classify(level);
+                *counts.entry(state.as_str()).or_insert(0) += 1;
+
+                if state != LocalState::Current || all {
+                    println!(
+                        "{:>8}  {:>6}  {}",
+                        state.as_str(),
+                        key.as_deref().unwrap_or("-"),
+                        level
+                            .folder
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                    );
+                }
+            }
+
+            info!(
+                "[Local] {} maps in {}: {:?}",
+                levels.len(),
+                custom_levels,
+                counts
+            );
+
+            if let Some(path) = &song_hash_data
+                && let Err(e) = write_song_hash_data(&levels, &maps, path)
+            {
+                error!("Couldn't write {}: {:?}", path, e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Some(Command::Query { input, query }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            for (key, map) in map_query(query).select(&maps) {
+                print_map(key, map);
+            }
+        }
+        Some(Command::Search {
+            query,
+            input,
+            index,
+            limit,
+        }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
+
+            let hits = match &index {
+                Some(dir) => SearchIndex::open(dir),
+                None => SearchIndex::build(&maps, None),
+            }
+            .and_then(|index| index.search(&query, limit));
+
+            match hits {
+                Ok(hits) => {
+                    for hit in hits {
+                        if let Some(map) = maps.map_metadata.get(&hit.key) {
+                            print_map(&hit.key, map);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Couldn't search for {:?}: {:?}", query, e);
+                    std::process::exit(e.exit_code());
+                }
+            }
+        }
+        Some(Command::Serve {
+            input,
+            listen,
+            search_index,
+            api_compat,
+            graphql,
+        }) => {
+            let maps = match read_cache_with_journal(&input) {
+                Ok(maps) => maps,
+                Err(e) => {
+                    error!("Couldn't read {}: {:?}", input, e);
+                    std::process::exit(e.exit_code());
+                }
+            };
 
-    let beatsaver_api = BeatSaverClient::default();
+            let search = match SearchIndex::build(&maps, search_index.as_deref()) {
+                Ok(search) => search,
+                Err(e) => {
+                    error!("Couldn't build the search index: {:?}", e);
+                    std::process::exit(e.exit_code());
+                }
+            };
 
-    let maps = init_cache(&beatsaver_api).await;
+            run_serve(maps, search, listen, api_compat, graphql).await
+        }
+        Some(Command::Descriptor { output }) => {
+            if let Err(e) = write_descriptor_set(Path::new(&output)) {
+                error!("Couldn't write {}: {:?}", output, e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Some(Command::GenFixture { maps, seed, output }) => {
+            if let Err(e) = write_cache(&generate_fixture(maps, seed), &output, false).await {
+                error!("Couldn't write {}: {:?}", output, e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        Some(Command::Refresh {
+            keys,
+            keys_file,
+            cache,
+            max_retries,
+        }) => {
+            if let Err(e) =
+                run_refresh(keys, keys_file.as_deref(), &cache, max_retries, &config).await
+            {
+                error!("{:?}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+        Some(Command::Reprocess {
+            archive,
+            scrape: mut args,
+        }) => {
+            args.from_saved = Some(archive);
+            scrape(args, config).await
+        }
+        Some(Command::Daemon {
+            interval,
+            healthy_within,
+            listen,
+            grpc_listen,
+            serve_from,
+            events,
+            scrape,
+        }) => {
+            let healthy_within = healthy_within.unwrap_or(interval * 3);
 
-    write_cache(&maps, "mapData.proto.gz").await;
+            run_daemon(
+                scrape,
+                config,
+                Duration::from_secs(interval),
+                Duration::from_secs(healthy_within),
+                listen,
+                grpc_listen,
+                serve_from,
+                events,
+            )
+            .await
+        }
+    }
 }
@@ -0,0 +1,162 @@
+// answering questions about a cache over HTTP, for tools that would rather not decode it
+
+mod compat;
+mod graphql;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use async_graphql_axum::GraphQL;
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::{
+    cacher::{expr::parse_filter, hash_index::HashIndex, query::MapQuery, search::SearchIndex},
+    mapdata::{MapList, MapMetadata},
+};
+
+/// Most results one request can ask for.
+const MAX_LIMIT: usize = 100;
+
+struct Served {
+    maps: MapList,
+    index: HashIndex,
+    search: SearchIndex,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    filter: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+#[derive(Serialize)]
+struct QueryResult<'a> {
+    key: &'a str,
+    map: &'a MapMetadata,
+}
+
+#[derive(Serialize)]
+struct SearchResult<'a> {
+    key: &'a str,
+    score: f32,
+    map: &'a MapMetadata,
+}
+
+async fn search(State(served): State<Arc<Served>>, Query(params): Query<SearchParams>) -> Response {
+    let hits = match served.search.search(&params.q, params.limit.min(MAX_LIMIT)) {
+        Ok(hits) => hits,
+        Err(e) => {
+            error!("Search for {:?} failed: {:?}", params.q, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let results: Vec<SearchResult> = hits
+        .iter()
+        .filter_map(|hit| {
+            Some(SearchResult {
+                key: &hit.key,
+                score: hit.score,
+                map: served.maps.map_metadata.get(&hit.key)?,
+            })
+        })
+        .collect();
+
+    Json(results).into_response()
+}
+
+/// Maps matching a `--filter` expression, newest first. A filter that doesn't parse gets a 400
+/// saying why.
+async fn query(State(served): State<Arc<Served>>, Query(params): Query<QueryParams>) -> Response {
+    let filter = match parse_filter(&params.filter) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let query = MapQuery {
+        filter: Some(filter),
+        limit: Some(params.limit.min(MAX_LIMIT)),
+        ..Default::default()
+    };
+
+    let results: Vec<QueryResult> = query
+        .select(&served.maps)
+        .into_iter()
+        .map(|(key, map)| QueryResult { key, map })
+        .collect();
+
+    Json(results).into_response()
+}
+
+/// Serves `maps` on `listen` until Ctrl-C. With `api_compat`, BeatSaver's own map and search
+/// endpoints are answered too, and with `graphql`, GraphQL queries on `/graphql`.
+pub async fn run_serve(
+    maps: MapList,
+    search: SearchIndex,
+    listen: SocketAddr,
+    api_compat: bool,
+    graphql: bool,
+) {
+    let listener = match TcpListener::bind(listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Couldn't listen on {}: {:?}", listen, e);
+            std::process::exit(1);
+        }
+    };
+
+    let count = maps.map_metadata.len();
+    let index = HashIndex::build(&maps);
+    let served = Arc::new(Served {
+        maps,
+        index,
+        search,
+    });
+    let mut router = Router::new()
+        .route("/search", get(search))
+        .route("/query", get(query));
+
+    if api_compat {
+        router = router.merge(compat::routes());
+    }
+
+    if graphql {
+        router = router.route_service("/graphql", GraphQL::new(graphql::schema(served.clone())));
+    }
+
+    let app = router.with_state(served);
+
+    info!("Serving {} maps on http://{}", count, listen);
+
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        error!("Server stopped: {:?}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,386 @@
+// enough of BeatSaver's own API, answered from the cache, that mods can be pointed at a mirror
+// while BeatSaver is down. only what the cache knows comes back, so fields like descriptions
+// are missing
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    cacher::{mods::ModFlags, query::MapQuery},
+    mapdata::{Difficulty, MapMetadata, RankedState, RankedValue},
+    serve::Served,
+};
+
+/// BeatSaver's page size for searches.
+const PAGE_SIZE: usize = 20;
+
+/// What BeatSaver answers with for a map it doesn't have.
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "Not Found" })),
+    )
+        .into_response()
+}
+
+fn timestamp(secs: i64) -> Option<String> {
+    DateTime::from_timestamp(secs, 0).map(|at| at.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiUser<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u32>,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMetadata<'a> {
+    bpm: f32,
+    duration: u32,
+    song_name: &'a str,
+    song_sub_name: &'a str,
+    song_author_name: &'a str,
+    level_author_name: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiStats {
+    plays: u32,
+    downloads: u32,
+    upvotes: u32,
+    downvotes: u32,
+    score: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiParitySummary {
+    errors: u32,
+    warns: u32,
+    resets: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiDifficulty<'a> {
+    njs: f32,
+    notes: u32,
+    bombs: u32,
+    obstacles: u32,
+    events: u32,
+    nps: f32,
+    seconds: f32,
+    characteristic: &'a str,
+    difficulty: &'a str,
+    chroma: bool,
+    me: bool,
+    ne: bool,
+    cinema: bool,
+    vivify: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parity_summary: Option<ApiParitySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stars: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bl_stars: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_score: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+    environment: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiVersion<'a> {
+    hash: String,
+    state: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diffs: Vec<ApiDifficulty<'a>>,
+    #[serde(rename = "downloadURL", skip_serializing_if = "Option::is_none")]
+    download_url: Option<String>,
+    #[serde(rename = "coverURL", skip_serializing_if = "Option::is_none")]
+    cover_url: Option<String>,
+    #[serde(rename = "previewURL", skip_serializing_if = "Option::is_none")]
+    preview_url: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMap<'a> {
+    id: &'a str,
+    name: &'a str,
+    uploader: ApiUser<'a>,
+    metadata: ApiMetadata<'a>,
+    stats: ApiStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
+    ranked: bool,
+    qualified: bool,
+    bl_ranked: bool,
+    bl_qualified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    curator: Option<ApiUser<'a>>,
+    tags: &'a [String],
+    versions: Vec<ApiVersion<'a>>,
+}
+
+fn is_state(ranked: &RankedValue, state: RankedState) -> bool {
+    ranked
+        .state
+        .map_or(state == RankedState::Ranked && ranked.is_ranked, |actual| {
+            actual == state as i32
+        })
+}
+
+fn api_difficulty(diff: &Difficulty) -> ApiDifficulty<'_> {
+    let stars = |ranked: &RankedValue| ranked.is_ranked.then_some(ranked.stars);
+    let mods = ModFlags::from(diff.mods);
+
+    ApiDifficulty {
+        njs: diff.njs,
+        notes: diff.notes,
+        bombs: diff.bombs(),
+        obstacles: diff.obstacles(),
+        events: diff.events(),
+        nps: diff.nps(),
+        seconds: diff.seconds(),
+        characteristic: &diff.characteristic_name,
+        difficulty: &diff.difficulty_name,
+        cinema: mods.contains(ModFlags::CINEMA),
+        me: mods.contains(ModFlags::MAPPING_EXTENSIONS),
+        chroma: mods.contains(ModFlags::CHROMA),
+        ne: mods.contains(ModFlags::NOODLE_EXTENSIONS),
+        vivify: mods.contains(ModFlags::VIVIFY),
+        parity_summary: diff.parity.as_ref().map(|parity| ApiParitySummary {
+            errors: parity.errors,
+            warns: parity.warns,
+            resets: parity.resets,
+        }),
+        stars: stars(&diff.ranked.score_saber),
+        bl_stars: stars(&diff.ranked.beat_leader),
+        max_score: diff.max_score,
+        label: diff.label.as_deref(),
+        environment: &diff.environment_name,
+    }
+}
+
+/// A map the way BeatSaver's `MapDetail` has it.
+fn api_map<'a>(key: &'a str, map: &'a MapMetadata) -> ApiMap<'a> {
+    let any = |check: &dyn Fn(&Difficulty) -> bool| map.difficulties.iter().any(check);
+    let hash = map.hash.to_lowercase();
+
+    // the current version is the only one the cache has difficulties for
+    let mut versions = vec![ApiVersion {
+        state: "Published",
+        created_at: timestamp(map.uploaded),
+        diffs: map.difficulties.iter().map(api_difficulty).collect(),
+        download_url: map.download_url.clone(),
+        cover_url: map.cover_url.clone(),
+        preview_url: map.preview_url.clone(),
+        hash: hash.clone(),
+    }];
+
+    versions.extend(
+        map.versions
+            .iter()
+            .filter(|version| version.hash.to_lowercase() != hash)
+            .map(|version| ApiVersion {
+                hash: version.hash.to_lowercase(),
+                state: &version.state,
+                created_at: timestamp(version.created),
+                diffs: Vec::new(),
+                download_url: None,
+                cover_url: None,
+                preview_url: None,
+            }),
+    );
+
+    ApiMap {
+        id: key,
+        name: map.song_name(),
+        uploader: ApiUser {
+            id: map.uploader_id,
+            name: map
+                .uploader_name
+                .as_deref()
+                .unwrap_or(map.level_author_name()),
+        },
+        metadata: ApiMetadata {
+            bpm: map.bpm(),
+            duration: map.duration,
+            song_name: map.song_name(),
+            song_sub_name: map.song_sub_name(),
+            song_author_name: map.song_author_name(),
+            level_author_name: map.level_author_name(),
+        },
+        stats: ApiStats {
+            plays: map.plays(),
+            downloads: map.downloads(),
+            upvotes: map.votes.up,
+            downvotes: map.votes.down,
+            score: map.score(),
+        },
+        uploaded: timestamp(map.uploaded),
+        created_at: timestamp(map.created.unwrap_or(map.uploaded)),
+        updated_at: timestamp(map.last_updated),
+        ranked: any(&|diff| is_state(&diff.ranked.score_saber, RankedState::Ranked)),
+        qualified: any(&|diff| is_state(&diff.ranked.score_saber, RankedState::Qualified)),
+        bl_ranked: any(&|diff| is_state(&diff.ranked.beat_leader, RankedState::Ranked)),
+        bl_qualified: any(&|diff| is_state(&diff.ranked.beat_leader, RankedState::Qualified)),
+        curator: map
+            .curator_name
+            .as_deref()
+            .map(|name| ApiUser { id: None, name }),
+        tags: &map.tags,
+        versions,
+    }
+}
+
+async fn map_by_id(State(served): State<Arc<Served>>, Path(id): Path<String>) -> Response {
+    let key = id.to_lowercase();
+
+    match served.maps.map_metadata.get_key_value(&key) {
+        Some((key, map)) => Json(api_map(key, map)).into_response(),
+        None => not_found(),
+    }
+}
+
+/// One hash gets the map back, several (comma-separated) get an object by hash, same as
+/// BeatSaver.
+async fn maps_by_hash(State(served): State<Arc<Served>>, Path(hashes): Path<String>) -> Response {
+    let find = |hash: &str| {
+        let key = served.index.key(hash)?;
+        served.maps.map_metadata.get_key_value(key)
+    };
+
+    if !hashes.contains(',') {
+        return match find(&hashes) {
+            Some((key, map)) => Json(api_map(key, map)).into_response(),
+            None => not_found(),
+        };
+    }
+
+    let found: BTreeMap<String, Option<ApiMap>> = hashes
+        .split(',')
+        .map(|hash| {
+            let map = find(hash).map(|(key, map)| api_map(key, map));
+            (hash.to_lowercase(), map)
+        })
+        .collect();
+
+    Json(found).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchParams {
+    #[serde(default)]
+    q: String,
+    /// `Relevance` or `Latest`, anything else is treated as `Latest`.
+    sort_order: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchResponse<'a> {
+    docs: Vec<ApiMap<'a>>,
+}
+
+async fn search_text(
+    State(served): State<Arc<Served>>,
+    page: Option<Path<usize>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    let page = page.map_or(0, |Path(page)| page);
+    let wanted = (page + 1) * PAGE_SIZE;
+    let by_relevance = !params.q.trim().is_empty()
+        && params
+            .sort_order
+            .as_deref()
+            .is_none_or(|order| order == "Relevance");
+
+    let keys: Vec<String> = if by_relevance {
+        match served.search.search(&params.q, wanted) {
+            Ok(hits) => hits.into_iter().map(|hit| hit.key).collect(),
+            Err(e) => {
+                error!("Search for {:?} failed: {:?}", params.q, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    } else if params.q.trim().is_empty() {
+        let query = MapQuery {
+            limit: Some(wanted),
+            ..Default::default()
+        };
+
+        query
+            .select(&served.maps)
+            .into_iter()
+            .map(|(key, _)| key.clone())
+            .collect()
+    } else {
+        // every match, newest first
+        let mut hits = match served
+            .search
+            .search(&params.q, served.maps.map_metadata.len())
+        {
+            Ok(hits) => hits,
+            Err(e) => {
+                error!("Search for {:?} failed: {:?}", params.q, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+        hits.sort_by_key(|hit| {
+            std::cmp::Reverse(
+                served
+                    .maps
+                    .map_metadata
+                    .get(&hit.key)
+                    .map(|map| map.uploaded),
+            )
+        });
+        hits.truncate(wanted);
+        hits.into_iter().map(|hit| hit.key).collect()
+    };
+
+    let docs = keys
+        .iter()
+        .skip(page * PAGE_SIZE)
+        .filter_map(|key| {
+            let (key, map) = served.maps.map_metadata.get_key_value(key)?;
+            Some(api_map(key, map))
+        })
+        .collect();
+
+    Json(SearchResponse { docs }).into_response()
+}
+
+/// `/maps/id/{id}`, `/maps/hash/{hash}` and `/search/text/{page}`, shaped like BeatSaver's.
+pub(super) fn routes() -> Router<Arc<Served>> {
+    Router::new()
+        .route("/maps/id/{id}", get(map_by_id))
+        .route("/maps/hash/{hashes}", get(maps_by_hash))
+        .route("/search/text", get(search_text))
+        .route("/search/text/{page}", get(search_text))
+}
@@ -0,0 +1,324 @@
+// a GraphQL schema over the cache, for dashboards that only want a few fields of a few maps
+
+use std::sync::Arc;
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, InputObject, Object, Schema};
+
+use crate::{
+    cacher::{expr::parse_filter, query::MapQuery},
+    mapdata::{Difficulty, MapMetadata, RankedState, RankedValue, Votes},
+    serve::{MAX_LIMIT, Served},
+};
+
+pub(super) type CacheSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub(super) fn schema(served: Arc<Served>) -> CacheSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(served)
+        .finish()
+}
+
+struct GqlVotes<'a>(&'a Votes);
+
+#[Object(name = "Votes")]
+impl<'a> GqlVotes<'a> {
+    async fn up(&self) -> u32 {
+        self.0.up
+    }
+
+    async fn down(&self) -> u32 {
+        self.0.down
+    }
+}
+
+struct GqlRanked<'a>(&'a RankedValue);
+
+/// Where a difficulty stands on one leaderboard.
+#[Object(name = "RankedStatus")]
+impl<'a> GqlRanked<'a> {
+    async fn ranked(&self) -> bool {
+        self.0.is_ranked
+    }
+
+    /// Only ranked difficulties have stars.
+    async fn stars(&self) -> Option<f32> {
+        self.0.is_ranked.then_some(self.0.stars)
+    }
+
+    /// UNRANKED, NOMINATED, QUALIFIED or RANKED.
+    async fn state(&self) -> Option<&str> {
+        let state = RankedState::try_from(self.0.state?).ok()?;
+        Some(state.as_str_name())
+    }
+
+    async fn leaderboard_id(&self) -> Option<&str> {
+        self.0.leaderboard_id.as_deref()
+    }
+}
+
+struct GqlDifficulty<'a>(&'a Difficulty);
+
+#[Object(name = "Difficulty")]
+impl<'a> GqlDifficulty<'a> {
+    async fn characteristic(&self) -> &str {
+        &self.0.characteristic_name
+    }
+
+    async fn difficulty(&self) -> &str {
+        &self.0.difficulty_name
+    }
+
+    async fn label(&self) -> Option<&str> {
+        self.0.label.as_deref()
+    }
+
+    async fn njs(&self) -> f32 {
+        self.0.njs
+    }
+
+    async fn notes(&self) -> u32 {
+        self.0.notes
+    }
+
+    async fn nps(&self) -> Option<f32> {
+        self.0.nps
+    }
+
+    async fn seconds(&self) -> Option<f32> {
+        self.0.seconds
+    }
+
+    /// The mod bitfield, as the cache stores it.
+    async fn mods(&self) -> u32 {
+        self.0.mods
+    }
+
+    async fn score_saber(&self) -> GqlRanked<'_> {
+        GqlRanked(&self.0.ranked.score_saber)
+    }
+
+    async fn beat_leader(&self) -> GqlRanked<'_> {
+        GqlRanked(&self.0.ranked.beat_leader)
+    }
+}
+
+struct GqlMap {
+    key: String,
+    map: MapMetadata,
+}
+
+impl GqlMap {
+    fn new(key: &str, map: &MapMetadata) -> Self {
+        GqlMap {
+            key: key.to_string(),
+            map: map.clone(),
+        }
+    }
+}
+
+#[Object(name = "Map")]
+impl GqlMap {
+    async fn key(&self) -> &str {
+        &self.key
+    }
+
+    async fn hash(&self) -> &str {
+        &self.map.hash
+    }
+
+    async fn song_name(&self) -> Option<&str> {
+        self.map.song_name.as_deref()
+    }
+
+    async fn song_sub_name(&self) -> Option<&str> {
+        self.map.song_sub_name.as_deref()
+    }
+
+    async fn song_author_name(&self) -> Option<&str> {
+        self.map.song_author_name.as_deref()
+    }
+
+    async fn level_author_name(&self) -> Option<&str> {
+        self.map.level_author_name.as_deref()
+    }
+
+    async fn uploader_name(&self) -> Option<&str> {
+        self.map.uploader_name.as_deref()
+    }
+
+    async fn curator_name(&self) -> Option<&str> {
+        self.map.curator_name.as_deref()
+    }
+
+    /// In seconds.
+    async fn duration(&self) -> u32 {
+        self.map.duration
+    }
+
+    async fn bpm(&self) -> Option<f32> {
+        self.map.bpm
+    }
+
+    /// Unix seconds.
+    async fn uploaded(&self) -> i64 {
+        self.map.uploaded
+    }
+
+    async fn last_updated(&self) -> i64 {
+        self.map.last_updated
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.map.tags
+    }
+
+    async fn votes(&self) -> GqlVotes<'_> {
+        GqlVotes(&self.map.votes)
+    }
+
+    async fn score(&self) -> Option<f32> {
+        self.map.score
+    }
+
+    async fn plays(&self) -> Option<u32> {
+        self.map.plays
+    }
+
+    async fn downloads(&self) -> Option<u32> {
+        self.map.downloads
+    }
+
+    /// Ranked on ScoreSaber or BeatLeader.
+    async fn ranked(&self) -> bool {
+        self.map
+            .difficulties
+            .iter()
+            .any(|diff| diff.ranked.score_saber.is_ranked || diff.ranked.beat_leader.is_ranked)
+    }
+
+    async fn difficulties(
+        &self,
+        characteristic: Option<String>,
+        ranked: Option<bool>,
+    ) -> Vec<GqlDifficulty<'_>> {
+        self.map
+            .difficulties
+            .iter()
+            .filter(|diff| {
+                characteristic
+                    .as_ref()
+                    .is_none_or(|name| diff.characteristic_name.eq_ignore_ascii_case(name))
+            })
+            .filter(|diff| {
+                ranked.is_none_or(|ranked| {
+                    ranked
+                        == (diff.ranked.score_saber.is_ranked || diff.ranked.beat_leader.is_ranked)
+                })
+            })
+            .map(GqlDifficulty)
+            .collect()
+    }
+}
+
+/// One page of maps, newest first.
+struct MapPage {
+    total: usize,
+    maps: Vec<GqlMap>,
+}
+
+#[Object]
+impl MapPage {
+    /// How many maps matched, over every page.
+    async fn total(&self) -> u32 {
+        self.total as u32
+    }
+
+    async fn maps(&self) -> &[GqlMap] {
+        &self.maps
+    }
+}
+
+/// What `maps` picks by. Every field that's set has to match.
+#[derive(InputObject, Default)]
+#[graphql(name = "MapFilter")]
+struct GqlMapFilter {
+    /// The same expressions as `query --filter`.
+    expr: Option<String>,
+    #[graphql(default)]
+    ranked: bool,
+    min_stars: Option<f32>,
+    max_stars: Option<f32>,
+    #[graphql(default)]
+    tags: Vec<String>,
+    mapper: Option<String>,
+}
+
+pub(super) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A map by key or hash, older versions' hashes too.
+    async fn map(&self, ctx: &Context<'_>, id: String) -> Option<GqlMap> {
+        let served = ctx.data_unchecked::<Arc<Served>>();
+        let id = id.to_lowercase();
+        let key = served.index.key(&id).unwrap_or(&id);
+
+        served
+            .maps
+            .map_metadata
+            .get_key_value(key)
+            .map(|(key, map)| GqlMap::new(key, map))
+    }
+
+    /// Maps matching `filter`, newest first.
+    async fn maps(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: GqlMapFilter,
+        #[graphql(default)] offset: u32,
+        #[graphql(default = 20)] limit: u32,
+    ) -> async_graphql::Result<MapPage> {
+        let served = ctx.data_unchecked::<Arc<Served>>();
+        let query = MapQuery {
+            filter: filter.expr.as_deref().map(parse_filter).transpose()?,
+            ranked: filter.ranked,
+            min_stars: filter.min_stars,
+            max_stars: filter.max_stars,
+            tags: filter.tags,
+            mapper: filter.mapper,
+            ..Default::default()
+        };
+
+        let selected = query.select(&served.maps);
+        let maps = selected
+            .iter()
+            .skip(offset as usize)
+            .take((limit as usize).min(MAX_LIMIT))
+            .map(|(key, map)| GqlMap::new(key, map))
+            .collect();
+
+        Ok(MapPage {
+            total: selected.len(),
+            maps,
+        })
+    }
+
+    /// Maps by song name, song author or mapper, best match first.
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        q: String,
+        #[graphql(default = 20)] limit: u32,
+    ) -> async_graphql::Result<Vec<GqlMap>> {
+        let served = ctx.data_unchecked::<Arc<Served>>();
+        let hits = served.search.search(&q, (limit as usize).min(MAX_LIMIT))?;
+
+        Ok(hits
+            .iter()
+            .filter_map(|hit| {
+                let (key, map) = served.maps.map_metadata.get_key_value(&hit.key)?;
+                Some(GqlMap::new(key, map))
+            })
+            .collect())
+    }
+}